@@ -0,0 +1,223 @@
+//! Keyframed animation, and rendering of numbered image sequences.
+//!
+//! Time is measured in seconds. Frame ``n`` of a sequence at ``fps`` frames per second
+//! starts at ``n / fps``, and the shutter of the camera is open for a fraction of the frame
+//! (Refer to [Camera::with_shutter()]). A single image is the same as frame 0 at 1 fps.
+
+use crate::camera::Camera;
+use crate::hittable::{HitRecord, Hittable};
+use crate::ray::Ray;
+use crate::scene::Scene;
+use crate::vec3::Vec3;
+use std::fs::File;
+use std::io::{BufWriter, Error, ErrorKind, Result};
+use std::ops::{Add, Mul, Range, Sub};
+
+/// Values that can be interpolated by a [Curve].
+pub trait Animatable: Copy + Add<Output = Self> + Sub<Output = Self> + Mul<f32, Output = Self> {}
+
+impl<T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f32, Output = T>> Animatable for T {}
+
+/// How a curve moves from one keyframe to the next.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+pub enum Interpolation {
+    /// A straight line
+    Linear,
+    /// A cubic Bézier curve, using the handles of the keyframes as control points.
+    /// Without handles, this eases in and out of every keyframe.
+    Bezier,
+    /// A Catmull-Rom spline, which passes smoothly through every keyframe.
+    CatmullRom,
+}
+
+/// A value of a curve at a given time.
+#[derive(Debug, Clone, Copy)]
+pub struct Keyframe<T: Animatable> {
+    pub time: f32,
+    pub value: T,
+    /// Interpolation used from this keyframe to the next
+    pub interpolation: Interpolation,
+    /// Control points before and after the keyframe, used by [Interpolation::Bezier]
+    pub handles: Option<(T, T)>,
+}
+
+/// A value that changes over time, defined by keyframes.
+/// Before the first and after the last keyframe, the value holds still.
+#[derive(Debug, Clone)]
+pub struct Curve<T: Animatable> {
+    keys: Vec<Keyframe<T>>,
+}
+
+#[allow(dead_code)]
+impl<T: Animatable> Curve<T> {
+    /// Creates a curve from keyframes, in any order. There must be at least one keyframe.
+    pub fn new(mut keys: Vec<Keyframe<T>>) -> Self {
+        assert!(!keys.is_empty(), "A curve needs at least one keyframe");
+        keys.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Self { keys }
+    }
+
+    /// Creates a curve that never changes
+    pub fn constant(value: T) -> Self {
+        Self::with_interpolation(&[(0.0, value)], Interpolation::Linear)
+    }
+
+    /// Creates a curve through pairs of time and value, all using the same interpolation.
+    pub fn with_interpolation(points: &[(f32, T)], interpolation: Interpolation) -> Self {
+        Self::new(
+            points
+                .iter()
+                .map(|&(time, value)| Keyframe { time, value, interpolation, handles: None })
+                .collect(),
+        )
+    }
+
+    /// Returns the value of the curve at the given time.
+    pub fn at(&self, time: f32) -> T {
+        let next = self.keys.partition_point(|x| x.time <= time);
+        if next == 0 {
+            return self.keys[0].value;
+        }
+        if next == self.keys.len() {
+            return self.keys[next - 1].value;
+        }
+
+        let (k0, k1) = (&self.keys[next - 1], &self.keys[next]);
+        let s = (time - k0.time) / (k1.time - k0.time);
+
+        match k0.interpolation {
+            Interpolation::Linear => k0.value + (k1.value - k0.value) * s,
+            Interpolation::Bezier => {
+                let p1 = k0.handles.map_or(k0.value, |x| x.1);
+                let p2 = k1.handles.map_or(k1.value, |x| x.0);
+                let lerp = |a: T, b: T| a + (b - a) * s;
+
+                let (a, b, c) = (lerp(k0.value, p1), lerp(p1, p2), lerp(p2, k1.value));
+                let (d, e) = (lerp(a, b), lerp(b, c));
+                lerp(d, e)
+            }
+            Interpolation::CatmullRom => {
+                // Neighbours past the ends are mirrored, so the ends are not pulled anywhere
+                let (t0, t1) = (k0.time, k1.time);
+                let (tp, vp) = match next.checked_sub(2).map(|i| &self.keys[i]) {
+                    Some(x) => (x.time, x.value),
+                    None => (2.0 * t0 - t1, k0.value + (k0.value - k1.value)),
+                };
+                let (tn, vn) = match self.keys.get(next + 1) {
+                    Some(x) => (x.time, x.value),
+                    None => (2.0 * t1 - t0, k1.value + (k1.value - k0.value)),
+                };
+
+                // Tangents scaled to the length of this segment
+                let m0 = (k1.value - vp) * ((t1 - t0) / (t1 - tp));
+                let m1 = (vn - k0.value) * ((t1 - t0) / (tn - t0));
+
+                let (s2, s3) = (s * s, s * s * s);
+                k0.value * (2.0 * s3 - 3.0 * s2 + 1.0)
+                    + m0 * (s3 - 2.0 * s2 + s)
+                    + k1.value * (-2.0 * s3 + 3.0 * s2)
+                    + m1 * (s3 - s2)
+            }
+        }
+    }
+}
+
+/// Curves for the parameters of a [Camera]. Parameters without a curve keep the
+/// value they have on the camera.
+#[derive(Debug, Clone, Default)]
+pub struct CameraAnimation {
+    pub lookfrom: Option<Curve<Vec3>>,
+    pub lookat: Option<Curve<Vec3>>,
+    pub vfov: Option<Curve<f32>>,
+    pub defocus_angle: Option<Curve<f32>>,
+    pub focus_distance: Option<Curve<f32>>,
+}
+
+/// Renders frames ``frames`` of an animation into numbered PPM files, named ``prefix``
+/// followed by the frame number (like ``frame_0001.ppm``).
+///
+/// The scene is built once and shared by every frame: anything that moves should be
+/// animated using the time of the rays (Refer to [crate::transform::Transformed]).
+pub fn render_sequence(
+    camera: &Camera,
+    animation: &CameraAnimation,
+    scene: &Scene,
+    frames: Range<u32>,
+    fps: f32,
+    prefix: &str,
+) -> Result<()> {
+    for frame in frames {
+        let path = format!("{}{:04}.ppm", prefix, frame);
+        eprintln!("Rendering frame {} to {}", frame, path);
+
+        let camera = camera.frame(animation, frame, fps).map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+        let mut file = BufWriter::new(File::create(&path)?);
+        camera.render_to(scene, &mut file)?;
+    }
+
+    Ok(())
+}
+
+/// Repeats the motion of an object in every frame of an animation. The object sees the time
+/// of the rays as a fraction of the frame they fall in, from 0 when the frame starts to 1 when
+/// the next one does, so an object that moves over the time 0 to 1 (like a single image with
+/// motion blur) moves the same way in every frame, instead of drifting further with each one.
+pub struct PerFrame {
+    object: Box<dyn Hittable>,
+    fps: f32,
+}
+
+#[allow(dead_code)]
+impl PerFrame {
+    /// Wraps the object for an animation at ``fps`` frames per second.
+    pub fn new(object: Box<dyn Hittable>, fps: f32) -> Self {
+        Self { object, fps }
+    }
+}
+
+impl Hittable for PerFrame {
+    fn hit(&self, r: &Ray, t_range: Range<f32>) -> Option<HitRecord> {
+        let mut local = *r;
+        local.time = (r.time * self.fps).fract();
+
+        self.object.hit(&local, t_range)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn catmull_rom_passes_through_the_keyframes() {
+        let points = [(0.0, 0.0), (1.0, 2.0), (3.0, -1.0), (4.0, 5.0)];
+        let curve = Curve::with_interpolation(&points, Interpolation::CatmullRom);
+
+        for (time, value) in points {
+            assert!((curve.at(time) - value).abs() < 1e-5, "at {}: {} instead of {}", time, curve.at(time), value);
+        }
+    }
+
+    #[test]
+    fn catmull_rom_is_smooth_at_the_keyframes() {
+        let points = [(0.0, 0.0), (1.0, 2.0), (3.0, -1.0), (4.0, 5.0)];
+        let curve = Curve::with_interpolation(&points, Interpolation::CatmullRom);
+
+        // The slope on either side of an inner keyframe is the same
+        let h = 1e-3;
+        for time in [1.0, 3.0] {
+            let before = (curve.at(time) - curve.at(time - h)) / h;
+            let after = (curve.at(time + h) - curve.at(time)) / h;
+            assert!((before - after).abs() < 0.05, "at {}: {} before, {} after", time, before, after);
+        }
+    }
+
+    #[test]
+    fn curves_hold_still_past_the_ends() {
+        let curve = Curve::with_interpolation(&[(1.0, 2.0), (2.0, 4.0)], Interpolation::CatmullRom);
+
+        assert_eq!(curve.at(0.0), 2.0);
+        assert_eq!(curve.at(5.0), 4.0);
+    }
+}
@@ -0,0 +1,518 @@
+//! Bidirectional path tracing (Veach, "Robust Monte Carlo Methods for Light Transport
+//! Simulation", chapter 10), following the structure of PBRT.
+//!
+//! For every sample, a path is traced from the camera and another from a light. Every vertex
+//! of one path is then joined to every vertex of the other, which gives a family of ways
+//! (strategies) to build a path between the light and the camera. Each strategy is good at
+//! something different: paths from the camera find lights that are seen directly, joining a
+//! camera vertex to a light is next event estimation, and paths from the light find caustics
+//! and lights hidden in fixtures or behind glass. The strategies are combined with multiple
+//! importance sampling, weighing each by how likely the others were to build the same path.
+//!
+//! Paths from the light that are joined directly to the camera can land on any pixel, so their
+//! light is splatted onto the film rather than added to the pixel being sampled.
+//!
+//! Lights infinitely far away cannot start paths (Refer to [crate::light::Light::emit()]), so they are only
+//! found by paths from the camera, or by joining a camera vertex to them.
+
+use crate::camera::Camera;
+use crate::hittable::{HitRecord, Hittable};
+use crate::light_sampler::{LightSampler, PowerLightSampler};
+use crate::ray::Ray;
+use crate::scene::Scene;
+use crate::utils::random;
+use crate::vec3::{Point3, Vec3};
+
+type Colour = Vec3;
+
+/// What a vertex of a path lies on.
+enum Kind {
+    /// The lens of the camera
+    Camera,
+    /// A point on a light, which is ``delta`` if the light is infinitely small
+    Light { index: usize, delta: bool },
+    /// A direction to the surroundings of the scene, reached by ``ray``. The index is that of
+    /// the environment, or of a light infinitely far away, and ``None`` for the sky gradient.
+    Infinite { index: Option<usize>, ray: Ray },
+    /// A surface in the scene, hit by ``r_in``
+    Surface { rec: HitRecord, r_in: Ray },
+}
+
+/// A vertex of a path from the camera or from a light.
+struct Vertex {
+    kind: Kind,
+    point: Point3,
+    /// Normal of the surface at the vertex, which turns densities per solid angle into
+    /// densities per unit area
+    normal: Option<Vec3>,
+    /// Light (or importance) carried along the path up to the vertex, divided by its density
+    beta: Vec3,
+    /// Density (per unit area) of the vertex being sampled in the direction the path was built
+    pdf_fwd: f32,
+    /// Density (per unit area) of the vertex being sampled in the opposite direction
+    pdf_rev: f32,
+    /// Whether the path scattered off the vertex in a single direction (like a mirror),
+    /// so it cannot be joined to other vertices
+    delta: bool,
+}
+
+impl Vertex {
+    fn new(kind: Kind, point: Point3, normal: Option<Vec3>, beta: Vec3) -> Self {
+        Self {
+            kind,
+            point,
+            normal,
+            beta,
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0,
+            delta: false,
+        }
+    }
+
+    /// Unit vector from this vertex towards ``next``
+    fn direction_to(&self, next: &Vertex) -> Vec3 {
+        match (&self.kind, &next.kind) {
+            (_, Kind::Infinite { ray, .. }) => ray.direction.unit(),
+            (Kind::Infinite { ray, .. }, _) => -ray.direction.unit(),
+            _ => (next.point - self.point).unit(),
+        }
+    }
+
+    /// Whether paths can be joined at this vertex
+    fn connectible(&self) -> bool {
+        match self.kind {
+            Kind::Camera => true,
+            Kind::Light { .. } => true,
+            Kind::Infinite { .. } => false,
+            Kind::Surface { .. } => !self.delta,
+        }
+    }
+
+    /// Index of the light among [Scene::all_lights()], if the vertex lies on one
+    fn light_index(&self) -> Option<usize> {
+        match &self.kind {
+            Kind::Light { index, .. } => Some(*index),
+            Kind::Infinite { index, .. } => *index,
+            Kind::Surface { rec, .. } => rec.material.light(),
+            Kind::Camera => None,
+        }
+    }
+
+    /// Whether the vertex lies on a light that rays can never hit
+    fn is_delta_light(&self, scene: &Scene) -> bool {
+        match &self.kind {
+            Kind::Light { delta, .. } => *delta,
+            Kind::Infinite { index: Some(index), .. } => scene.light(*index).is_some_and(|x| x.is_delta()),
+            _ => false,
+        }
+    }
+
+    /// Light scattered by the surface at the vertex towards ``direction``, arriving along
+    /// the path, times the cosine to the direction
+    fn eval(&self, direction: Vec3) -> Colour {
+        match &self.kind {
+            Kind::Surface { rec, r_in } => rec.material.eval(r_in, rec, direction),
+            _ => Vec3::new(),
+        }
+    }
+
+    /// Light given off by the vertex back along the path that found it
+    fn emitted(&self, scene: &Scene) -> Colour {
+        match &self.kind {
+            Kind::Infinite { ray, .. } => crate::camera::background(ray, scene),
+            Kind::Surface { rec, r_in } => rec.material.emitted(r_in, rec),
+            _ => Vec3::new(),
+        }
+    }
+
+    /// Density of sampling ``next`` from this vertex, having arrived from ``prev``.
+    fn pdf(&self, scene: &Scene, camera: &Camera, prev: Option<&Vertex>, next: &Vertex) -> f32 {
+        let direction = self.direction_to(next);
+        let pdf = match &self.kind {
+            Kind::Light { .. } | Kind::Infinite { .. } => return self.pdf_light(scene, next),
+            Kind::Camera => camera.importance_pdf(direction).1,
+            Kind::Surface { rec, r_in } => {
+                let r_in = match prev {
+                    Some(prev) => {
+                        let incoming = prev.direction_to(self);
+                        r_in.spawn(self.point - incoming, incoming)
+                    }
+                    None => *r_in,
+                };
+                rec.material.pdf(&r_in, rec, direction)
+            }
+        };
+
+        convert_density(pdf, self, next)
+    }
+
+    /// Density of the light at this vertex emitting light towards ``next``
+    fn pdf_light(&self, scene: &Scene, next: &Vertex) -> f32 {
+        let Some(light) = self.light_index().and_then(|x| scene.light(x)) else {
+            return 0.0;
+        };
+        if let Kind::Infinite { .. } = self.kind {
+            return 0.0;
+        }
+
+        let (_, pdf) = light.emit_pdf(self.point, self.direction_to(next));
+        convert_density(pdf, self, next)
+    }
+
+    /// Density of choosing the light at this vertex, and of the vertex on it,
+    /// when joining ``next`` to a light.
+    fn pdf_light_origin(&self, scene: &Scene, lights: &PowerLightSampler, next: &Vertex) -> f32 {
+        let Some(index) = self.light_index() else {
+            return 0.0;
+        };
+        let Some(light) = scene.light(index) else {
+            return 0.0;
+        };
+        let pmf = lights.pmf(self.point, index);
+
+        match &self.kind {
+            Kind::Infinite { ray, .. } => pmf * light.pdf(next.point, ray.direction),
+            _ => pmf * light.emit_pdf(self.point, self.direction_to(next)).0,
+        }
+    }
+}
+
+/// Turns a density per solid angle, seen from ``from``, into a density per unit area at ``to``.
+/// Vertices infinitely far away keep densities per solid angle.
+fn convert_density(pdf: f32, from: &Vertex, to: &Vertex) -> f32 {
+    if let Kind::Infinite { .. } = to.kind {
+        return pdf;
+    }
+
+    let offset = to.point - from.point;
+    let distance_sq = offset.length_sq();
+    if distance_sq == 0.0 {
+        return 0.0;
+    }
+
+    match to.normal {
+        Some(normal) => pdf * normal.dot(offset.unit()).abs() / distance_sq,
+        None => pdf / distance_sq,
+    }
+}
+
+/// Whether nothing blocks the way from ``origin`` along a unit ``direction`` up to ``distance``
+fn visible(scene: &Scene, r: &Ray, origin: Point3, direction: Vec3, distance: f32) -> bool {
+    let shadow = r.spawn(origin, direction);
+    scene.world.hit(&shadow, 0.001..distance * (1.0 - 1e-4)).is_none()
+}
+
+/// Extends ``path`` by following ``ray`` through the scene for at most ``max_bounces`` hits.
+/// ``pdf`` is the density (per solid angle) of the ray's direction. Paths from the camera that
+/// escape the scene end on an [Kind::Infinite] vertex.
+fn random_walk(scene: &Scene, mut ray: Ray, mut beta: Vec3, mut pdf: f32, max_bounces: u32, path: &mut Vec<Vertex>, from_camera: bool) {
+    let mut bounces = 0;
+
+    while bounces < max_bounces && !beta.near_zero() {
+        let Some(rec) = scene.world.hit(&ray, 0.001..f32::INFINITY) else {
+            if from_camera {
+                let index = scene.environment.as_ref().map(|_| scene.environment_index());
+                let mut vertex = Vertex::new(Kind::Infinite { index, ray }, ray.origin, None, beta);
+                vertex.pdf_fwd = pdf;
+                path.push(vertex);
+            }
+            break;
+        };
+
+        let (point, normal) = (rec.point, rec.normal);
+        let mut vertex = Vertex::new(Kind::Surface { rec, r_in: ray }, point, Some(normal), beta);
+        vertex.pdf_fwd = convert_density(pdf, path.last().unwrap(), &vertex);
+        path.push(vertex);
+
+        bounces += 1;
+        if bounces >= max_bounces {
+            break;
+        }
+
+        let n = path.len();
+        let Kind::Surface { rec, .. } = &path[n - 1].kind else {
+            unreachable!()
+        };
+        let Some(x) = rec.material.scatter(&ray, rec) else {
+            break;
+        };
+
+        // Density of scattering back the way the path came
+        let pdf_rev = if x.pdf > 0.0 {
+            let reversed = ray.spawn(x.scattered.at(1.0), -x.scattered.direction);
+            rec.material.pdf(&reversed, rec, -ray.direction)
+        } else {
+            path[n - 1].delta = true;
+            0.0
+        };
+        path[n - 2].pdf_rev = convert_density(pdf_rev, &path[n - 1], &path[n - 2]);
+
+        beta = beta * x.attenuation;
+        pdf = x.pdf;
+        ray = x.scattered;
+    }
+}
+
+/// Builds a path from the camera, starting with the ray ``r``
+fn camera_path(scene: &Scene, camera: &Camera, r: &Ray) -> Vec<Vertex> {
+    let mut path = vec![Vertex::new(Kind::Camera, r.origin, None, Vec3::from_point(1.0, 1.0, 1.0))];
+    let (_, pdf) = camera.importance_pdf(r.direction);
+    random_walk(scene, *r, path[0].beta, pdf, camera.max_depth(), &mut path, true);
+
+    path
+}
+
+/// Builds a path from a light chosen by ``lights``, carrying the wavelengths of ``r``.
+/// The path is empty if the light cannot start paths.
+fn light_path(scene: &Scene, lights: &PowerLightSampler, r: &Ray, max_depth: u32) -> Vec<Vertex> {
+    let mut path = Vec::new();
+    let Some((index, pmf)) = lights.sample(r.origin, random()) else {
+        return path;
+    };
+    let Some(light) = scene.light(index) else {
+        return path;
+    };
+    let Some(emission) = light.emit(r) else {
+        return path;
+    };
+    if emission.pdf_position <= 0.0 || emission.pdf_direction <= 0.0 || emission.radiance.near_zero() {
+        return path;
+    }
+
+    let kind = Kind::Light { index, delta: light.is_delta() };
+    let mut vertex = Vertex::new(kind, emission.origin, emission.normal, emission.radiance);
+    vertex.pdf_fwd = pmf * emission.pdf_position;
+    path.push(vertex);
+
+    let cos_theta = emission.normal.map_or(1.0, |x| x.dot(emission.direction).abs());
+    let beta = emission.radiance * cos_theta / (pmf * emission.pdf_position * emission.pdf_direction);
+    let ray = r.spawn(emission.origin, emission.direction);
+    random_walk(scene, ray, beta, emission.pdf_direction, max_depth.saturating_sub(1), &mut path, false);
+
+    path
+}
+
+/// Traces the camera ray ``r`` bidirectionally, and returns the light it carries, in its units.
+/// Light reaching other pixels is passed to ``splat`` with the pixel it lands on.
+pub fn trace(camera: &Camera, r: &Ray, scene: &Scene, lights: &PowerLightSampler, splat: &mut dyn FnMut((u32, u32), Colour)) -> Colour {
+    let camera_path = camera_path(scene, camera, r);
+    let light_path = light_path(scene, lights, r, camera.max_depth());
+
+    let mut colour = Vec3::new();
+    for t in 1..=camera_path.len() {
+        // A light is sampled afresh when joining a single light vertex, even without a light path
+        for s in 0..=light_path.len().max(1) {
+            let depth = (s + t) as i64 - 2;
+            if (s == 1 && t == 1) || depth < 0 || depth > camera.max_depth() as i64 {
+                continue;
+            }
+
+            let (light, pixel) = connect(scene, camera, lights, &light_path, &camera_path, s, t);
+            match pixel {
+                Some(pixel) if !light.near_zero() => splat(pixel, light),
+                Some(_) => {}
+                None => colour += light,
+            }
+        }
+    }
+
+    colour
+}
+
+/// Joins the first ``s`` vertices of the light path to the first ``t`` vertices of the camera
+/// path, and returns the light carried by the joined path with its weight. Paths joined directly
+/// to the camera (``t == 1``) also return the pixel they land on.
+fn connect(
+    scene: &Scene,
+    camera: &Camera,
+    lights: &PowerLightSampler,
+    light_path: &[Vertex],
+    camera_path: &[Vertex],
+    s: usize,
+    t: usize,
+) -> (Colour, Option<(u32, u32)>) {
+    let none = (Vec3::new(), None);
+    let mut sampled = None;
+    let mut pixel = None;
+
+    let light = if s == 0 {
+        // The camera path found a light by itself
+        let pt = &camera_path[t - 1];
+        pt.beta * pt.emitted(scene)
+    } else if t == 1 {
+        // Join a vertex of the light path to a point on the lens
+        let qs = &light_path[s - 1];
+        if !qs.connectible() {
+            return none;
+        }
+        let Kind::Surface { r_in, .. } = &qs.kind else {
+            return none;
+        };
+        let Some(sample) = camera.sample_importance(qs.point) else {
+            return none;
+        };
+
+        let offset = sample.lens - qs.point;
+        let (distance, direction) = (offset.length(), offset.unit());
+        let weight = sample.importance / sample.pdf;
+        let light = qs.beta * qs.eval(direction) * weight;
+        if light.near_zero() || !visible(scene, r_in, qs.point, direction, distance) {
+            return none;
+        }
+
+        pixel = Some(sample.pixel);
+        sampled = Some(Vertex::new(Kind::Camera, sample.lens, None, Vec3::from_point(weight, weight, weight)));
+        light
+    } else if s == 1 {
+        // Join a vertex of the camera path to a point on a light (next event estimation)
+        let pt = &camera_path[t - 1];
+        if !pt.connectible() {
+            return none;
+        }
+        let Kind::Surface { r_in, .. } = &pt.kind else {
+            return none;
+        };
+        let Some((index, pmf)) = lights.sample(pt.point, random()) else {
+            return none;
+        };
+        let Some(sample) = scene.light(index).and_then(|x| x.sample(pt.point, r_in)) else {
+            return none;
+        };
+        let light = scene.light(index).unwrap();
+
+        let beta = sample.radiance / pmf;
+        let mut vertex = if sample.distance.is_infinite() {
+            let ray = r_in.spawn(pt.point, sample.direction);
+            Vertex::new(Kind::Infinite { index: Some(index), ray }, pt.point, None, beta)
+        } else {
+            let point = pt.point + sample.distance * sample.direction;
+            Vertex::new(Kind::Light { index, delta: light.is_delta() }, point, light.normal(point), beta)
+        };
+        vertex.pdf_fwd = vertex.pdf_light_origin(scene, lights, pt);
+
+        let light = pt.beta * pt.eval(sample.direction) * vertex.beta;
+        if light.near_zero() || !visible(scene, r_in, pt.point, sample.direction, sample.distance) {
+            return none;
+        }
+
+        sampled = Some(vertex);
+        light
+    } else {
+        // Join a vertex of each path
+        let (qs, pt) = (&light_path[s - 1], &camera_path[t - 1]);
+        if !qs.connectible() || !pt.connectible() {
+            return none;
+        }
+        let Kind::Surface { r_in, .. } = &pt.kind else {
+            return none;
+        };
+
+        let offset = qs.point - pt.point;
+        let (distance, direction) = (offset.length(), offset.unit());
+        let light = qs.beta * qs.eval(-direction) * pt.eval(direction) * pt.beta / (distance * distance);
+        if light.near_zero() || !visible(scene, r_in, pt.point, direction, distance) {
+            return none;
+        }
+
+        light
+    };
+
+    if light.near_zero() {
+        return none;
+    }
+
+    let weight = mis_weight(scene, camera, lights, light_path, camera_path, sampled.as_ref(), s, t);
+    (weight * light, pixel)
+}
+
+/// Weight of the path joined from ``s`` light vertices and ``t`` camera vertices, against every
+/// other way of building the same path (the balance heuristic). ``sampled`` replaces the end
+/// vertex of a path of length 1, which is sampled when joining.
+#[allow(clippy::too_many_arguments)]
+fn mis_weight(
+    scene: &Scene,
+    camera: &Camera,
+    lights: &PowerLightSampler,
+    light_path: &[Vertex],
+    camera_path: &[Vertex],
+    sampled: Option<&Vertex>,
+    s: usize,
+    t: usize,
+) -> f32 {
+    if s + t == 2 {
+        return 1.0;
+    }
+
+    let mut light_vertices: Vec<&Vertex> = light_path[..s.min(light_path.len())].iter().collect();
+    let mut camera_vertices: Vec<&Vertex> = camera_path[..t].iter().collect();
+    match (s, t, sampled) {
+        (1, _, Some(vertex)) => light_vertices = vec![vertex],
+        (_, 1, Some(vertex)) => camera_vertices[0] = vertex,
+        _ => {}
+    }
+
+    let pt = camera_vertices[t - 1];
+    let pt_minus = (t > 1).then(|| camera_vertices[t - 2]);
+    let qs = (s > 0).then(|| light_vertices[s - 1]);
+    let qs_minus = (s > 1).then(|| light_vertices[s - 2]);
+
+    // Emitters that are not lights (and the sky gradient) can only be found by chance
+    if s == 0 && pt.light_index().is_none() {
+        return 1.0;
+    }
+
+    // Densities (forward, reverse) and delta flags, with those around the join
+    // replaced by the densities of building the joined path the other way
+    let mut camera_pdfs: Vec<(f32, f32, bool)> = camera_vertices.iter().map(|x| (x.pdf_fwd, x.pdf_rev, x.delta)).collect();
+    let mut light_pdfs: Vec<(f32, f32, bool)> = light_vertices.iter().map(|x| (x.pdf_fwd, x.pdf_rev, x.delta)).collect();
+
+    camera_pdfs[t - 1].1 = match qs {
+        Some(qs) => qs.pdf(scene, camera, qs_minus, pt),
+        None => pt.pdf_light_origin(scene, lights, pt_minus.unwrap()),
+    };
+    camera_pdfs[t - 1].2 = false;
+    if let Some(pt_minus) = pt_minus {
+        camera_pdfs[t - 2].1 = match qs {
+            Some(qs) => pt.pdf(scene, camera, Some(qs), pt_minus),
+            None => pt.pdf_light(scene, pt_minus),
+        };
+    }
+    if let Some(qs) = qs {
+        light_pdfs[s - 1].1 = pt.pdf(scene, camera, pt_minus, qs);
+        light_pdfs[s - 1].2 = false;
+    }
+    if let Some(qs_minus) = qs_minus {
+        light_pdfs[s - 2].1 = qs.unwrap().pdf(scene, camera, Some(pt), qs_minus);
+    }
+
+    let remap = |x: f32| if x != 0.0 { x } else { 1.0 };
+    // Lights infinitely far away cannot start paths, so only the strategies with at most
+    // one light vertex are possible
+    let infinite = matches!(pt.kind, Kind::Infinite { .. }) || qs.is_some_and(|x| matches!(x.kind, Kind::Infinite { .. }));
+
+    let mut sum = 0.0;
+    let mut ratio = 1.0;
+    for i in (1..t).rev() {
+        if infinite && s + t - i >= 2 {
+            break;
+        }
+        ratio *= remap(camera_pdfs[i].1) / remap(camera_pdfs[i].0);
+        if !camera_pdfs[i].2 && !camera_pdfs[i - 1].2 {
+            sum += ratio;
+        }
+    }
+
+    ratio = 1.0;
+    for i in (0..s).rev() {
+        ratio *= remap(light_pdfs[i].1) / remap(light_pdfs[i].0);
+        let delta_light = match i {
+            0 => light_vertices[0].is_delta_light(scene),
+            _ => light_pdfs[i - 1].2,
+        };
+        if !light_pdfs[i].2 && !delta_light {
+            sum += ratio;
+        }
+    }
+
+    1.0 / (1.0 + sum)
+}
@@ -1,24 +1,70 @@
 use rayon::prelude::*;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use std::sync::{Arc, Mutex};
 
-use crate::hittable::{Hittable, World};
+use crate::animation::{CameraAnimation, Curve};
+use crate::bdpt;
+use crate::checkpoint::{Checkpoint, CheckpointSettings};
+use crate::denoise::{self, DenoiseSettings, Features};
+use crate::distribution::Distribution2D;
+use crate::hittable::{HitRecord, Hittable};
+use crate::image::Image;
+use crate::lens::LensSystem;
+use crate::light::power_heuristic;
+use crate::light_sampler::PowerLightSampler;
+use crate::material::Lobe;
+use crate::mlt::{self, MetropolisSettings};
+use crate::photon::{self, PhotonMaps, PhotonSettings};
 use crate::ray::Ray;
-use crate::utils::{random, random_range};
+use crate::scene::Scene;
+use crate::spectrum;
+use crate::utils::{mix_seed, random, random_range, with_seed};
 use crate::vec3::{Point3, Vec3};
-use core::f32::INFINITY;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Camera {
-    pub aspect_ratio: f32,
-    pub image_width: u32,
-    pub samples_per_pixel: u32,
-    pub max_depth: u32,
-    pub vfov: f32,
-    pub defocus_angle: f32,
-    pub focus_distance: f32,
-    pub lookfrom: Vec3,
-    pub lookat: Vec3,
-    pub vup: Vec3,
+    aspect_ratio: f32,
+    image_width: u32,
+    samples_per_pixel: u32,
+    max_depth: u32,
+    vfov: f32,
+    defocus_angle: f32,
+    focus_distance: f32,
+    lookfrom: Vec3,
+    lookat: Vec3,
+    vup: Vec3,
+    spectral: bool,
+    projection: Projection,
+    stereo: Option<Stereo>,
+    aperture: Aperture,
+    anamorphic: f32,
+    vignetting: f32,
+    shutter_open: f32,
+    shutter_close: f32,
+    shutter_curve: ShutterCurve,
+    camera_motion: Option<(Point3, Point3)>,
+    integrator: Integrator,
+    path_depth: PathDepth,
+    adaptive: Option<AdaptiveSampling>,
+    progressive: Option<Progressive>,
+    denoise: Option<DenoiseSettings>,
+    resolution: f32,
+    region: Option<Region>,
+    seed: Option<u64>,
 
+    /// Distribution of points on the aperture, for [Aperture::Mask]
+    aperture_mask: Option<Arc<Distribution2D>>,
+    /// Checkpoint already loaded to resume from (Refer to [Camera::with_checkpoint()])
+    checkpoint: Option<Arc<Checkpoint>>,
+    /// Height asked for instead of following from ``aspect_ratio`` (Refer to [CameraBuilder::with_image_height()])
+    explicit_height: Option<u32>,
+    /// Width of the rendered image, ``image_width`` scaled by ``resolution``
+    render_width: u32,
     image_height: u32,
     camera_center: Point3,
     init_pixel_loc: Point3,
@@ -26,58 +72,697 @@ pub struct Camera {
     delta_u: Vec3,
     delta_v: Vec3,
     defocus_u: Vec3,
-    defocus_v: Vec3
+    defocus_v: Vec3,
+
+    u: Vec3,
+    v: Vec3,
+    w: Vec3
 }
 
 type Colour = Vec3;
 
+/// How the camera maps directions in the scene onto the image.
 #[allow(dead_code)]
-impl Camera {
-    /// Creates a new instance of Camera and initialises it (Refer to [initialize()])
-    ///
-    /// Options:
-    /// - ``aspect_ratio``: Configures the ratio of the image height to width
-    /// - ``image_width``: Configures how wide the image will be
-    /// - ``samples_per_pixel``: While anti-aliiasing, how many samples should the raytracer take. Set to 1 to turn off anti-aliasing.
-    /// - ``max_depth``: While bouncing off rays (for the lack of a better word), how many reflections should be counted.
-    /// - ``vfov``: How close should the camera be to the object
-    /// - ``defocus_angle``: Indirectly asking: how thick is the lens?
-    /// - ``focus_distance``: How far is the focus of the camera?
-    /// - ``lookfrom``: What is the vector of placement of the camera?
-    /// - ``lookat``: In what direction is the camera looking? One can also calculate this by subtracting the position vectors of the image from the position vector of the camera (``lookfrom``).
-    /// - ``vup``: Where is the sky?
-    pub fn new(
-        aspect_ratio: f32,
-        image_width: u32,
-        samples_per_pixel: u32,
-        max_depth: u32,
-        vfov: f32,
-	defocus_angle: f32,
-	focus_distance: f32,
-        lookfrom: Vec3,
-        lookat: Vec3,
-        vup: Vec3,
-    ) -> Self {
+#[derive(Debug, Clone)]
+pub enum Projection {
+    /// A pinhole (or thin lens, with ``defocus_angle``) camera, framed by ``vfov``.
+    Perspective,
+    /// Parallel rays, with no perspective. ``width`` is the width of the view
+    /// in scene units, and the height follows from the aspect ratio.
+    Orthographic { width: f32 },
+    /// A circular fisheye image, covering ``fov`` degrees across the width of the image.
+    /// Pixels outside the circle are black.
+    Fisheye { fov: f32, mapping: FisheyeMapping },
+    /// A full 360° panorama, with longitude across and latitude down the image.
+    /// The aspect ratio should be 2 for square pixels.
+    Equirectangular,
+    /// Rays are traced through a lens made of several elements (Refer to [LensSystem]).
+    /// The field of view and the depth of field come from the lens itself, so ``vfov``
+    /// and ``defocus_angle`` are ignored, while ``focus_distance`` is measured from the film.
+    Realistic(Arc<LensSystem>),
+}
+
+/// How the distance from the centre of a fisheye image relates to the angle
+/// away from the viewing direction.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+pub enum FisheyeMapping {
+    /// The distance is proportional to the angle.
+    Equidistant,
+    /// Equal areas of the image cover equal solid angles.
+    Equisolid,
+}
+
+/// The shape of the lens aperture, which gives out of focus highlights (bokeh) their shape.
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub enum Aperture {
+    Circular,
+    /// A regular polygon formed by ``blades`` straight aperture blades, turned by ``rotation`` degrees.
+    Polygon { blades: u32, rotation: f32 },
+    /// An arbitrary shape, taken from the brightness of an image. The image covers
+    /// the square around the circular aperture, and points on it are drawn in proportion
+    /// to the brightness of its pixels.
+    Mask(Arc<Image>),
+}
+
+/// How far open the shutter is over the time it is open, which shapes the streaks
+/// left by moving objects. Times are importance sampled according to the curve.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+pub enum ShutterCurve {
+    /// The shutter opens and closes instantly
+    Box,
+    /// The shutter opens fully halfway, giving soft ended streaks
+    Triangle,
+    /// The shutter takes a ``ramp`` fraction of the time (at most 0.5) to open, and again to close
+    Trapezoid { ramp: f32 },
+}
+
+impl ShutterCurve {
+    /// Maps a uniform random number to a fraction of the time the shutter is open,
+    /// distributed according to the curve.
+    fn sample(&self, u: f32) -> f32 {
+        let ramp = match self {
+            ShutterCurve::Box => return u,
+            ShutterCurve::Triangle => 0.5,
+            ShutterCurve::Trapezoid { ramp } => ramp.clamp(0.0, 0.5),
+        };
+        if ramp == 0.0 {
+            return u;
+        }
+
+        // Invert the area under the curve, which is made of a ramp up,
+        // a flat top, and a ramp down
+        let area = u * (1.0 - ramp);
+        if area < ramp / 2.0 {
+            (2.0 * area * ramp).sqrt()
+        } else if area < 1.0 - 1.5 * ramp {
+            area + ramp / 2.0
+        } else {
+            1.0 - (2.0 * (1.0 - ramp - area) * ramp).max(0.0).sqrt()
+        }
+    }
+}
+
+/// Settings for rendering a pair of images, one for each eye.
+#[derive(Debug, Clone, Copy)]
+pub struct Stereo {
+    /// Distance between the eyes, in scene units
+    pub ipd: f32,
+    /// Distance at which the views of both eyes meet. Objects at this distance
+    /// appear at the depth of the screen. It must be positive, while ``ipd`` may be 0.
+    pub convergence: f32,
+    pub layout: StereoLayout,
+}
+
+/// How the images of the two eyes are placed in the output image.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+pub enum StereoLayout {
+    /// Left eye on the left, right eye on the right
+    SideBySide,
+    /// Left eye on the top, right eye on the bottom
+    TopBottom,
+}
+
+/// How the light reaching the camera is found.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Integrator {
+    /// Paths are traced from the camera, sampling a light at every hit (Refer to [ray_colour()])
+    Path,
+    /// Paths are traced from both the camera and the lights, and joined in every possible
+    /// way (Refer to [bdpt]). This finds caustics, and lights hidden behind glass or in
+    /// fixtures, but only works with a plain perspective camera
+    /// (Refer to [Camera::supports_light_tracing()]).
+    Bidirectional,
+    /// Light is gathered from photons traced from the lights beforehand (Refer to [photon]).
+    /// This gives sharp caustics, but blurs the lighting slightly.
+    PhotonMapping(PhotonSettings),
+    /// Photons are traced again in every pass, gathered from a shrinking radius
+    /// (Refer to [photon::render_progressive()]). ``samples_per_pixel`` is ignored,
+    /// as every pass traces one ray through each pixel.
+    ProgressivePhotonMapping(PhotonSettings),
+    /// Paths are found by mutating the random numbers of paths already found, in proportion
+    /// to their brightness (Refer to [mlt]). This keeps exploring narrow ways for light to
+    /// reach the camera once they are found. ``samples_per_pixel`` sets the number of
+    /// mutations per pixel.
+    Metropolis(MetropolisSettings),
+}
+
+/// Limits on the paths traced by the path tracer, besides ``max_depth``.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PathDepth {
+    /// Number of bounces after which paths are ended at random, with a probability that grows
+    /// as less light is carried (Russian roulette). Paths that carry on are made brighter to
+    /// make up for those that were ended, so the image is not darkened.
+    pub roulette: u32,
+    /// Largest number of diffuse bounces along a path
+    pub diffuse: u32,
+    /// Largest number of mirror-like (sharp or blurred) reflections along a path
+    pub glossy: u32,
+    /// Largest number of times a path passes through a surface, like glass
+    pub transmission: u32,
+}
+
+impl Default for PathDepth {
+    fn default() -> Self {
+        Self {
+            roulette: 5,
+            diffuse: u32::MAX,
+            glossy: u32::MAX,
+            transmission: u32::MAX,
+        }
+    }
+}
+
+/// Builds a [Camera], checking its settings. Unset settings are those of the camera in
+/// "Ray Tracing in One Weekend": a square image 100 pixels wide, looking down -z from the origin.
+///
+/// Options:
+/// - ``aspect_ratio``: Configures the ratio of the image width to height
+/// - ``image_width``: Configures how wide the image will be
+/// - ``image_height``: Configures how tall the image will be, instead of following from ``aspect_ratio``
+/// - ``samples_per_pixel``: While anti-aliiasing, how many samples should the raytracer take. Set to 1 to turn off anti-aliasing.
+/// - ``max_depth``: While bouncing off rays (for the lack of a better word), how many reflections should be counted.
+/// - ``vfov``: How close should the camera be to the object
+/// - ``defocus_angle``: Indirectly asking: how thick is the lens?
+/// - ``focus_distance``: How far is the focus of the camera?
+/// - ``lookfrom``: What is the vector of placement of the camera?
+/// - ``lookat``: In what direction is the camera looking? One can also calculate this by subtracting the position vectors of the image from the position vector of the camera (``lookfrom``).
+/// - ``vup``: Where is the sky?
+#[derive(Debug, Clone)]
+pub struct CameraBuilder {
+    aspect_ratio: f32,
+    image_width: u32,
+    image_height: Option<u32>,
+    samples_per_pixel: u32,
+    max_depth: u32,
+    vfov: f32,
+    defocus_angle: f32,
+    focus_distance: f32,
+    lookfrom: Vec3,
+    lookat: Vec3,
+    vup: Vec3,
+}
+
+/// Why a [CameraBuilder] could not build a camera, or a camera could not be changed.
+#[derive(Debug, Clone)]
+pub enum CameraError {
+    /// The aspect ratio is not a positive number
+    AspectRatio(f32),
+    /// The image is 0 pixels wide
+    ImageWidth,
+    /// The image is asked to be 0 pixels tall
+    ImageHeight,
+    /// The aspect ratio leaves less than one row of pixels at the width of the image
+    TooFewRows { width: u32, aspect_ratio: f32 },
+    /// No samples are taken per pixel
+    SamplesPerPixel,
+    /// The resolution is not a positive percentage
+    Resolution(f32),
+    /// The frames per second of an animation are not a positive number
+    FrameRate(f32),
+    /// The region is empty, reversed, or not inside the output image of the given size
+    Region { bounds: RegionBounds, width: u32, height: u32 },
+    /// The vertical field of view is not between 0 and 180 degrees
+    Fov(f32),
+    /// The defocus angle is not between 0 and 180 degrees
+    DefocusAngle(f32),
+    /// The focus distance is not a positive number
+    FocusDistance(f32),
+    /// The camera looks at the point it is placed at, so it faces no direction
+    LookAtLookFrom(Point3),
+    /// The up vector is zero, or points along the view direction, so the camera cannot be
+    /// turned upright
+    Vup { vup: Vec3, direction: Vec3 },
+    /// A polygonal aperture has fewer than 3 blades
+    ApertureBlades(u32),
+    /// The image of an aperture mask has no pixels, or no bright ones
+    ApertureMask,
+    /// The anamorphic squeeze is not a positive number
+    Anamorphic(f32),
+    /// The vignetting strength is negative, or not a number
+    Vignetting(f32),
+    /// The shutter closes before it opens, or at a time that is not a number
+    Shutter { open: f32, close: f32 },
+    /// The width of the view of an orthographic camera is not a positive number
+    OrthographicWidth(f32),
+    /// The field of view of a fisheye camera is not between 0 and 360 degrees
+    FisheyeFov(f32),
+    /// The distance between the eyes of a stereo pair is negative, or not a number
+    Ipd(f32),
+    /// The distance at which the eyes of a stereo pair converge is not a positive number
+    Convergence(f32),
+}
+
+impl fmt::Display for CameraError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::AspectRatio(x) => write!(f, "the aspect ratio must be a positive number, not {}", x),
+            Self::ImageWidth => write!(f, "the image must be at least 1 pixel wide"),
+            Self::ImageHeight => write!(f, "the image must be at least 1 pixel tall"),
+            Self::TooFewRows { width, aspect_ratio } => write!(
+                f,
+                "the image must be at least 1 pixel tall, but a width of {} at an aspect ratio of {} leaves {:.2} rows",
+                width,
+                aspect_ratio,
+                *width as f32 / aspect_ratio
+            ),
+            Self::SamplesPerPixel => write!(f, "at least 1 sample must be taken per pixel"),
+            Self::Resolution(x) => write!(f, "the resolution must be a positive percentage, not {}", x),
+            Self::FrameRate(x) => write!(f, "the frames per second must be a positive number, not {}", x),
+            Self::Region { bounds: RegionBounds::Pixels { x0, y0, x1, y1 }, width, height } => write!(
+                f,
+                "the region from {},{} to {},{} must not be empty, and must be inside the {}x{} image",
+                x0, y0, x1, y1, width, height
+            ),
+            Self::Region { bounds: RegionBounds::Border { x0, y0, x1, y1 }, width, height } => write!(
+                f,
+                "the border from {},{} to {},{} must be in fractions between 0 and 1, and must leave at least a pixel of the {}x{} image",
+                x0, y0, x1, y1, width, height
+            ),
+            Self::Fov(x) => write!(f, "the vertical field of view must be between 0 and 180 degrees, not {}", x),
+            Self::DefocusAngle(x) => write!(f, "the defocus angle must be between 0 and 180 degrees, not {}", x),
+            Self::FocusDistance(x) => write!(f, "the focus distance must be a positive number, not {}", x),
+            Self::LookAtLookFrom(x) => write!(
+                f,
+                "the camera looks at the point it is placed at ({}, {}, {}), so it faces no direction",
+                x.x, x.y, x.z
+            ),
+            Self::Vup { vup, direction } => write!(
+                f,
+                "the up vector ({}, {}, {}) must not be zero or point along the view direction ({}, {}, {})",
+                vup.x, vup.y, vup.z, direction.x, direction.y, direction.z
+            ),
+            Self::ApertureBlades(x) => write!(f, "a polygonal aperture must have at least 3 blades, not {}", x),
+            Self::ApertureMask => write!(f, "the aperture mask must have at least one pixel that is not black"),
+            Self::Anamorphic(x) => write!(f, "the anamorphic squeeze must be a positive number, not {}", x),
+            Self::Vignetting(x) => write!(f, "the vignetting strength must be a number of at least 0, not {}", x),
+            Self::Shutter { open, close } => write!(
+                f,
+                "the shutter must open at a time no later than it closes, not open at {} and close at {}",
+                open, close
+            ),
+            Self::OrthographicWidth(x) => write!(f, "the width of an orthographic view must be a positive number, not {}", x),
+            Self::FisheyeFov(x) => write!(f, "the field of view of a fisheye must be above 0 and at most 360 degrees, not {}", x),
+            Self::Ipd(x) => write!(f, "the distance between the eyes must be a number of at least 0, not {}", x),
+            Self::Convergence(x) => write!(f, "the convergence distance must be a positive number, not {}", x),
+        }
+    }
+}
+
+impl Error for CameraError {}
+
+impl Default for CameraBuilder {
+    fn default() -> Self {
         Self {
+            aspect_ratio: 1.0,
+            image_width: 100,
+            image_height: None,
+            samples_per_pixel: 10,
+            max_depth: 10,
+            vfov: 90.0,
+            defocus_angle: 0.0,
+            focus_distance: 10.0,
+            lookfrom: Vec3::from_point(0.0, 0.0, 0.0),
+            lookat: Vec3::from_point(0.0, 0.0, -1.0),
+            vup: Vec3::from_point(0.0, 1.0, 0.0),
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl CameraBuilder {
+    /// Sets the ratio of the image width to height. The height follows from the width.
+    pub fn with_aspect_ratio(mut self, aspect_ratio: f32) -> Self {
+        self.aspect_ratio = aspect_ratio;
+        self
+    }
+
+    pub fn with_image_width(mut self, image_width: u32) -> Self {
+        self.image_width = image_width;
+        self
+    }
+
+    /// Sets the height of the image, which sets the aspect ratio instead of following from it.
+    pub fn with_image_height(mut self, image_height: u32) -> Self {
+        self.image_height = Some(image_height);
+        self
+    }
+
+    pub fn with_samples_per_pixel(mut self, samples_per_pixel: u32) -> Self {
+        self.samples_per_pixel = samples_per_pixel;
+        self
+    }
+
+    pub fn with_max_depth(mut self, max_depth: u32) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Sets the vertical field of view, in degrees.
+    pub fn with_vfov(mut self, vfov: f32) -> Self {
+        self.vfov = vfov;
+        self
+    }
+
+    /// Sets the angle (in degrees) of the cone of rays through each pixel, from the lens to the
+    /// focus plane. 0 keeps everything in focus.
+    pub fn with_defocus_angle(mut self, defocus_angle: f32) -> Self {
+        self.defocus_angle = defocus_angle;
+        self
+    }
+
+    /// Sets the distance from the camera to the plane that is in perfect focus.
+    pub fn with_focus_distance(mut self, focus_distance: f32) -> Self {
+        self.focus_distance = focus_distance;
+        self
+    }
+
+    /// Places the camera at ``lookfrom``, looking at ``lookat``, turned so that ``vup`` points
+    /// up the image.
+    pub fn with_frame(mut self, lookfrom: Point3, lookat: Point3, vup: Vec3) -> Self {
+        self.lookfrom = lookfrom;
+        self.lookat = lookat;
+        self.vup = vup;
+        self
+    }
+
+    /// Checks the settings, and creates the camera (Refer to [Camera::initialize()]).
+    pub fn build(self) -> Result<Camera, CameraError> {
+        if self.image_width == 0 {
+            return Err(CameraError::ImageWidth);
+        }
+        let aspect_ratio = match self.image_height {
+            Some(0) => return Err(CameraError::ImageHeight),
+            Some(height) => self.image_width as f32 / height as f32,
+            None if !positive(self.aspect_ratio) => return Err(CameraError::AspectRatio(self.aspect_ratio)),
+            None if self.image_width as f32 / self.aspect_ratio < 1.0 => {
+                return Err(CameraError::TooFewRows { width: self.image_width, aspect_ratio: self.aspect_ratio });
+            }
+            None => self.aspect_ratio,
+        };
+
+        let camera = Camera {
             aspect_ratio,
-            image_width,
-            samples_per_pixel,
-            max_depth,
-            vfov,
-            lookfrom,
-            lookat,
-            vup,
-	    defocus_angle,
-	    focus_distance,
+            image_width: self.image_width,
+            samples_per_pixel: self.samples_per_pixel,
+            max_depth: self.max_depth,
+            vfov: self.vfov,
+            lookfrom: self.lookfrom,
+            lookat: self.lookat,
+            vup: self.vup,
+            defocus_angle: self.defocus_angle,
+            focus_distance: self.focus_distance,
+            spectral: false,
+            projection: Projection::Perspective,
+            stereo: None,
+            aperture: Aperture::Circular,
+            anamorphic: 1.0,
+            vignetting: 0.0,
+            shutter_open: 0.0,
+            shutter_close: 1.0,
+            shutter_curve: ShutterCurve::Box,
+            camera_motion: None,
+            integrator: Integrator::Path,
+            path_depth: PathDepth::default(),
+            adaptive: None,
+            progressive: None,
+            denoise: None,
+            resolution: 100.0,
+            region: None,
+            seed: None,
+            aperture_mask: None,
+            checkpoint: None,
+            explicit_height: self.image_height,
+            render_width: 0,
             image_height: 0,
             camera_center: Vec3::new(),
             init_pixel_loc: Vec3::new(),
             delta_u: Vec3::new(),
             delta_v: Vec3::new(),
-	    defocus_u: Vec3::new(),
-	    defocus_v: Vec3::new()
+            defocus_u: Vec3::new(),
+            defocus_v: Vec3::new(),
+            u: Vec3::new(),
+            v: Vec3::new(),
+            w: Vec3::new(),
+        };
+
+        camera.checked()
+    }
+}
+
+/// Whether the number is finite and above 0
+fn positive(x: f32) -> bool {
+    x.is_finite() && x > 0.0
+}
+
+/// Part of the output image to render (Refer to [Camera::with_region()]).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Region {
+    pub bounds: RegionBounds,
+    /// Whether to write the whole image, with the pixels outside the region black,
+    /// rather than only the region
+    pub full_canvas: bool,
+}
+
+/// Corners of a [Region], from the top left (inclusive) to the bottom right (exclusive).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RegionBounds {
+    /// Pixels of the output image, after scaling by ``resolution``
+    Pixels { x0: u32, y0: u32, x1: u32, y1: u32 },
+    /// Fractions of the width and height of the output image, from 0 to 1, which keep the
+    /// region in place at any resolution
+    Border { x0: f32, y0: f32, x1: f32, y1: f32 },
+}
+
+/// Settings of adaptive sampling: every pixel stops taking samples once its brightness is known
+/// well enough, so that flat areas finish early and noisy ones get up to ``samples_per_pixel``.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdaptiveSampling {
+    /// Largest relative error of the brightness of a pixel (the standard error of its mean,
+    /// over the mean) at which it stops taking samples
+    pub threshold: f32,
+    /// Number of samples every pixel takes before its error is checked
+    pub min_samples: u32,
+    /// Number of samples taken between checks of the error
+    pub batch: u32,
+    /// Where to write a PPM image of the number of samples taken by every pixel, from
+    /// blue (fewest) to red (``samples_per_pixel``)
+    pub heat_map: Option<PathBuf>,
+}
+
+impl Default for AdaptiveSampling {
+    fn default() -> Self {
+        Self {
+            threshold: 0.02,
+            min_samples: 16,
+            batch: 16,
+            heat_map: None,
+        }
+    }
+}
+
+/// Settings of progressive rendering: the image is rendered in passes over every pixel, each
+/// doubling the samples taken so far, and the image so far is saved as the render goes on.
+/// The render stops at ``samples_per_pixel``, or at the first limit met.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Progressive {
+    /// Where the image rendered so far is saved
+    pub output: PathBuf,
+    /// Number of passes between saves
+    pub save_passes: Option<u32>,
+    /// Number of seconds between saves
+    pub save_seconds: Option<f32>,
+    /// Number of seconds after which no more passes are started
+    pub time_limit: Option<f32>,
+    /// Noise at which the render stops: the relative error of the brightness of the pixels
+    /// (Refer to [AdaptiveSampling::threshold]), averaged over the image
+    pub noise: Option<f32>,
+    /// Where the state of the render is saved along with the image, to be resumed from
+    /// (Refer to [Checkpoint]). Passes then no longer shrink to meet ``time_limit`` or
+    /// ``save_seconds``, which are only checked between passes.
+    pub checkpoint: Option<PathBuf>,
+    /// Whether to continue from the state saved in ``checkpoint``
+    pub resume: bool,
+}
+
+impl Default for Progressive {
+    fn default() -> Self {
+        Self {
+            output: PathBuf::from("progress.ppm"),
+            save_passes: Some(1),
+            save_seconds: None,
+            time_limit: None,
+            noise: None,
+            checkpoint: None,
+            resume: false,
+        }
+    }
+}
+
+/// Running sums of the samples of a pixel.
+#[derive(Debug, Clone, Copy)]
+pub struct PixelStats {
+    pub colour: Colour,
+    /// Sums of the luminance of the samples and of its square, kept in double precision
+    /// as the variance is their difference
+    pub luminance: f64,
+    pub luminance_sq: f64,
+    pub count: u32,
+}
+
+impl Default for PixelStats {
+    fn default() -> Self {
+        Self {
+            colour: Vec3::new(),
+            luminance: 0.0,
+            luminance_sq: 0.0,
+            count: 0,
+        }
+    }
+}
+
+impl PixelStats {
+    pub fn add(mut self, other: Self) -> Self {
+        self.colour += other.colour;
+        self.luminance += other.luminance;
+        self.luminance_sq += other.luminance_sq;
+        self.count += other.count;
+        self
+    }
+
+    /// Variance of the mean luminance of the samples
+    pub fn variance(&self) -> f64 {
+        if self.count < 2 {
+            return 0.0;
+        }
+        let n = self.count as f64;
+        let mean = self.luminance / n;
+        ((self.luminance_sq - self.luminance * mean) / (n - 1.0)).max(0.0) / n
+    }
+
+    /// Standard error of the mean luminance, over the mean. Pixels darker than 1% grey
+    /// are measured against it instead, so that black pixels can converge.
+    pub fn relative_error(&self) -> f64 {
+        if self.count < 2 {
+            return f64::INFINITY;
+        }
+        let mean = self.luminance / self.count as f64;
+        self.variance().sqrt() / mean.max(0.01)
+    }
+}
+
+/// What is shared by every sample of a render.
+struct RenderState {
+    /// Lights are chosen by power alone when starting paths from them
+    lights: PowerLightSampler,
+    /// Light splatted onto the film by the bidirectional integrator
+    splats: Mutex<Vec<Colour>>,
+    photons: Option<PhotonMaps>,
+    /// Seed every sample takes its random numbers from, if they are to be repeatable
+    seed: Option<u64>,
+}
+
+/// A point on the lens, sampled to join a path from a light to the camera.
+pub struct ImportanceSample {
+    pub lens: Point3,
+    /// Camera importance of the ray from the lens to the point
+    pub importance: f32,
+    /// Probability density of the lens point, per solid angle at the point
+    pub pdf: f32,
+    /// Pixel of the output image the ray lands on
+    pub pixel: (u32, u32),
+}
+
+#[allow(dead_code)]
+impl Camera {
+    /// Starts building a camera (Refer to [CameraBuilder]).
+    pub fn builder() -> CameraBuilder {
+        CameraBuilder::default()
+    }
+
+    /// Checks the settings that the camera cannot be rendered without, which the builder,
+    /// [Camera::frame()] and every setting of the camera go through (Refer to [Camera::checked()]).
+    fn validate(&self) -> Result<(), CameraError> {
+        if self.image_width == 0 {
+            return Err(CameraError::ImageWidth);
+        }
+        if !positive(self.aspect_ratio) {
+            return Err(CameraError::AspectRatio(self.aspect_ratio));
+        }
+        if self.samples_per_pixel == 0 {
+            return Err(CameraError::SamplesPerPixel);
+        }
+        if !positive(self.resolution) {
+            return Err(CameraError::Resolution(self.resolution));
+        }
+        if !(positive(self.vfov) && self.vfov < 180.0) {
+            return Err(CameraError::Fov(self.vfov));
+        }
+        if !(self.defocus_angle.is_finite() && (0.0..180.0).contains(&self.defocus_angle)) {
+            return Err(CameraError::DefocusAngle(self.defocus_angle));
+        }
+        if !positive(self.focus_distance) {
+            return Err(CameraError::FocusDistance(self.focus_distance));
+        }
+        match self.projection {
+            Projection::Orthographic { width } if !positive(width) => return Err(CameraError::OrthographicWidth(width)),
+            Projection::Fisheye { fov, .. } if !(positive(fov) && fov <= 360.0) => return Err(CameraError::FisheyeFov(fov)),
+            _ => {}
+        }
+        if let Some(stereo) = self.stereo {
+            if !(stereo.ipd >= 0.0 && stereo.ipd.is_finite()) {
+                return Err(CameraError::Ipd(stereo.ipd));
+            }
+            if !positive(stereo.convergence) {
+                return Err(CameraError::Convergence(stereo.convergence));
+            }
+        }
+        match self.aperture {
+            Aperture::Polygon { blades, .. } if blades < 3 => return Err(CameraError::ApertureBlades(blades)),
+            Aperture::Mask(_) if !self.aperture_mask.as_ref().is_some_and(|x| x.integral() > 0.0) => {
+                return Err(CameraError::ApertureMask);
+            }
+            _ => {}
+        }
+        if !positive(self.anamorphic) {
+            return Err(CameraError::Anamorphic(self.anamorphic));
+        }
+        if !(self.vignetting >= 0.0 && self.vignetting.is_finite()) {
+            return Err(CameraError::Vignetting(self.vignetting));
+        }
+        if !(self.shutter_open.is_finite() && self.shutter_close.is_finite() && self.shutter_open <= self.shutter_close) {
+            return Err(CameraError::Shutter { open: self.shutter_open, close: self.shutter_close });
+        }
+
+        // The camera must face somewhere, and be upright, both when the shutter opens and
+        // when it closes
+        let frames = std::iter::once((self.lookfrom, self.lookat)).chain(self.camera_motion);
+        for (lookfrom, lookat) in frames {
+            let direction = lookat - lookfrom;
+            if !(direction.length_sq() > 0.0 && direction.length_sq().is_finite()) {
+                return Err(CameraError::LookAtLookFrom(lookfrom));
+            }
+            // Nearly parallel vectors leave an up direction too short to normalise reliably
+            let sine = self.vup.cross(direction).length() / (self.vup.length() * direction.length());
+            if !sine.is_finite() || sine < 1e-6 {
+                return Err(CameraError::Vup { vup: self.vup, direction });
+            }
         }
-        .initialize()
+
+        Ok(())
+    }
+
+    /// Checks the settings (Refer to [Camera::validate()]), and initialises the camera with them.
+    /// The region is checked last, as it depends on the size of the initialised image.
+    fn checked(self) -> Result<Self, CameraError> {
+        self.validate()?;
+        let camera = self.initialize();
+        camera.validate_region()?;
+        Ok(camera)
     }
 
     /// Initialises the camera by setting internal variables required for rendering.
@@ -85,18 +770,33 @@ impl Camera {
         // Camera setup. Currently at the origin
         self.camera_center = self.lookfrom;
 
-        // TODO: We currently ignore case when height is < 1
-        self.image_height = (self.image_width as f32 / self.aspect_ratio) as u32;
-        let viewport_ratio: f32 = self.image_width as f32 / self.image_height as f32;
+        // The field of view stays the same at any resolution
+        self.render_width = ((self.image_width as f32 * self.resolution / 100.0).round() as u32).max(1);
 
-        let height: f32 = (self.vfov.to_radians() / 2.0).tan();
-        let viewport_height: f32 = 2.0 * height * self.focus_distance;
-        let viewport_width: f32 = viewport_height * viewport_ratio;
+        // The builder makes sure there is at least one row, but scaling can take it away
+        self.image_height = match self.explicit_height {
+            Some(height) => (height as f32 * self.resolution / 100.0).round() as u32,
+            None => (self.render_width as f32 / self.aspect_ratio) as u32,
+        }
+        .max(1);
+        let viewport_ratio: f32 = self.render_width as f32 / self.image_height as f32;
+
+        let (viewport_width, viewport_height) = match self.projection {
+            Projection::Orthographic { width } => (width, width / viewport_ratio),
+            _ => {
+                let height: f32 = (self.vfov.to_radians() / 2.0).tan();
+                let viewport_height: f32 = 2.0 * height * self.focus_distance;
+                (viewport_height * viewport_ratio, viewport_height)
+            }
+        };
 
 	// Set orthonormal viewport vectors
         let w = (self.lookfrom - self.lookat).unit();
         let u = self.vup.cross(w).unit();
         let v = w.cross(u);
+        self.u = u;
+        self.v = v;
+        self.w = w;
 
         // Viewport vectors. Since the pixels are numbered from the top,
         // while the camera has axis facing upwards, we are forced to mark
@@ -104,7 +804,7 @@ impl Camera {
         let viewport_u: Vec3 = viewport_width * u;
         let viewport_v: Vec3 = viewport_height * -v;
 
-        self.delta_u = viewport_u / (self.image_width as f32);
+        self.delta_u = viewport_u / (self.render_width as f32);
         self.delta_v = viewport_v / (self.image_height as f32);
 
         let viewport_topleft = self.camera_center
@@ -119,71 +819,969 @@ impl Camera {
         self
     }
 
+    /// Moves the film of a realistic lens (Refer to [Projection::Realistic]) to focus at
+    /// ``focus_distance``. This traces rays through the lens, so it is only done when the
+    /// lens or the focus distance change.
+    fn focus_lens(mut self) -> Self {
+        if let Projection::Realistic(lens) = &self.projection {
+            self.projection = Projection::Realistic(Arc::new(lens.focused(self.focus_distance)));
+        }
+        self
+    }
+
+    /// Largest number of times a ray is scattered
+    pub fn max_depth(&self) -> u32 {
+        self.max_depth
+    }
+
+    /// Number of samples taken per pixel (Refer to [Integrator] for how each integrator uses it)
+    pub fn samples_per_pixel(&self) -> u32 {
+        self.samples_per_pixel
+    }
+
+    /// Limits on the paths traced by the path tracer (Refer to [PathDepth])
+    pub fn path_depth(&self) -> &PathDepth {
+        &self.path_depth
+    }
+
+    /// Seed of the random numbers of the samples, if any (Refer to [Camera::with_seed()])
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    /// Point the camera is placed at, when the shutter opens
+    pub fn lookfrom(&self) -> Point3 {
+        self.lookfrom
+    }
+
+    /// Point the camera looks at, when the shutter opens
+    pub fn lookat(&self) -> Point3 {
+        self.lookat
+    }
+
+    /// Times at which the shutter opens and closes
+    pub fn shutter(&self) -> (f32, f32) {
+        (self.shutter_open, self.shutter_close)
+    }
+
+    /// Switches between RGB and spectral rendering.
+    /// In spectral mode, every camera ray carries a set of wavelengths (Refer to [spectrum]),
+    /// which is slower, but can reproduce dispersion and measured metals accurately.
+    pub fn with_spectral(mut self, spectral: bool) -> Result<Self, CameraError> {
+        self.spectral = spectral;
+        self.checked()
+    }
+
+    /// Sets how the camera projects the scene onto the image (Refer to [Projection]).
+    /// All projections share the frame set by ``lookfrom``, ``lookat`` and ``vup``.
+    pub fn with_projection(mut self, projection: Projection) -> Result<Self, CameraError> {
+        self.projection = projection;
+        self.focus_lens().checked()
+    }
+
+    /// Renders a stereo pair (Refer to [Stereo]) instead of a single image.
+    /// The image size set on the camera is the size of each eye's image.
+    ///
+    /// With the equirectangular projection, this renders omni-directional stereo,
+    /// where the eyes are placed on a circle, so that the stereo effect holds in
+    /// every direction of the panorama.
+    pub fn with_stereo(mut self, stereo: Stereo) -> Result<Self, CameraError> {
+        self.stereo = Some(stereo);
+        self.checked()
+    }
+
+    /// Returns the width and height of the rendered image, including both eyes for stereo.
+    pub fn output_size(&self) -> (u32, u32) {
+        match self.stereo.map(|x| x.layout) {
+            None => (self.render_width, self.image_height),
+            Some(StereoLayout::SideBySide) => (2 * self.render_width, self.image_height),
+            Some(StereoLayout::TopBottom) => (self.render_width, 2 * self.image_height),
+        }
+    }
+
+    /// Maps a pixel of the output image to the eye it belongs to (-1 for left,
+    /// 1 for right, and 0 without stereo) and the pixel in that eye's image.
+    fn eye_pixel(&self, i: u32, j: u32) -> (f32, u32, u32) {
+        match self.stereo.map(|x| x.layout) {
+            None => (0.0, i, j),
+            Some(StereoLayout::SideBySide) if i < self.render_width => (-1.0, i, j),
+            Some(StereoLayout::SideBySide) => (1.0, i - self.render_width, j),
+            Some(StereoLayout::TopBottom) if j < self.image_height => (-1.0, i, j),
+            Some(StereoLayout::TopBottom) => (1.0, i, j - self.image_height),
+        }
+    }
+
     /// Samples random points uniformly in a square sized ``delta_u`` times ``delta_v``
     fn pixel_sample_square(&self) -> Vec3 {
         0.5 * random() * self.delta_u + 0.5 * random() * self.delta_v
     }
 
+    /// Sets the shape of the aperture (Refer to [Aperture]).
+    /// A polygon needs at least 3 blades, and a mask at least one pixel that is not black.
+    pub fn with_aperture(mut self, aperture: Aperture) -> Result<Self, CameraError> {
+        // Points are drawn in proportion to the brightness of the mask, one texel at a time
+        self.aperture_mask = match &aperture {
+            Aperture::Mask(image) => {
+                let weights: Vec<f32> = image.pixels.iter().map(|x| ((x.x + x.y + x.z) / 3.0).clamp(0.0, 1.0)).collect();
+                (!weights.is_empty()).then(|| Arc::new(Distribution2D::new(&weights, image.width, image.height)))
+            }
+            _ => None,
+        };
+        self.aperture = aperture;
+        self.checked()
+    }
+
+    /// Stretches the aperture vertically by ``squeeze``, as with an anamorphic lens,
+    /// giving oval out of focus highlights. A squeeze of 1 leaves the aperture as is.
+    pub fn with_anamorphic(mut self, squeeze: f32) -> Result<Self, CameraError> {
+        self.anamorphic = squeeze;
+        self.checked()
+    }
+
+    /// Enables optical vignetting, where the lens barrel blocks part of the aperture
+    /// towards the edges of the image. This darkens the corners, and turns the bokeh
+    /// there into a "cat's eye" shape. At a ``strength`` of 1, the aperture is completely
+    /// blocked at the edge of the image along the width.
+    pub fn with_vignetting(mut self, strength: f32) -> Result<Self, CameraError> {
+        self.vignetting = strength;
+        self.checked()
+    }
+
+    /// Sets the times at which the shutter opens and closes, and how it does so
+    /// (Refer to [ShutterCurve]). Every ray is given a time in this interval.
+    pub fn with_shutter(mut self, open: f32, close: f32, curve: ShutterCurve) -> Result<Self, CameraError> {
+        self.shutter_open = open;
+        self.shutter_close = close;
+        self.shutter_curve = curve;
+        self.checked()
+    }
+
+    /// Moves the camera while the shutter is open, from ``lookfrom`` and ``lookat`` when
+    /// the shutter opens, to ``lookfrom_end`` and ``lookat_end`` when it closes.
+    pub fn with_camera_motion(mut self, lookfrom_end: Point3, lookat_end: Point3) -> Result<Self, CameraError> {
+        self.camera_motion = Some((lookfrom_end, lookat_end));
+        self.checked()
+    }
+
+    /// Sets how light is carried to the camera (Refer to [Integrator]).
+    pub fn with_integrator(mut self, integrator: Integrator) -> Result<Self, CameraError> {
+        self.integrator = integrator;
+        self.checked()
+    }
+
+    /// Sets the limits on the paths traced by the path tracer (Refer to [PathDepth]).
+    pub fn with_path_depth(mut self, path_depth: PathDepth) -> Result<Self, CameraError> {
+        self.path_depth = path_depth;
+        self.checked()
+    }
+
+    /// Makes pixels stop taking samples once they have converged (Refer to [AdaptiveSampling]).
+    pub fn with_adaptive_sampling(mut self, adaptive: AdaptiveSampling) -> Result<Self, CameraError> {
+        self.adaptive = Some(adaptive);
+        self.checked()
+    }
+
+    /// Seeds the random numbers of the samples, so that rendering again gives the same image.
+    /// A progressive render without a seed picks one at random (or takes the one of the
+    /// checkpoint it resumes).
+    pub fn with_seed(mut self, seed: Option<u64>) -> Result<Self, CameraError> {
+        self.seed = seed;
+        self.checked()
+    }
+
+    /// Renders in passes over the whole image, saving the image as it goes (Refer to [Progressive]).
+    pub fn with_progressive(mut self, progressive: Progressive) -> Result<Self, CameraError> {
+        self.progressive = Some(progressive);
+        self.checked()
+    }
+
+    /// Resumes the progressive render from a checkpoint that was already loaded (to build the
+    /// scene from its seed, say), instead of loading the one in ``progressive`` again.
+    pub fn with_checkpoint(mut self, checkpoint: Checkpoint) -> Result<Self, CameraError> {
+        self.checkpoint = Some(Arc::new(checkpoint));
+        self.checked()
+    }
+
+    /// Settings a checkpoint must have been saved with to be resumed (Refer to [CheckpointSettings])
+    fn checkpoint_settings(&self) -> CheckpointSettings {
+        CheckpointSettings {
+            integrator: format!("{:?}", self.integrator),
+            spectral: self.spectral,
+            samples_per_pixel: self.samples_per_pixel,
+            adaptive: self.adaptive.as_ref().map(|x| (x.threshold, x.min_samples, x.batch)),
+            region: self.region_bounds(),
+        }
+    }
+
+    /// Scales the size of the image to the given percentage of ``image_width``, keeping the
+    /// same framing (the field of view is not changed). Handy for quick previews.
+    /// The percentage must be positive.
+    pub fn with_resolution(mut self, percentage: f32) -> Result<Self, CameraError> {
+        if !positive(percentage) {
+            return Err(CameraError::Resolution(percentage));
+        }
+        self.resolution = percentage;
+        self.checked()
+    }
+
+    /// Renders only the given part of the image (Refer to [Region]). The pixels outside it
+    /// are not traced, although the photon mapping and Metropolis integrators, which light
+    /// the image as a whole, still render all of it before the region is cut out.
+    /// The region must cover at least one pixel, inside the output image.
+    pub fn with_region(mut self, region: Region) -> Result<Self, CameraError> {
+        self.region = Some(region);
+        self.checked()
+    }
+
+    /// Checks that the region is not empty or reversed, and lies inside the output image,
+    /// which is only known once the camera is initialised
+    fn validate_region(&self) -> Result<(), CameraError> {
+        let Some(region) = self.region else {
+            return Ok(());
+        };
+        let (width, height) = self.output_size();
+        let fraction = |x: f32| (0.0..=1.0).contains(&x);
+        let valid = match region.bounds {
+            RegionBounds::Pixels { .. } => true,
+            RegionBounds::Border { x0, y0, x1, y1 } => [x0, y0, x1, y1].into_iter().all(fraction),
+        };
+
+        let (x0, y0, x1, y1) = self.region_bounds();
+        if !(valid && x0 < x1 && y0 < y1 && x1 <= width && y1 <= height) {
+            return Err(CameraError::Region { bounds: region.bounds, width, height });
+        }
+        Ok(())
+    }
+
+    /// Returns the corners of the region of the output image that is rendered, as
+    /// ``(x0, y0, x1, y1)`` (Refer to [RegionBounds]). This is the whole image without a region.
+    pub fn region_bounds(&self) -> (u32, u32, u32, u32) {
+        let (width, height) = self.output_size();
+        match self.region.map(|x| x.bounds) {
+            None => (0, 0, width, height),
+            Some(RegionBounds::Pixels { x0, y0, x1, y1 }) => (x0, y0, x1, y1),
+            Some(RegionBounds::Border { x0, y0, x1, y1 }) => {
+                let across = |x: f32| (x * width as f32).round() as u32;
+                let down = |y: f32| (y * height as f32).round() as u32;
+                (across(x0), down(y0), across(x1), down(y1))
+            }
+        }
+    }
+
+    /// Whether the given pixel of the output image is in the region that is rendered
+    fn in_region(&self, i: u32, j: u32) -> bool {
+        let (x0, y0, x1, y1) = self.region_bounds();
+        (x0..x1).contains(&i) && (y0..y1).contains(&j)
+    }
+
+    /// Returns the width and height of the image that is written: the region that is
+    /// rendered, or the whole output image if it is written in full.
+    pub fn written_size(&self) -> (u32, u32) {
+        match self.region {
+            Some(region) if !region.full_canvas => {
+                let (x0, y0, x1, y1) = self.region_bounds();
+                (x1 - x0, y1 - y0)
+            }
+            _ => self.output_size(),
+        }
+    }
+
+    /// Cuts the values of the pixels of the output image down to the image that is written
+    /// (Refer to [Camera::written_size()]). Pixels outside the region are left as they are,
+    /// which is black for those that were not rendered.
+    fn crop<T: Copy>(&self, pixels: Vec<T>) -> Vec<T> {
+        match self.region {
+            Some(region) if !region.full_canvas => self.in_region_only(&pixels),
+            _ => pixels,
+        }
+    }
+
+    /// Returns the values of the pixels of the output image that are in the region that is
+    /// rendered, row by row.
+    fn in_region_only<T: Copy>(&self, pixels: &[T]) -> Vec<T> {
+        let width = self.output_size().0;
+        let (x0, y0, x1, y1) = self.region_bounds();
+        (y0..y1)
+            .flat_map(|j| (x0..x1).map(move |i| (j * width + i) as usize))
+            .map(|k| pixels[k])
+            .collect()
+    }
+
+    /// Removes noise from the rendered image (Refer to [denoise]).
+    pub fn with_denoiser(mut self, settings: DenoiseSettings) -> Result<Self, CameraError> {
+        self.denoise = Some(settings);
+        self.checked()
+    }
+
+    /// Whether paths from the lights can be joined to the camera, which needs a plain
+    /// perspective camera: one eye, a round unvignetted aperture, and no camera motion.
+    pub fn supports_light_tracing(&self) -> bool {
+        matches!(self.projection, Projection::Perspective)
+            && matches!(self.aperture, Aperture::Circular)
+            && self.stereo.is_none()
+            && self.anamorphic == 1.0
+            && self.vignetting <= 0.0
+            && self.camera_motion.is_none()
+    }
+
+    /// Radius of the lens, which is 0 for a pinhole camera
+    fn lens_radius(&self) -> f32 {
+        if self.defocus_angle <= 0.0 {
+            0.0
+        } else {
+            self.focus_distance * (self.defocus_angle.to_radians() / 2.0).tan()
+        }
+    }
+
+    /// Area of the lens, taken as 1 for a pinhole camera
+    fn lens_area(&self) -> f32 {
+        match self.lens_radius() {
+            r if r > 0.0 => std::f32::consts::PI * r * r,
+            _ => 1.0,
+        }
+    }
+
+    /// Area of the image, at a distance of 1 from the camera
+    fn image_area(&self) -> f32 {
+        let width = self.render_width as f32 * self.delta_u.length();
+        let height = self.image_height as f32 * self.delta_v.length();
+
+        width * height / (self.focus_distance * self.focus_distance)
+    }
+
+    /// Returns the camera importance (the response of the sensor) for a ray leaving the
+    /// lens at ``origin`` along ``direction``, and the pixel it lands on. Importance is
+    /// normalised over the image, so that it integrates to 1 over the lens and the image.
+    /// Returns ``None`` for rays outside the image. Refer to [Camera::supports_light_tracing()].
+    pub fn importance(&self, origin: Point3, direction: Vec3) -> Option<(f32, (u32, u32))> {
+        let direction = direction.unit();
+        let cos_theta = direction.dot(-self.w);
+        if cos_theta <= 0.0 {
+            return None;
+        }
+
+        // Find where the ray crosses the plane in focus, where the pixels are laid out
+        let offset = origin + (self.focus_distance / cos_theta) * direction - self.init_pixel_loc;
+        let x = offset.dot(self.delta_u) / self.delta_u.length_sq() + 0.5;
+        let y = offset.dot(self.delta_v) / self.delta_v.length_sq() + 0.5;
+        if x < 0.0 || y < 0.0 || x >= self.render_width as f32 || y >= self.image_height as f32 {
+            return None;
+        }
+
+        let importance = 1.0 / (self.image_area() * self.lens_area() * cos_theta.powi(4));
+        Some((importance, (x as u32, y as u32)))
+    }
+
+    /// Returns the densities of the camera generating a ray along ``direction``,
+    /// as (position on the lens, direction).
+    pub fn importance_pdf(&self, direction: Vec3) -> (f32, f32) {
+        let cos_theta = direction.unit().dot(-self.w);
+        if cos_theta <= 0.0 {
+            return (0.0, 0.0);
+        }
+
+        (1.0 / self.lens_area(), 1.0 / (self.image_area() * cos_theta.powi(3)))
+    }
+
+    /// Samples a point on the lens that sees ``point``, to join a path from a light to it.
+    pub fn sample_importance(&self, point: Point3) -> Option<ImportanceSample> {
+        let (x, y) = Self::sample_disc();
+        let lens = if self.lens_radius() > 0.0 {
+            self.camera_center + x * self.defocus_u + y * self.defocus_v
+        } else {
+            self.camera_center
+        };
+
+        let offset = lens - point;
+        let distance_sq = offset.length_sq();
+        let direction = offset.unit();
+        let cos_lens = direction.dot(-self.w).abs();
+        if cos_lens <= 0.0 {
+            return None;
+        }
+
+        let (importance, pixel) = self.importance(lens, -direction)?;
+        Some(ImportanceSample {
+            lens,
+            importance,
+            pdf: distance_sq / (cos_lens * self.lens_area()),
+            pixel,
+        })
+    }
+
+    /// Samples the time of a ray
+    fn sample_time(&self) -> f32 {
+        self.shutter_open + self.shutter_curve.sample(random()) * (self.shutter_close - self.shutter_open)
+    }
+
+    /// Moves a ray, generated for the camera where it is when the shutter opens,
+    /// to where the camera is at the time of the ray.
+    fn apply_motion(&self, ray: Ray) -> Ray {
+        let Some((lookfrom_end, lookat_end)) = self.camera_motion else {
+            return ray;
+        };
+
+        let duration = self.shutter_close - self.shutter_open;
+        let t = if duration > 0.0 { (ray.time - self.shutter_open) / duration } else { 0.0 };
+        let lookfrom = self.lookfrom + t * (lookfrom_end - self.lookfrom);
+        let lookat = self.lookat + t * (lookat_end - self.lookat);
+
+        let w = (lookfrom - lookat).unit();
+        let u = self.vup.cross(w).unit();
+        let v = w.cross(u);
+        let to_frame = |x: Vec3| x.dot(self.u) * u + x.dot(self.v) * v + x.dot(self.w) * w;
+
+        let mut moved = ray;
+        moved.origin = lookfrom + to_frame(ray.origin - self.camera_center);
+        moved.direction = to_frame(ray.direction);
+        moved
+    }
+
     /// Samples random points uniformly in a unit circle centered at origin.
     /// Does this by taking random samples inside a unit square centered at origin, and discarding points that lie outside the circle.
-    fn sample_disc (&self) -> Vec3 {
+    fn sample_disc () -> (f32, f32) {
 	loop {
 	    let x = random_range(-1.0..1.0);
 	    let y = random_range(-1.0..1.0);
 
 	    if (x.powi(2) + y.powi(2)) < 1.0 {
-		return x * self.defocus_u + y * self.defocus_v
+		return (x, y)
 	    }
 	}
     }
 
-    /// Samples a ray. Uses randomness to allow for anti-aliasing and defocus blurring
-    fn get_ray(&self, i: u32, j: u32) -> Ray {
+    /// Samples a point on the aperture, as seen from pixel ``(i, j)``.
+    /// Returns ``None`` if the point is blocked by vignetting.
+    fn sample_aperture(&self, i: u32, j: u32) -> Option<Vec3> {
+        let (x, y) = match &self.aperture {
+            Aperture::Circular => Self::sample_disc(),
+            Aperture::Polygon { blades, rotation } => {
+                // Pick one of the triangles between the centre and each edge
+                // (they all have the same area), and sample uniformly inside it.
+                let blades = (*blades).max(3) as f32;
+                let edge = (random() * blades).floor();
+                let angle = |k: f32| rotation.to_radians() + 2.0 * std::f32::consts::PI * k / blades;
+                let (a, b) = (angle(edge), angle(edge + 1.0));
+
+                let (mut s, mut t) = (random(), random());
+                if s + t > 1.0 {
+                    (s, t) = (1.0 - s, 1.0 - t);
+                }
+
+                (s * a.cos() + t * b.cos(), s * a.sin() + t * b.sin())
+            }
+            Aperture::Mask(_) => {
+                // The image runs from the top down, while the aperture runs up
+                let ((u, v), _) = self.aperture_mask.as_ref()?.sample(random(), random());
+                (2.0 * u - 1.0, 1.0 - 2.0 * v)
+            }
+        };
+
+        if self.vignetting > 0.0 {
+            // The barrel is the same size as the aperture, shifted towards the
+            // centre of the image the further out the pixel is.
+            let px = 2.0 * (i as f32 + 0.5) / self.render_width as f32 - 1.0;
+            let py = (1.0 - 2.0 * (j as f32 + 0.5) / self.image_height as f32) * self.image_height as f32
+                / self.render_width as f32;
+            let (bx, by) = (-2.0 * self.vignetting * px, -2.0 * self.vignetting * py);
+
+            if (x - bx).powi(2) + (y - by).powi(2) > 1.0 {
+                return None;
+            }
+        }
+
+        Some(x * self.defocus_u + self.anamorphic * y * self.defocus_v)
+    }
+
+    /// Samples a ray for the given ``eye`` (Refer to [Camera::eye_pixel()]). Uses randomness
+    /// to allow for anti-aliasing and defocus blurring.
+    /// Returns ``None`` if the sample falls outside the area covered by the projection.
+    fn get_ray(&self, i: u32, j: u32, eye: f32) -> Option<Ray> {
         let pixel_center = self.init_pixel_loc
             + (i as f32) * self.delta_u
             + (j as f32) * self.delta_v
             + self.pixel_sample_square();
 
-	let ray_origin = self.camera_center + if self.defocus_angle <= 0.0 {
-	   Vec3::new() 
-	} else {
-	    self.sample_disc()
-	};
-	
-        Ray::construct(ray_origin, pixel_center - ray_origin, random())
+        let (ipd, convergence) = self.stereo.map_or((0.0, 1.0), |x| (x.ipd, x.convergence));
+        let eye_offset = eye * ipd / 2.0 * self.u;
+
+        let (ray_origin, direction) = match &self.projection {
+            Projection::Perspective => {
+                let ray_origin = self.camera_center + eye_offset + if self.defocus_angle <= 0.0 {
+                    Vec3::new()
+                } else {
+                    self.sample_aperture(i, j)?
+                };
+
+                // Shift the image, rather than turning the eye, so that both
+                // views line up at the convergence distance
+                let target = pixel_center + (1.0 - self.focus_distance / convergence) * eye_offset;
+                (ray_origin, target - ray_origin)
+            }
+            Projection::Orthographic { .. } => {
+                let direction = -self.focus_distance * self.w;
+                (pixel_center + eye_offset - direction, direction)
+            }
+            &Projection::Fisheye { fov, mapping } => {
+                // Coordinates on the image, with the image circle fitting the width
+                let (x, y) = self.image_coordinates(i, j);
+                let (x, y) = (2.0 * x - 1.0, (1.0 - 2.0 * y) * self.image_height as f32 / self.render_width as f32);
+                let r = (x * x + y * y).sqrt();
+                if r > 1.0 {
+                    return None;
+                }
+
+                let half_fov = fov.to_radians() / 2.0;
+                let theta = match mapping {
+                    FisheyeMapping::Equidistant => r * half_fov,
+                    FisheyeMapping::Equisolid => 2.0 * (r * (half_fov / 2.0).sin()).asin(),
+                };
+                let phi = y.atan2(x);
+
+                self.converge(self.direction_from(theta, phi), eye_offset, convergence)
+            }
+            Projection::Equirectangular => {
+                let (x, y) = self.image_coordinates(i, j);
+                let longitude = 2.0 * std::f32::consts::PI * (x - 0.5);
+
+                // For omni-directional stereo, the eyes turn with the direction of
+                // view, staying on a circle of diameter ipd.
+                let right = longitude.cos() * self.u + longitude.sin() * self.w;
+                self.converge(self.panorama_direction(x, y), eye * ipd / 2.0 * right, convergence)
+            }
+            Projection::Realistic(lens) => {
+                // The lens flips the image, so the top left of the image is at the bottom right of the film
+                let (x, y) = self.image_coordinates(i, j);
+                let (half_width, half_height) = lens.film_extent(self.render_width as f32 / self.image_height as f32);
+                let (origin, direction) = lens.sample_ray((1.0 - 2.0 * x) * half_width, (2.0 * y - 1.0) * half_height)?;
+
+                let to_world = |p: Vec3| p.x * self.u + p.y * self.v - p.z * self.w;
+                (self.camera_center + eye_offset + to_world(origin), to_world(direction))
+            }
+        };
+
+        let mut ray = Ray::construct(ray_origin, direction, self.sample_time());
+        if self.spectral {
+            ray.wavelengths = Some(spectrum::sample_wavelengths(random()));
+        }
+
+        Some(self.apply_motion(ray))
     }
 
-    /// Renders the given world using the given camera settings.
-    pub fn render(&self, world: &World) {
+    /// Returns the origin and direction of a ray from an eye at ``eye_offset`` from the
+    /// camera, towards the point at the convergence distance along ``direction``.
+    fn converge(&self, direction: Vec3, eye_offset: Vec3, convergence: f32) -> (Point3, Vec3) {
+        if eye_offset.near_zero() {
+            return (self.camera_center, direction);
+        }
+
+        (self.camera_center + eye_offset, convergence * direction.unit() - eye_offset)
+    }
+
+    /// Samples a point in the given pixel, as fractions of the image width and height
+    fn image_coordinates(&self, i: u32, j: u32) -> (f32, f32) {
+        (
+            (i as f32 + random()) / self.render_width as f32,
+            (j as f32 + random()) / self.image_height as f32,
+        )
+    }
+
+    /// Returns the direction at an angle ``theta`` away from the viewing direction,
+    /// rotated by ``phi`` anticlockwise from the right of the image.
+    fn direction_from(&self, theta: f32, phi: f32) -> Vec3 {
+        theta.sin() * (phi.cos() * self.u + phi.sin() * self.v) - theta.cos() * self.w
+    }
+
+    /// Returns the direction through a point on an equirectangular panorama.
+    /// The centre of the image looks towards ``lookat``.
+    fn panorama_direction(&self, x: f32, y: f32) -> Vec3 {
+        let longitude = 2.0 * std::f32::consts::PI * (x - 0.5);
+        let latitude = std::f32::consts::PI * (0.5 - y);
+
+        latitude.cos() * (longitude.sin() * self.u - longitude.cos() * self.w) + latitude.sin() * self.v
+    }
+
+    /// Samples a ray through the given pixel of the output image.
+    /// Returns ``None`` if the ray is blocked by vignetting.
+    pub fn pixel_ray(&self, i: u32, j: u32) -> Option<Ray> {
+        let (eye, i, j) = self.eye_pixel(i, j);
+        self.get_ray(i, j, eye)
+    }
+
+    /// Traces a ray through the given pixel of the output image, and returns its colour in RGB.
+    /// Light found for other pixels (by the bidirectional integrator) is added to ``splats``.
+    fn sample_pixel(&self, i: u32, j: u32, scene: &Scene, state: &RenderState) -> Colour {
+        let Some(ray) = self.pixel_ray(i, j) else {
+            return Vec3::new();
+        };
+        let to_rgb = |colour: Colour| match ray.wavelengths {
+            Some(wavelengths) => spectrum::to_rgb(colour, wavelengths),
+            None => colour,
+        };
+
+        let colour = match self.integrator {
+            Integrator::Bidirectional if self.supports_light_tracing() => {
+                bdpt::trace(self, &ray, scene, &state.lights, &mut |(x, y), colour| {
+                    state.splats.lock().unwrap()[(y * self.render_width + x) as usize] += to_rgb(colour);
+                })
+            }
+            Integrator::PhotonMapping(_) => match &state.photons {
+                Some(maps) => photon::trace(&ray, scene, maps, self.max_depth),
+                None => Vec3::new(),
+            },
+            _ => ray_colour(&ray, scene, self.max_depth, &self.path_depth),
+        };
+
+        to_rgb(colour)
+    }
+
+    /// Returns the camera for a frame of an animation, at ``fps`` frames per second.
+    /// The shutter is moved to the time of the frame, and the animated parameters are
+    /// set to their values when the shutter opens. If the camera moves, it is also
+    /// given the motion up to when the shutter closes. The animated camera is checked like a
+    /// built one (Refer to [CameraBuilder::build()]).
+    pub fn frame(&self, animation: &CameraAnimation, frame: u32, fps: f32) -> Result<Camera, CameraError> {
+        if !positive(fps) {
+            return Err(CameraError::FrameRate(fps));
+        }
+
+        let mut camera = self.clone();
+        camera.shutter_open = (frame as f32 + self.shutter_open) / fps;
+        camera.shutter_close = (frame as f32 + self.shutter_close) / fps;
+
+        let (open, close) = (camera.shutter_open, camera.shutter_close);
+        let at = |curve: &Option<Curve<f32>>, value: f32| curve.as_ref().map_or(value, |x| x.at(open));
+
+        camera.vfov = at(&animation.vfov, self.vfov);
+        camera.defocus_angle = at(&animation.defocus_angle, self.defocus_angle);
+        camera.focus_distance = at(&animation.focus_distance, self.focus_distance);
+
+        if animation.lookfrom.is_some() || animation.lookat.is_some() {
+            let lookfrom = |time| animation.lookfrom.as_ref().map_or(self.lookfrom, |x| x.at(time));
+            let lookat = |time| animation.lookat.as_ref().map_or(self.lookat, |x| x.at(time));
+
+            camera.lookfrom = lookfrom(open);
+            camera.lookat = lookat(open);
+            camera.camera_motion = Some((lookfrom(close), lookat(close)));
+        }
+
+        camera.focus_lens().checked()
+    }
+
+    /// Renders the given scene using the given camera settings, to stdout.
+    pub fn render(&self, scene: &Scene) {
+        self.render_to(scene, &mut io::stdout().lock())
+            .expect("Failed to render the image");
+    }
+
+    /// Renders the given scene using the given camera settings, writing a PPM image to ``out``.
+    pub fn render_to(&self, scene: &Scene, out: &mut impl Write) -> io::Result<()> {
         self.debug();
 
-        println!("P3");
-        println!("{} {}", self.image_width, self.image_height);
-        println!("255");
+        if self.integrator == Integrator::Bidirectional && !self.supports_light_tracing() {
+            eprintln!("The bidirectional integrator needs a plain perspective camera, falling back to path tracing");
+        }
+        if self.progressive.is_some() && matches!(self.integrator, Integrator::ProgressivePhotonMapping(_) | Integrator::Metropolis(_)) {
+            eprintln!("Progressive photon mapping and Metropolis light transport render in passes of their own, ignoring the progressive settings");
+        }
+        if matches!(self.integrator, Integrator::PhotonMapping(_) | Integrator::ProgressivePhotonMapping(_))
+            && scene.all_lights().any(|x| x.bounds().is_none())
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "photon mapping cannot trace photons from lights infinitely far away, like the sky or the environment",
+            ));
+        }
+
+        // Progressive renders are always seeded, so that they can be resumed
+        let resumed = match (&self.progressive, &self.checkpoint) {
+            (Some(Progressive { resume: true, .. }), Some(checkpoint)) => Some(checkpoint.clone()),
+            (Some(Progressive { checkpoint: Some(path), resume: true, .. }), None) => Some(Arc::new(Checkpoint::load(path)?)),
+            _ => None,
+        };
+        let seed = match (&self.progressive, &resumed) {
+            (_, Some(checkpoint)) => Some(checkpoint.seed),
+            (Some(_), None) => Some(self.seed.unwrap_or_else(rand::random)),
+            (None, None) => self.seed,
+        };
+        let features = self.denoise.map(|x| Features::gather(self, scene, x.feature_samples, seed));
+
+        let lights = PowerLightSampler::new(&scene.all_lights().collect::<Vec<_>>());
+        let (pixels, variance) = match (self.integrator, &self.progressive, seed) {
+            (Integrator::ProgressivePhotonMapping(settings), _, _) => (photon::render_progressive(self, scene, &lights, &settings, seed), None),
+            (Integrator::Metropolis(settings), _, _) => (mlt::render(self, scene, &settings), None),
+            (_, Some(progressive), Some(seed)) => {
+                let (pixels, variance) = self.render_progressive(scene, lights, progressive, seed, resumed, features.as_ref())?;
+                (pixels, Some(variance))
+            }
+            _ => {
+                let (pixels, variance) = self.render_samples(scene, lights)?;
+                (pixels, Some(variance))
+            }
+        };
+
+        let (width, height) = self.written_size();
+        write_ppm(out, width, height, self.crop(self.denoised(pixels, variance.as_deref(), features.as_ref())))?;
+
+        eprintln!("Done.");
+
+        Ok(())
+    }
+
+    /// Gathers what is shared by every sample of a render, building the photon maps if needed.
+    fn render_state(&self, scene: &Scene, lights: PowerLightSampler, seed: Option<u64>) -> RenderState {
+        let (width, height) = self.output_size();
+        let photons = match self.integrator {
+            Integrator::PhotonMapping(settings) => Some(PhotonMaps::build(scene, &lights, self, settings, seed)),
+            _ => None,
+        };
+
+        RenderState {
+            lights,
+            splats: Mutex::new(vec![Vec3::new(); (width * height) as usize]),
+            photons,
+            seed,
+        }
+    }
+
+    /// Traces ``count`` rays through the given pixel of the output image, following the
+    /// ``taken`` rays traced through it before, and returns their sums. The sums are made in
+    /// order, so that seeded samples always add up the same.
+    fn sample_stats(&self, i: u32, j: u32, taken: u32, count: u32, scene: &Scene, state: &RenderState) -> PixelStats {
+        let pixel = (j * self.output_size().0 + i) as u64;
+
+        (taken..taken + count)
+            .into_par_iter()
+            .map(|sample| {
+                let seed = state.seed.map(|x| mix_seed(&[x, pixel, sample as u64]));
+                let colour = with_seed(seed, || self.sample_pixel(i, j, scene, state));
+                let luminance = spectrum::luminance(colour) as f64;
+                PixelStats { colour, luminance, luminance_sq: luminance * luminance, count: 1 }
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .fold(PixelStats::default(), PixelStats::add)
+    }
+
+    /// Whether adaptive sampling lets a pixel stop taking samples.
+    fn converged(&self, stats: &PixelStats) -> bool {
+        self.adaptive.as_ref().is_some_and(|x| {
+            stats.count >= x.min_samples.max(2) && stats.relative_error() < x.threshold as f64
+        })
+    }
+
+    /// Returns the average colours of the pixels in RGB, with the light splatted onto them.
+    fn resolve(&self, stats: &[PixelStats], state: &RenderState) -> Vec<Colour> {
+        // Every sample traced one path from the lights, which could land anywhere on the image
+        let total: u64 = stats.iter().map(|x| x.count as u64).sum();
+        let samples = total.max(1) as f32 / stats.len().max(1) as f32;
+        let splats = state.splats.lock().unwrap();
+
+        stats
+            .iter()
+            .zip(splats.iter())
+            .map(|(x, splat)| match x.count {
+                // Pixels outside the region are left black
+                0 => Vec3::new(),
+                count => x.colour / count as f32 + *splat / samples,
+            })
+            .collect()
+    }
+
+    /// Runs the denoiser over the pixels, if the camera has one (Refer to [denoise::denoise()]).
+    /// ``variance`` is the variance of the mean luminance of every pixel, if it is known.
+    /// Only the region that is rendered is denoised, so that the black pixels around it do not
+    /// bleed into its edges.
+    fn denoised(&self, mut pixels: Vec<Colour>, variance: Option<&[f32]>, features: Option<&Features>) -> Vec<Colour> {
+        let (Some(settings), Some(features)) = (&self.denoise, features) else {
+            return pixels;
+        };
 
-        for j in 0..self.image_height {
-            eprint!("\rNumber of lines remaining: {}", self.image_height - j);
+        let (x0, y0, x1, y1) = self.region_bounds();
+        let variance = variance.map(|x| self.in_region_only(x));
+        let denoised = denoise::denoise(&self.in_region_only(&pixels), variance.as_deref(), features, x1 - x0, y1 - y0, settings);
 
-            for i in 0..self.image_width {
-                let pixel: Vec3 = (0..self.samples_per_pixel)
-                    .into_par_iter()
-                    .map(|_| ray_colour(&self.get_ray(i, j), world, self.max_depth))
-                    .reduce(|| Vec3::new(), |sum, x| sum + x)
-		    / self.samples_per_pixel as f32;
+        let width = self.output_size().0;
+        for (k, colour) in denoised.into_iter().enumerate() {
+            let (i, j) = (x0 + k as u32 % (x1 - x0), y0 + k as u32 / (x1 - x0));
+            pixels[(j * width + i) as usize] = colour;
+        }
+        pixels
+    }
 
-                println!("{}", pixel);
+    /// Reports the number of samples taken, and writes the heat map of adaptive sampling.
+    fn finish_adaptive(&self, stats: &[PixelStats]) -> io::Result<()> {
+        let Some(adaptive) = &self.adaptive else {
+            return Ok(());
+        };
+
+        let (x0, y0, x1, y1) = self.region_bounds();
+        let total: u64 = stats.iter().map(|x| x.count as u64).sum();
+        eprintln!("Average samples per pixel: {:.1}", total as f64 / ((x1 - x0) * (y1 - y0)) as f64);
+
+        match &adaptive.heat_map {
+            Some(path) => {
+                let (width, height) = self.written_size();
+                let counts = self.crop(stats.iter().map(|x| x.count).collect());
+                write_heat_map(path, &counts, width, height, self.samples_per_pixel.max(1))
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Traces ``samples_per_pixel`` rays through every pixel of the output image, or fewer
+    /// with adaptive sampling, and returns their average colours in RGB, with the variances
+    /// of their luminance.
+    fn render_samples(&self, scene: &Scene, lights: PowerLightSampler) -> io::Result<(Vec<Colour>, Vec<f32>)> {
+        let (width, height) = self.output_size();
+        let state = self.render_state(scene, lights, self.seed);
+        let max_samples = self.samples_per_pixel.max(1);
+        // At least two samples are needed to estimate the error, unless only one is allowed
+        let (min_samples, batch) = match &self.adaptive {
+            Some(adaptive) => (adaptive.min_samples.max(2).min(max_samples), adaptive.batch.max(1)),
+            None => (max_samples, max_samples),
+        };
+        let mut stats = Vec::with_capacity((width * height) as usize);
+
+        for j in 0..height {
+            eprint!("\rNumber of lines remaining: {}", height - j);
+
+            for i in 0..width {
+                if !self.in_region(i, j) {
+                    stats.push(PixelStats::default());
+                    continue;
+                }
+
+                let mut pixel = self.sample_stats(i, j, 0, min_samples, scene, &state);
+
+                while pixel.count < max_samples && !self.converged(&pixel) {
+                    let count = batch.min(max_samples - pixel.count);
+                    pixel = pixel.add(self.sample_stats(i, j, pixel.count, count, scene, &state));
+                }
+
+                stats.push(pixel);
             }
         }
 
         eprintln!();
-        eprintln!("Done.");
+
+        self.finish_adaptive(&stats)?;
+        Ok((self.resolve(&stats, &state), variances(&stats)))
+    }
+
+    /// Renders the whole image in passes, each doubling the samples taken so far, until
+    /// ``samples_per_pixel`` is reached or a limit of ``progressive`` is met. The image
+    /// rendered so far is saved between passes (denoised with ``features``, if the camera has
+    /// a denoiser), with a checkpoint if asked (Refer to [Progressive]). Every sample takes its
+    /// random numbers from ``seed``, which is the seed of the ``resumed`` checkpoint when resuming.
+    fn render_progressive(
+        &self,
+        scene: &Scene,
+        lights: PowerLightSampler,
+        progressive: &Progressive,
+        seed: u64,
+        resumed: Option<Arc<Checkpoint>>,
+        features: Option<&Features>,
+    ) -> io::Result<(Vec<Colour>, Vec<f32>)> {
+        let (width, height) = self.output_size();
+        let max_samples = self.samples_per_pixel.max(1);
+        let settings = self.checkpoint_settings();
+
+        let state = self.render_state(scene, lights, Some(seed));
+        let (mut stats, mut samples, mut pass) = match resumed {
+            Some(checkpoint) => {
+                checkpoint.check(width, height, &settings)?;
+                eprintln!("Resuming from pass {}, at {} samples per pixel", checkpoint.pass, checkpoint.samples);
+
+                let checkpoint = Arc::unwrap_or_clone(checkpoint);
+                *state.splats.lock().unwrap() = checkpoint.splats;
+                (checkpoint.stats, checkpoint.samples, checkpoint.pass)
+            }
+            None => (vec![PixelStats::default(); (width * height) as usize], 0, 0),
+        };
+
+        let save = |stats: &[PixelStats], samples: u32, pass: u32| -> io::Result<()> {
+            if let Some(path) = &progressive.checkpoint {
+                let splats = state.splats.lock().unwrap().clone();
+                let settings = settings.clone();
+                Checkpoint { width, height, seed, settings, pass, samples, stats: stats.to_vec(), splats }.save(path)?;
+            }
+            let pixels = self.denoised(self.resolve(stats, &state), Some(&variances(stats)), features);
+            let (width, height) = self.written_size();
+            save_ppm(&progressive.output, width, height, self.crop(pixels))
+        };
+
+        let (x0, y0, x1, y1) = self.region_bounds();
+        let region_size = (x1 - x0) * (y1 - y0);
+
+        let start = Instant::now();
+        let mut last_save = start;
+        let first_samples = samples;
+
+        while samples < max_samples {
+            let elapsed = start.elapsed().as_secs_f32();
+            if progressive.time_limit.is_some_and(|x| elapsed >= x) {
+                eprint!("\nTime limit reached");
+                break;
+            }
+
+            // Passes are kept short enough to meet the time limit and the next save, going by
+            // how long the samples taken so far took. With a checkpoint, they keep to their
+            // sizes instead, so that a resumed render passes over the same samples (and stops
+            // its pixels at the same counts) as an uninterrupted one, however fast each ran.
+            let mut count = samples.max(1).min(max_samples - samples);
+            if samples > first_samples && progressive.checkpoint.is_none() {
+                let rate = (samples - first_samples) as f32 / elapsed.max(1e-3);
+                let until_save = progressive.save_seconds.map(|x| x - last_save.elapsed().as_secs_f32());
+                let until_limit = progressive.time_limit.map(|x| x - elapsed);
+                if let Some(seconds) = until_save.into_iter().chain(until_limit).reduce(f32::min) {
+                    count = count.min((seconds * rate) as u32).max(1);
+                }
+            }
+
+            stats.par_iter_mut().enumerate().for_each(|(k, pixel)| {
+                let (i, j) = (k as u32 % width, k as u32 / width);
+                if self.in_region(i, j) && !self.converged(pixel) {
+                    *pixel = pixel.add(self.sample_stats(i, j, pixel.count, count, scene, &state));
+                }
+            });
+            samples += count;
+            pass += 1;
+
+            let noise = stats
+                .iter()
+                .enumerate()
+                .filter(|(k, _)| self.in_region(*k as u32 % width, *k as u32 / width))
+                .map(|(_, x)| x.relative_error().min(1.0))
+                .sum::<f64>()
+                / region_size as f64;
+            eprint!("\rPass {}: {} samples per pixel, noise {:.4}   ", pass, samples, noise);
+
+            if progressive.noise.is_some_and(|x| noise < x as f64) {
+                eprint!("\nNoise target reached");
+                break;
+            }
+
+            let due = progressive.save_passes.is_some_and(|x| pass % x.max(1) == 0)
+                || progressive.save_seconds.is_some_and(|x| last_save.elapsed().as_secs_f32() >= x);
+            if due && samples < max_samples {
+                save(&stats, samples, pass)?;
+                last_save = Instant::now();
+            }
+        }
+
+        eprintln!();
+
+        self.finish_adaptive(&stats)?;
+        save(&stats, samples, pass)?;
+        Ok((self.resolve(&stats, &state), variances(&stats)))
     }
 
     /// Prints debug information to stderr. 
     pub fn debug(&self) {
         eprintln!("Image properties:");
         eprintln!("\tHeight: {}", self.image_height);
-        eprintln!("\tWidth: {}", self.image_width);
+        eprintln!("\tWidth: {}", self.render_width);
         eprintln!("\tAspect Ratio: {}", self.aspect_ratio);
 
         eprintln!("Debug Info:");
@@ -194,27 +1792,255 @@ impl Camera {
 }
 
 
-/// Finds the colour of the ray.
-/// This is done by calculating the colour of the ray until it is undetectable
-/// (i.e. detected as black) by the camera.
-fn ray_colour(r: &Ray, world: &World, depth: u32) -> Colour {
-    if depth == 0 {
-        return Vec3::new();
+/// Variances of the mean luminance of the pixels
+fn variances(stats: &[PixelStats]) -> Vec<f32> {
+    stats.iter().map(|x| x.variance() as f32).collect()
+}
+
+/// Writes the given pixels (in RGB) as a PPM image, gamma corrected.
+fn write_ppm(out: &mut impl Write, width: u32, height: u32, pixels: impl IntoIterator<Item = Colour>) -> io::Result<()> {
+    writeln!(out, "P3")?;
+    writeln!(out, "{} {}", width, height)?;
+    writeln!(out, "255")?;
+
+    for pixel in pixels {
+        writeln!(out, "{}", pixel)?;
     }
 
-    match world.hit(r, 0.001..INFINITY) {
-        Some(t) => {
-            if let Some(x) = t.material.scatter(r, &t) {
-                x.attenuation * ray_colour(&x.scattered, world, depth - 1)
-            } else {
-                Vec3::new()
+    Ok(())
+}
+
+/// Writes the given pixels as a PPM image to ``path``. The image is written next to it first,
+/// and then moved in place, so that the file is never seen half written.
+fn save_ppm(path: &Path, width: u32, height: u32, pixels: impl IntoIterator<Item = Colour>) -> io::Result<()> {
+    let partial = path.with_extension("partial");
+    let mut out = BufWriter::new(File::create(&partial)?);
+    write_ppm(&mut out, width, height, pixels)?;
+    out.into_inner()?.sync_all()?;
+    fs::rename(partial, path)
+}
+
+/// Writes a PPM image of the number of samples taken by every pixel, shading pixels from blue
+/// (no samples) through green to red (``max_samples``).
+fn write_heat_map(path: &Path, counts: &[u32], width: u32, height: u32, max_samples: u32) -> io::Result<()> {
+    let colours = counts.iter().map(|&count| {
+        let t = count as f32 / max_samples as f32;
+        let colour = Vec3::from_point((2.0 * t - 1.0).max(0.0), 1.0 - (2.0 * t - 1.0).abs(), (1.0 - 2.0 * t).max(0.0));
+        // Squared, as the output is gamma corrected when written
+        colour * colour * 0.999
+    });
+
+    save_ppm(path, width, height, colours)
+}
+
+/// Finds the colour of the ray, by following it through the scene for at most ``max_depth``
+/// hits, carrying the fraction of light that makes it back along the path (its throughput).
+/// At every hit, a light of the scene is also sampled directly (Refer to [direct_light()]).
+/// Paths are also ended by the limits of ``depth`` (Refer to [PathDepth]).
+pub fn ray_colour(r: &Ray, scene: &Scene, max_depth: u32, depth: &PathDepth) -> Colour {
+    let mut colour = Vec3::new();
+    let mut throughput = Vec3::from_point(1.0, 1.0, 1.0);
+    let mut ray = *r;
+    // Density with which the ray was scattered, or 0 if it could not have been found by
+    // sampling lights (like camera rays, and reflections off mirrors)
+    let mut pdf = 0.0;
+    let (mut diffuse, mut glossy, mut transmission) = (0, 0, 0);
+
+    for bounce in 0..max_depth {
+        let Some(rec) = scene.world.hit(&ray, 0.001..f32::INFINITY) else {
+            colour += throughput * escaped_light(&ray, scene, pdf);
+            break;
+        };
+
+        colour += throughput * (emitted_light(&ray, &rec, scene, pdf) + direct_light(&ray, &rec, scene, true));
+
+        let Some(x) = rec.material.scatter(&ray, &rec) else {
+            break;
+        };
+
+        let (count, limit) = match x.lobe {
+            Lobe::Diffuse => (&mut diffuse, depth.diffuse),
+            Lobe::Glossy => (&mut glossy, depth.glossy),
+            Lobe::Transmission => (&mut transmission, depth.transmission),
+        };
+        *count += 1;
+        if *count > limit {
+            break;
+        }
+
+        throughput = throughput * x.attenuation;
+
+        if bounce + 1 >= depth.roulette {
+            let survival = throughput.x.max(throughput.y).max(throughput.z).min(1.0);
+            if survival <= 0.0 || random() >= survival {
+                break;
             }
+            throughput /= survival;
         }
+
+        pdf = x.pdf;
+        ray = x.scattered;
+    }
+
+    colour
+}
+
+/// Light arriving along a ray that escapes the scene, weighed against sampling the environment
+/// directly at the hit the ray left (where it was scattered with density ``pdf``).
+fn escaped_light(r: &Ray, scene: &Scene, pdf: f32) -> Colour {
+    match &scene.environment {
+        Some(environment) if pdf > 0.0 => {
+            let light_pdf = scene.light_pdf(r.origin, scene.environment_index(), r.direction);
+            environment.radiance(r) * power_heuristic(pdf, light_pdf)
+        }
+        _ => background(r, scene),
+    }
+}
+
+/// Light arriving along a ray that escapes the scene: the environment, or a plain sky gradient
+pub fn background(r: &Ray, scene: &Scene) -> Colour {
+    match &scene.environment {
+        Some(environment) => environment.radiance(r),
         None => {
             let dir = r.direction.unit();
             let a = 0.5 * (dir.y + 1.0);
 
-            (1.0 - a) * Vec3::from_point(1.0, 1.0, 1.0) + a * Vec3::from_point(0.5, 0.7, 1.0)
+            r.sample_colour((1.0 - a) * Vec3::from_point(1.0, 1.0, 1.0) + a * Vec3::from_point(0.5, 0.7, 1.0))
         }
     }
 }
+
+/// Light given off by the surface that was hit. Surfaces that are also sampled as a light
+/// are weighed against sampling them at the previous hit.
+fn emitted_light(r: &Ray, rec: &HitRecord, scene: &Scene, pdf: f32) -> Colour {
+    let emitted = rec.material.emitted(r, rec);
+
+    match rec.material.light() {
+        Some(index) if pdf > 0.0 && !emitted.near_zero() => {
+            emitted * power_heuristic(pdf, scene.light_pdf(r.origin, index, r.direction))
+        }
+        _ => emitted,
+    }
+}
+
+/// Samples the light arriving directly from one light in the scene, reflected by the
+/// material towards the ray. The light is chosen by the scene (Refer to
+/// [crate::light_sampler]), and blocked lights are found with a shadow ray. With ``mis``, lights
+/// that rays can hit are weighed against finding them by scattering, which the caller must do.
+pub fn direct_light(r: &Ray, rec: &HitRecord, scene: &Scene, mis: bool) -> Colour {
+    let Some((index, pmf)) = scene.sample_light(rec.point, random()) else {
+        return Vec3::new();
+    };
+    let Some(sample) = scene.light(index).and_then(|light| light.sample(rec.point, r)) else {
+        return Vec3::new();
+    };
+
+    let mut reflected = rec.material.eval(r, rec, sample.direction);
+    if reflected.near_zero() {
+        return Vec3::new();
+    }
+
+    // Lights that rays can hit are weighed against finding them by scattering
+    if mis && sample.pdf > 0.0 {
+        reflected *= power_heuristic(pmf * sample.pdf, rec.material.pdf(r, rec, sample.direction));
+    }
+
+    let shadow = r.spawn(rec.point, sample.direction);
+    if scene.world.hit(&shadow, 0.001..sample.distance * (1.0 - 1e-4)).is_some() {
+        return Vec3::new();
+    }
+
+    reflected * sample.radiance / pmf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fraction of the exposure of a trapezoid shutter made by the time ``t``
+    fn exposure(ramp: f32, t: f32) -> f32 {
+        let area = if t < ramp {
+            t * t / (2.0 * ramp)
+        } else if t < 1.0 - ramp {
+            t - ramp / 2.0
+        } else {
+            1.0 - ramp - (1.0 - t) * (1.0 - t) / (2.0 * ramp)
+        };
+        area / (1.0 - ramp)
+    }
+
+    #[test]
+    fn box_shutter_is_uniform() {
+        for k in 0..=10 {
+            let u = k as f32 / 10.0;
+            assert_eq!(ShutterCurve::Box.sample(u), u);
+        }
+    }
+
+    #[test]
+    fn shutter_curves_invert_their_exposure() {
+        for (curve, ramp) in [(ShutterCurve::Triangle, 0.5), (ShutterCurve::Trapezoid { ramp: 0.2 }, 0.2)] {
+            let mut last = 0.0;
+            for k in 0..=100 {
+                let u = k as f32 / 100.0;
+                let t = curve.sample(u);
+
+                assert!((0.0..=1.0).contains(&t) && t >= last, "{:?} at {}: {}", curve, u, t);
+                assert!((exposure(ramp, t) - u).abs() < 1e-4, "{:?} at {}: {}", curve, u, t);
+                last = t;
+            }
+        }
+    }
+
+    #[test]
+    fn flat_trapezoid_is_a_box() {
+        assert_eq!(ShutterCurve::Trapezoid { ramp: 0.0 }.sample(0.3), 0.3);
+    }
+
+    /// A camera rendering a 100x50 image
+    fn camera() -> Camera {
+        Camera::builder().with_image_width(100).with_aspect_ratio(2.0).build().unwrap()
+    }
+
+    #[test]
+    fn region_bounds_cover_the_image_without_a_region() {
+        assert_eq!(camera().region_bounds(), (0, 0, 100, 50));
+    }
+
+    #[test]
+    fn region_bounds_of_pixels_and_borders() {
+        let pixels = Region { bounds: RegionBounds::Pixels { x0: 10, y0: 5, x1: 30, y1: 50 }, full_canvas: false };
+        let camera = camera().with_region(pixels).unwrap();
+        assert_eq!(camera.region_bounds(), (10, 5, 30, 50));
+        assert_eq!(camera.written_size(), (20, 45));
+
+        let border = Region { bounds: RegionBounds::Border { x0: 0.25, y0: 0.2, x1: 0.75, y1: 1.0 }, full_canvas: true };
+        let camera = camera.with_region(border).unwrap();
+        assert_eq!(camera.region_bounds(), (25, 10, 75, 50));
+        assert_eq!(camera.written_size(), (100, 50));
+
+        // Borders keep to the same part of the image at any resolution
+        let camera = camera.with_resolution(50.0).unwrap();
+        assert_eq!(camera.region_bounds(), (13, 5, 38, 25));
+    }
+
+    #[test]
+    fn invalid_regions_are_rejected() {
+        let regions = [
+            RegionBounds::Pixels { x0: 30, y0: 5, x1: 10, y1: 50 },
+            RegionBounds::Pixels { x0: 10, y0: 5, x1: 10, y1: 50 },
+            RegionBounds::Pixels { x0: 10, y0: 5, x1: 30, y1: 51 },
+            RegionBounds::Border { x0: -0.1, y0: 0.0, x1: 0.5, y1: 0.5 },
+            RegionBounds::Border { x0: 0.0, y0: 0.0, x1: 0.5, y1: f32::NAN },
+            RegionBounds::Border { x0: 0.5, y0: 0.5, x1: 0.501, y1: 0.6 },
+        ];
+        for bounds in regions {
+            let region = Region { bounds, full_canvas: false };
+            assert!(matches!(camera().with_region(region), Err(CameraError::Region { .. })), "{:?} was not rejected", bounds);
+        }
+
+        // A region that fits may no longer fit once the image shrinks
+        let region = Region { bounds: RegionBounds::Pixels { x0: 0, y0: 0, x1: 80, y1: 40 }, full_canvas: false };
+        assert!(camera().with_region(region).unwrap().with_resolution(50.0).is_err());
+    }
+}
@@ -0,0 +1,321 @@
+//! Checkpoints of progressive renders, saved between passes so that a render that is stopped
+//! can be resumed from its last checkpoint (Refer to [crate::camera::Progressive]).
+//!
+//! Every sample takes its random numbers from a generator seeded with the seed of the render
+//! and the indices of its pixel and of the sample in it, so the state of the random numbers is
+//! the seed and the number of samples taken in every pixel. The features of the denoiser are
+//! gathered from the same seed. With a checkpoint, passes take a fixed number of samples
+//! whatever the time limit and the time between saves (which are only checked between passes),
+//! so pixels stop at the same counts and their sums are made in the same batches. A resumed
+//! render then makes the same samples an uninterrupted one would, and adds them up the same.
+//!
+//! Light splatted by the bidirectional integrator is the exception: it is added up in whatever
+//! order the threads get to it, so it can differ in the last bits between two renders.
+//!
+//! The settings that change the samples (Refer to [CheckpointSettings]) are saved too, and a
+//! render is only resumed with the settings it was started with.
+
+use crate::camera::PixelStats;
+use crate::vec3::Vec3;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Result, Seek, Write};
+use std::path::Path;
+
+/// Marks the start of a checkpoint file, with the version of its layout
+const MAGIC: &[u8; 8] = b"RTCKPT02";
+
+/// Bytes taken by every pixel: its [PixelStats], and its splat
+const PIXEL_BYTES: u64 = 12 + 8 + 8 + 4 + 12;
+
+/// Longest name of an integrator that is read back, well past that of any integrator
+const MAX_INTEGRATOR_LEN: usize = 1024;
+
+/// The settings a progressive render is resumed with, which must be those it was started with
+/// for the samples to carry on where they stopped.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CheckpointSettings {
+    /// Description of the integrator, with its settings
+    pub integrator: String,
+    pub spectral: bool,
+    /// Number of samples per pixel the render stops at
+    pub samples_per_pixel: u32,
+    /// Threshold, least samples, and samples per batch of adaptive sampling
+    pub adaptive: Option<(f32, u32, u32)>,
+    /// Corners of the region of the output image that is rendered
+    pub region: (u32, u32, u32, u32),
+}
+
+impl CheckpointSettings {
+    /// Describes the first setting that differs from ``other``, which the render was started with
+    fn mismatch(&self, other: &CheckpointSettings) -> Option<String> {
+        if self.integrator != other.integrator {
+            return Some(format!("the integrator {}, not {}", other.integrator, self.integrator));
+        }
+        if self.spectral != other.spectral {
+            let mode = |spectral| if spectral { "spectral" } else { "RGB" };
+            return Some(format!("{} colour, not {}", mode(other.spectral), mode(self.spectral)));
+        }
+        if self.samples_per_pixel != other.samples_per_pixel {
+            return Some(format!("{} samples per pixel, not {}", other.samples_per_pixel, self.samples_per_pixel));
+        }
+        if self.adaptive != other.adaptive {
+            return Some(format!("the adaptive sampling {:?}, not {:?}", other.adaptive, self.adaptive));
+        }
+        if self.region != other.region {
+            return Some(format!("the region {:?}, not {:?}", other.region, self.region));
+        }
+        None
+    }
+}
+
+/// The state of a progressive render after a pass.
+#[derive(Debug, Clone)]
+pub struct Checkpoint {
+    pub width: u32,
+    pub height: u32,
+    /// Seed the random numbers of every sample are drawn from
+    pub seed: u64,
+    pub settings: CheckpointSettings,
+    /// Number of passes made
+    pub pass: u32,
+    /// Number of samples per pixel taken by the passes (pixels that converged took fewer)
+    pub samples: u32,
+    /// Running sums of the samples of every pixel
+    pub stats: Vec<PixelStats>,
+    /// Light splatted onto every pixel by the bidirectional integrator
+    pub splats: Vec<Vec3>,
+}
+
+#[allow(dead_code)]
+impl Checkpoint {
+    /// Writes the checkpoint to ``path``. It is written next to it first, and then moved in
+    /// place, so that the last checkpoint is kept if the render is stopped while writing.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let partial = path.with_extension("partial");
+        let mut out = BufWriter::new(File::create(&partial)?);
+
+        out.write_all(MAGIC)?;
+        for value in [self.width, self.height] {
+            out.write_all(&value.to_le_bytes())?;
+        }
+        out.write_all(&self.seed.to_le_bytes())?;
+
+        let settings = &self.settings;
+        out.write_all(&(settings.integrator.len() as u32).to_le_bytes())?;
+        out.write_all(settings.integrator.as_bytes())?;
+        out.write_all(&[settings.spectral as u8])?;
+        out.write_all(&settings.samples_per_pixel.to_le_bytes())?;
+        let (threshold, min_samples, batch) = settings.adaptive.unwrap_or_default();
+        out.write_all(&[settings.adaptive.is_some() as u8])?;
+        out.write_all(&threshold.to_le_bytes())?;
+        let (x0, y0, x1, y1) = settings.region;
+        for value in [min_samples, batch, x0, y0, x1, y1] {
+            out.write_all(&value.to_le_bytes())?;
+        }
+
+        for value in [self.pass, self.samples] {
+            out.write_all(&value.to_le_bytes())?;
+        }
+
+        for stats in &self.stats {
+            write_vec3(&mut out, stats.colour)?;
+            out.write_all(&stats.luminance.to_le_bytes())?;
+            out.write_all(&stats.luminance_sq.to_le_bytes())?;
+            out.write_all(&stats.count.to_le_bytes())?;
+        }
+        for &splat in &self.splats {
+            write_vec3(&mut out, splat)?;
+        }
+
+        out.into_inner()?.sync_all()?;
+        fs::rename(partial, path)
+    }
+
+    /// Reads a checkpoint written by [Checkpoint::save()].
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = File::open(path)?;
+        let length = file.metadata()?.len();
+        let mut input = BufReader::new(file);
+
+        let magic: [u8; 8] = read_bytes(&mut input)?;
+        if &magic != MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "Not a checkpoint file, or one of an older version"));
+        }
+        let width = read_u32(&mut input)?;
+        let height = read_u32(&mut input)?;
+        let seed = u64::from_le_bytes(read_bytes(&mut input)?);
+        let settings = read_settings(&mut input)?;
+        let pass = read_u32(&mut input)?;
+        let samples = read_u32(&mut input)?;
+
+        // The size in the header is checked against the file before anything is made that large
+        let count = width as u64 * height as u64;
+        if count.checked_mul(PIXEL_BYTES) != Some(length - input.stream_position()?) {
+            return Err(Error::new(ErrorKind::InvalidData, "The checkpoint is cut short, or not of the size in its header"));
+        }
+
+        let count = count as usize;
+        let mut stats = Vec::with_capacity(count);
+        for _ in 0..count {
+            stats.push(PixelStats {
+                colour: read_vec3(&mut input)?,
+                luminance: f64::from_le_bytes(read_bytes(&mut input)?),
+                luminance_sq: f64::from_le_bytes(read_bytes(&mut input)?),
+                count: read_u32(&mut input)?,
+            });
+        }
+        let splats = (0..count).map(|_| read_vec3(&mut input)).collect::<Result<_>>()?;
+
+        Ok(Self { width, height, seed, settings, pass, samples, stats, splats })
+    }
+
+    /// Checks that the render is resumed at the size and with the settings it was started with.
+    pub fn check(&self, width: u32, height: u32, settings: &CheckpointSettings) -> Result<()> {
+        let error = if (self.width, self.height) != (width, height) {
+            format!("The checkpoint is of a {}x{} image, not {}x{}", self.width, self.height, width, height)
+        } else if let Some(mismatch) = settings.mismatch(&self.settings) {
+            format!("The checkpoint was rendered with {}", mismatch)
+        } else {
+            return Ok(());
+        };
+        Err(Error::new(ErrorKind::InvalidData, error))
+    }
+}
+
+/// Reads the settings in the header of a checkpoint (Refer to [CheckpointSettings])
+fn read_settings(input: &mut impl Read) -> Result<CheckpointSettings> {
+    let length = read_u32(input)? as usize;
+    if length > MAX_INTEGRATOR_LEN {
+        return Err(Error::new(ErrorKind::InvalidData, "The integrator of the checkpoint is too long"));
+    }
+    let mut integrator = vec![0; length];
+    input.read_exact(&mut integrator)?;
+    let integrator = String::from_utf8(integrator).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+
+    let [spectral] = read_bytes(input)?;
+    let samples_per_pixel = read_u32(input)?;
+    let [adaptive] = read_bytes(input)?;
+    let threshold = f32::from_le_bytes(read_bytes(input)?);
+    let (min_samples, batch) = (read_u32(input)?, read_u32(input)?);
+    let region = (read_u32(input)?, read_u32(input)?, read_u32(input)?, read_u32(input)?);
+
+    Ok(CheckpointSettings {
+        integrator,
+        spectral: spectral != 0,
+        samples_per_pixel,
+        adaptive: (adaptive != 0).then_some((threshold, min_samples, batch)),
+        region,
+    })
+}
+
+fn read_bytes<const N: usize>(input: &mut impl Read) -> Result<[u8; N]> {
+    let mut bytes = [0; N];
+    input.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_u32(input: &mut impl Read) -> Result<u32> {
+    Ok(u32::from_le_bytes(read_bytes(input)?))
+}
+
+fn read_vec3(input: &mut impl Read) -> Result<Vec3> {
+    let x = f32::from_le_bytes(read_bytes(input)?);
+    let y = f32::from_le_bytes(read_bytes(input)?);
+    let z = f32::from_le_bytes(read_bytes(input)?);
+    Ok(Vec3::from_point(x, y, z))
+}
+
+fn write_vec3(out: &mut impl Write, value: Vec3) -> Result<()> {
+    for component in [value.x, value.y, value.z] {
+        out.write_all(&component.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> CheckpointSettings {
+        CheckpointSettings {
+            integrator: String::from("Path"),
+            spectral: true,
+            samples_per_pixel: 64,
+            adaptive: Some((0.05, 16, 8)),
+            region: (0, 1, 2, 3),
+        }
+    }
+
+    fn checkpoint() -> Checkpoint {
+        let (width, height) = (2, 3);
+        let stats = (0..width * height)
+            .map(|k| PixelStats {
+                colour: Vec3::from_point(k as f32, 0.5, -1.0),
+                luminance: k as f64 / 3.0,
+                luminance_sq: k as f64 * 7.0,
+                count: k + 1,
+            })
+            .collect();
+        let splats = (0..width * height).map(|k| Vec3::from_point(0.0, k as f32, 2.0)).collect();
+
+        Checkpoint { width, height, seed: 0x1234_5678_9abc, settings: settings(), pass: 4, samples: 15, stats, splats }
+    }
+
+    /// A file of its own for each test, as tests run at the same time
+    fn path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("raytracer-{}-{}.ckpt", name, std::process::id()))
+    }
+
+    #[test]
+    fn round_trip() {
+        let path = path("round-trip");
+        let saved = checkpoint();
+        saved.save(&path).unwrap();
+        let loaded = Checkpoint::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!((loaded.width, loaded.height, loaded.seed), (saved.width, saved.height, saved.seed));
+        assert_eq!((loaded.pass, loaded.samples), (saved.pass, saved.samples));
+        assert_eq!(loaded.settings, saved.settings);
+
+        let vec3 = |x: Vec3| [x.x, x.y, x.z];
+        for (a, b) in loaded.stats.iter().zip(&saved.stats) {
+            assert_eq!(vec3(a.colour), vec3(b.colour));
+            assert_eq!((a.luminance, a.luminance_sq, a.count), (b.luminance, b.luminance_sq, b.count));
+        }
+        let splats = |x: &Checkpoint| x.splats.iter().map(|&x| vec3(x)).collect::<Vec<_>>();
+        assert_eq!(splats(&loaded), splats(&saved));
+    }
+
+    #[test]
+    fn cut_short_is_rejected() {
+        let path = path("cut-short");
+        checkpoint().save(&path).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        fs::write(&path, &bytes[..bytes.len() - 4]).unwrap();
+        let loaded = Checkpoint::load(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn mismatches_are_rejected() {
+        let checkpoint = checkpoint();
+        assert!(checkpoint.check(2, 3, &settings()).is_ok());
+        assert!(checkpoint.check(3, 2, &settings()).is_err());
+
+        let changes: [fn(&mut CheckpointSettings); 5] = [
+            |x| x.integrator = String::from("Bidirectional"),
+            |x| x.spectral = false,
+            |x| x.samples_per_pixel = 128,
+            |x| x.adaptive = None,
+            |x| x.region = (0, 0, 2, 3),
+        ];
+        for change in changes {
+            let mut settings = settings();
+            change(&mut settings);
+            assert!(checkpoint.check(2, 3, &settings).is_err(), "{:?} was not rejected", settings);
+        }
+    }
+}
@@ -0,0 +1,102 @@
+//! Transparency cutouts, for things like leaves, fences and decals, where modelling
+//! the holes as geometry would be impractical.
+//!
+//! A cutout can be attached either to a primitive (Refer to [Cutout]), or to a material
+//! (Refer to [AlphaMask]). Either way, the ray carries on as if the cut out hit never
+//! happened, so this works for any ray traced through the scene.
+
+use crate::hittable::{hit_skipping, HitRecord, Hittable};
+use crate::material::{Material, Reflect};
+use crate::ray::Ray;
+use crate::texture::Texture;
+use crate::utils::random;
+use crate::vec3::Vec3;
+use std::ops::Range;
+use std::sync::Arc;
+
+/// Decides whether a hit is cut out, given the alpha at that point.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+pub enum AlphaMode {
+    /// Hits are cut out where the alpha is below the threshold. Gives hard edges.
+    Threshold(f32),
+    /// Hits are cut out at random, with the probability of passing through
+    /// being one minus the alpha. Gives soft, partially transparent edges.
+    Stochastic,
+}
+
+impl AlphaMode {
+    /// Returns true if a hit with the given alpha is cut out.
+    pub fn cuts(&self, alpha: f32) -> bool {
+        match self {
+            AlphaMode::Threshold(threshold) => alpha < *threshold,
+            AlphaMode::Stochastic => random() >= alpha,
+        }
+    }
+}
+
+/// Wraps a primitive, cutting out parts of it according to an alpha texture.
+/// The alpha is the scalar value of the texture (Refer to [Texture::scalar()]).
+#[allow(dead_code)]
+pub struct Cutout <T: Texture + Sync + Send> {
+    object: Box<dyn Hittable>,
+    alpha: T,
+    mode: AlphaMode,
+}
+
+#[allow(dead_code)]
+impl<T: Texture + Sync + Send> Cutout<T> {
+    pub fn new (object: Box<dyn Hittable>, alpha: T, mode: AlphaMode) -> Self {
+        Self { object, alpha, mode }
+    }
+}
+
+impl<T: Texture + Sync + Send> Hittable for Cutout<T> {
+    fn hit(&self, r: &Ray, t_range: Range<f32>) -> Option<HitRecord> {
+        hit_skipping(self.object.as_ref(), r, t_range, |rec| {
+            self.mode.cuts(self.alpha.scalar(rec.u, rec.v, &rec.point))
+        })
+    }
+}
+
+/// Wraps a material, cutting out every surface using it according to an alpha texture.
+/// This is handy when the same cutout (like a leaf) is shared by many primitives.
+#[allow(dead_code)]
+pub struct AlphaMask <T: Texture + Sync + Send> {
+    base: Arc<dyn Material>,
+    alpha: T,
+    mode: AlphaMode,
+}
+
+#[allow(dead_code)]
+impl<T: Texture + Sync + Send> AlphaMask<T> {
+    pub fn new (base: Arc<dyn Material>, alpha: T, mode: AlphaMode) -> Self {
+        Self { base, alpha, mode }
+    }
+}
+
+impl<T: Texture + Sync + Send> Material for AlphaMask<T> {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<Reflect> {
+        self.base.scatter(r_in, rec)
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> Vec3 {
+        self.base.eval(r_in, rec, direction)
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> f32 {
+        self.base.pdf(r_in, rec, direction)
+    }
+
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Vec3 {
+        self.base.emitted(r_in, rec)
+    }
+
+    fn light(&self) -> Option<usize> {
+        self.base.light()
+    }
+
+    fn cutout(&self, r_in: &Ray, rec: &HitRecord) -> bool {
+        self.mode.cuts(self.alpha.scalar(rec.u, rec.v, &rec.point)) || self.base.cutout(r_in, rec)
+    }
+}
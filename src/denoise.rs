@@ -0,0 +1,297 @@
+//! Removing noise from a rendered image with an edge-avoiding à-trous wavelet filter, guided by
+//! the variance of the pixels (Schied et al., "Spatiotemporal Variance-Guided Filtering").
+//!
+//! The image is blurred over wider and wider areas, but only between pixels that see the same
+//! surface: pixels are weighed down where their normals or depths differ (the feature buffers,
+//! traced separately), or where their brightness differs by more than their noise explains.
+//! Textures are kept sharp by blurring the light falling on the surfaces, which is the colour
+//! of a pixel divided by the albedo of what it sees, and multiplying the albedo back in after.
+
+use rayon::prelude::*;
+
+use crate::camera::{background, Camera};
+use crate::hittable::Hittable;
+use crate::ray::Ray;
+use crate::scene::Scene;
+use crate::spectrum::{self, luminance};
+use crate::utils::{mix_seed, with_seed};
+use crate::vec3::Vec3;
+
+type Colour = Vec3;
+
+/// Settings of the denoiser.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DenoiseSettings {
+    /// Number of passes of the filter, each reaching twice as far as the last
+    pub iterations: u32,
+    /// How many standard deviations of noise two pixels may differ by in brightness and still
+    /// be blurred together
+    pub sigma_luminance: f32,
+    /// Exponent of the cosine of the angle between normals. The higher, the less pixels with
+    /// different normals are blurred together.
+    pub sigma_normal: f32,
+    /// How far two depths may be from what the slope of the surface predicts
+    pub sigma_depth: f32,
+    /// Number of rays traced through every pixel to find its features
+    pub feature_samples: u32,
+}
+
+impl Default for DenoiseSettings {
+    fn default() -> Self {
+        Self {
+            iterations: 5,
+            sigma_luminance: 4.0,
+            sigma_normal: 128.0,
+            sigma_depth: 1.0,
+            feature_samples: 8,
+        }
+    }
+}
+
+/// What every pixel of the image sees first, besides mirrors and glass: the buffers that
+/// guide the filter. They cover the region of the image that is rendered (Refer to
+/// [Camera::region_bounds()]), row by row.
+pub struct Features {
+    /// Colour of the surfaces, through the mirrors and glass in front of them
+    pub albedo: Vec<Colour>,
+    /// Normals of the surfaces, which are zero for pixels that see the background
+    pub normal: Vec<Vec3>,
+    /// Distance to the surfaces along the camera rays, which is zero for the background
+    pub depth: Vec<f32>,
+}
+
+#[allow(dead_code)]
+impl Features {
+    /// Traces ``samples`` rays through every pixel of the region of the output image of
+    /// ``camera`` that is rendered, and averages what they see. With a ``seed``, every pixel
+    /// takes its random numbers from it, so that the features can be gathered again the same.
+    pub fn gather(camera: &Camera, scene: &Scene, samples: u32, seed: Option<u64>) -> Self {
+        let width = camera.output_size().0;
+        let (x0, y0, x1, y1) = camera.region_bounds();
+        let samples = samples.max(1);
+
+        let features: Vec<(Colour, Vec3, f32)> = (0..(x1 - x0) * (y1 - y0))
+            .into_par_iter()
+            .map(|k| {
+                let (i, j) = (x0 + k % (x1 - x0), y0 + k / (x1 - x0));
+                let (mut albedo, mut normal, mut depth, mut hits) = (Vec3::new(), Vec3::new(), 0.0, 0);
+
+                with_seed(seed.map(|x| mix_seed(&[x, (j * width + i) as u64])), || {
+                    for _ in 0..samples {
+                        let Some(ray) = camera.pixel_ray(i, j) else {
+                            continue;
+                        };
+                        let (a, n, d) = first_surface(ray, scene, camera.max_depth());
+                        albedo += a;
+                        if d > 0.0 {
+                            normal += n;
+                            depth += d;
+                            hits += 1;
+                        }
+                    }
+                });
+
+                // Pixels see a surface if most of their rays do
+                let normal = if 2 * hits > samples && normal.length_sq() > 0.0 { normal.unit() } else { Vec3::new() };
+                let depth = if 2 * hits > samples { depth / hits as f32 } else { 0.0 };
+                (albedo / samples as f32, normal, depth)
+            })
+            .collect();
+
+        Self {
+            albedo: features.iter().map(|x| x.0).collect(),
+            normal: features.iter().map(|x| x.1).collect(),
+            depth: features.iter().map(|x| x.2).collect(),
+        }
+    }
+}
+
+/// Follows the ray through mirrors and glass to the first surface that scatters light in more
+/// than one direction. Returns its albedo (tinted by what the ray passed through) in RGB, its
+/// normal, and how far the ray travelled to it, which is zero if it escaped the scene.
+fn first_surface(mut ray: Ray, scene: &Scene, max_depth: u32) -> (Colour, Vec3, f32) {
+    let wavelengths = ray.wavelengths;
+    let to_rgb = |colour: Colour| match wavelengths {
+        Some(wavelengths) => spectrum::to_rgb(colour, wavelengths),
+        None => colour,
+    };
+    let mut tint = Vec3::from_point(1.0, 1.0, 1.0);
+    let mut distance = 0.0;
+
+    for _ in 0..max_depth.max(1) {
+        let Some(rec) = scene.world.hit(&ray, 0.001..f32::INFINITY) else {
+            return (to_rgb(tint * background(&ray, scene)), Vec3::new(), 0.0);
+        };
+        distance += rec.t * ray.direction.length();
+
+        match rec.material.scatter(&ray, &rec) {
+            Some(x) if x.pdf == 0.0 => {
+                tint = tint * x.attenuation;
+                ray = x.scattered;
+            }
+            Some(x) => return (to_rgb(tint * x.attenuation), rec.normal, distance),
+            None => return (to_rgb(tint * rec.material.emitted(&ray, &rec)), rec.normal, distance),
+        }
+    }
+
+    (Vec3::new(), Vec3::new(), 0.0)
+}
+
+/// Weights of the B3 spline used by the à-trous transform, for offsets -2 to 2
+const KERNEL: [f32; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+/// Denoises an image of ``width`` by ``height`` pixels in RGB. ``variance`` is the variance of
+/// the luminance of every pixel (of the mean of its samples); if it is not known, it is
+/// estimated from the neighbours of every pixel.
+pub fn denoise(pixels: &[Colour], variance: Option<&[f32]>, features: &Features, width: u32, height: u32, settings: &DenoiseSettings) -> Vec<Colour> {
+    let (width, height) = (width as usize, height as usize);
+
+    // Only the light falling on the surfaces is blurred
+    let albedo: Vec<Colour> = features.albedo.iter().map(|&a| a.map(|x| if x > 1e-3 { x } else { 1.0 })).collect();
+    let mut light: Vec<Colour> = pixels.iter().zip(&albedo).map(|(&c, &a)| divide(c, a)).collect();
+    let mut variance: Vec<f32> = match variance {
+        Some(variance) => variance.iter().zip(&albedo).map(|(&v, &a)| v / luminance(a).max(1e-3).powi(2)).collect(),
+        None => estimate_variance(&light, width, height),
+    };
+    let gradient = depth_gradient(&features.depth, width, height);
+
+    for iteration in 0..settings.iterations {
+        let step = 1 << iteration;
+        let deviation = blur_variance(&variance, width, height);
+
+        let filtered: Vec<(Colour, f32)> = (0..width * height)
+            .into_par_iter()
+            .map(|p| {
+                let (x, y) = (p % width, p / width);
+                let l_p = luminance(light[p]);
+                let scale = settings.sigma_luminance * deviation[p].sqrt() + 1e-6;
+
+                let (mut colour, mut var, mut total) = (Vec3::new(), 0.0, 0.0);
+                for (dy, ky) in KERNEL.iter().enumerate() {
+                    for (dx, kx) in KERNEL.iter().enumerate() {
+                        let qx = x as isize + (dx as isize - 2) * step;
+                        let qy = y as isize + (dy as isize - 2) * step;
+                        if qx < 0 || qy < 0 || qx >= width as isize || qy >= height as isize {
+                            continue;
+                        }
+                        let q = qy as usize * width + qx as usize;
+
+                        let w = if q == p {
+                            kx * ky
+                        } else {
+                            let w_normal = normal_weight(features.normal[p], features.normal[q], settings.sigma_normal);
+                            let predicted = gradient[p].0 * (qx - x as isize) as f32 + gradient[p].1 * (qy - y as isize) as f32;
+                            let w_depth = depth_weight(features.depth[p], features.depth[q], predicted, settings.sigma_depth);
+                            let w_luminance = (-(l_p - luminance(light[q])).abs() / scale).exp();
+                            kx * ky * w_normal * w_depth * w_luminance
+                        };
+
+                        colour += w * light[q];
+                        var += w * w * variance[q];
+                        total += w;
+                    }
+                }
+
+                (colour / total, var / (total * total))
+            })
+            .collect();
+
+        light = filtered.iter().map(|x| x.0).collect();
+        variance = filtered.iter().map(|x| x.1).collect();
+    }
+
+    light.iter().zip(&albedo).map(|(&l, &a)| l * a).collect()
+}
+
+fn divide(a: Colour, b: Colour) -> Colour {
+    Vec3::from_point(a.x / b.x, a.y / b.y, a.z / b.z)
+}
+
+/// How alike two normals are. Pixels that both see the background count as alike.
+fn normal_weight(n_p: Vec3, n_q: Vec3, sigma: f32) -> f32 {
+    match (n_p.length_sq() > 0.0, n_q.length_sq() > 0.0) {
+        (false, false) => 1.0,
+        (true, true) => n_p.dot(n_q).max(0.0).powf(sigma),
+        _ => 0.0,
+    }
+}
+
+/// How alike two depths are, given the difference the slope of the surface predicts.
+/// Pixels that both see the background count as alike.
+fn depth_weight(z_p: f32, z_q: f32, predicted: f32, sigma: f32) -> f32 {
+    match (z_p > 0.0, z_q > 0.0) {
+        (false, false) => 1.0,
+        (true, true) => (-(z_p - z_q).abs() / (sigma * predicted.abs() + 1e-2 * z_p)).exp(),
+        _ => 0.0,
+    }
+}
+
+/// Slope of the depth across and down the image at every pixel, from its neighbours that see
+/// a surface (zero where none do)
+fn depth_gradient(depth: &[f32], width: usize, height: usize) -> Vec<(f32, f32)> {
+    let slope = |k: usize, before: Option<usize>, after: Option<usize>| {
+        let valid = |x: Option<usize>| x.filter(|&x| depth[x] > 0.0);
+        match (valid(before), valid(after)) {
+            (Some(b), Some(a)) => (depth[a] - depth[b]) / 2.0,
+            (Some(b), None) => depth[k] - depth[b],
+            (None, Some(a)) => depth[a] - depth[k],
+            (None, None) => 0.0,
+        }
+    };
+
+    (0..width * height)
+        .map(|k| {
+            if depth[k] <= 0.0 {
+                return (0.0, 0.0);
+            }
+            let (x, y) = (k % width, k / width);
+            let dx = slope(k, (x > 0).then(|| k - 1), (x + 1 < width).then_some(k + 1));
+            let dy = slope(k, (y > 0).then(|| k - width), (y + 1 < height).then_some(k + width));
+            (dx, dy)
+        })
+        .collect()
+}
+
+/// Variance of the luminance of the 3×3 pixels around every pixel, standing in for the
+/// variance of the pixel itself
+fn estimate_variance(light: &[Colour], width: usize, height: usize) -> Vec<f32> {
+    (0..width * height)
+        .map(|k| {
+            let (x, y) = (k % width, k / width);
+            let (mut sum, mut sum_sq, mut count) = (0.0, 0.0, 0.0);
+            for qy in y.saturating_sub(1)..(y + 2).min(height) {
+                for qx in x.saturating_sub(1)..(x + 2).min(width) {
+                    let l = luminance(light[qy * width + qx]);
+                    sum += l;
+                    sum_sq += l * l;
+                    count += 1.0;
+                }
+            }
+            let mean = sum / count;
+            (sum_sq / count - mean * mean).max(0.0)
+        })
+        .collect()
+}
+
+/// Blurs the variance over 3×3 pixels, which steadies the brightness test of the filter
+fn blur_variance(variance: &[f32], width: usize, height: usize) -> Vec<f32> {
+    const GAUSSIAN: [f32; 3] = [0.25, 0.5, 0.25];
+
+    (0..width * height)
+        .map(|k| {
+            let (x, y) = (k % width, k / width);
+            let (mut sum, mut total) = (0.0, 0.0);
+            for (dy, ky) in GAUSSIAN.iter().enumerate() {
+                for (dx, kx) in GAUSSIAN.iter().enumerate() {
+                    let (qx, qy) = (x as isize + dx as isize - 1, y as isize + dy as isize - 1);
+                    if qx < 0 || qy < 0 || qx >= width as isize || qy >= height as isize {
+                        continue;
+                    }
+                    sum += kx * ky * variance[qy as usize * width + qx as usize];
+                    total += kx * ky;
+                }
+            }
+            sum / total
+        })
+        .collect()
+}
@@ -0,0 +1,192 @@
+//! Piecewise constant distributions, used to importance sample tabulated functions like images.
+
+/// A distribution over [0, 1) proportional to a piecewise constant function.
+#[derive(Debug, Clone)]
+pub struct Distribution1D {
+    func: Vec<f32>,
+    cdf: Vec<f32>,
+    integral: f32,
+}
+
+impl Distribution1D {
+    /// Creates a distribution proportional to the (non-negative) values of ``func``,
+    /// each covering an equal part of [0, 1).
+    pub fn new(func: Vec<f32>) -> Self {
+        let n = func.len() as f32;
+
+        let mut cdf = Vec::with_capacity(func.len() + 1);
+        cdf.push(0.0);
+        for (i, x) in func.iter().enumerate() {
+            cdf.push(cdf[i] + x.abs() / n);
+        }
+
+        let integral = cdf[func.len()];
+        for (i, x) in cdf.iter_mut().enumerate() {
+            // A function that is zero everywhere is sampled uniformly
+            *x = if integral > 0.0 { *x / integral } else { i as f32 / n };
+        }
+
+        Self { func, cdf, integral }
+    }
+
+    /// Number of pieces
+    pub fn count(&self) -> usize {
+        self.func.len()
+    }
+
+    /// Integral of the function over [0, 1)
+    pub fn integral(&self) -> f32 {
+        self.integral
+    }
+
+    /// Maps a uniform random number to a sample in [0, 1), returning the sample, its density,
+    /// and the piece it lies in.
+    pub fn sample(&self, u: f32) -> (f32, f32, usize) {
+        let piece = (self.cdf.partition_point(|&x| x <= u).max(1) - 1).min(self.count() - 1);
+
+        let width = self.cdf[piece + 1] - self.cdf[piece];
+        let offset = if width > 0.0 { (u - self.cdf[piece]) / width } else { 0.0 };
+
+        ((piece as f32 + offset) / self.count() as f32, self.pdf_at(piece), piece)
+    }
+
+    /// Density of the piece
+    pub fn pdf_at(&self, piece: usize) -> f32 {
+        if self.integral > 0.0 {
+            self.func[piece].abs() / self.integral
+        } else {
+            1.0
+        }
+    }
+}
+
+/// A distribution over [0, 1)² proportional to a piecewise constant function on a grid.
+/// A row is chosen first from the marginal distribution, and then a column in that row.
+#[derive(Debug, Clone)]
+pub struct Distribution2D {
+    rows: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    /// Creates a distribution from a ``width`` by ``height`` grid of values, stored row by row.
+    pub fn new(func: &[f32], width: usize, height: usize) -> Self {
+        let rows: Vec<Distribution1D> = func
+            .chunks(width)
+            .take(height)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(rows.iter().map(|x| x.integral()).collect());
+
+        Self { rows, marginal }
+    }
+
+    /// Integral of the function over [0, 1)²
+    pub fn integral(&self) -> f32 {
+        self.marginal.integral()
+    }
+
+    /// Maps two uniform random numbers to a point ``(x, y)`` in [0, 1)², returning it with its density.
+    pub fn sample(&self, u: f32, v: f32) -> ((f32, f32), f32) {
+        let (y, pdf_y, row) = self.marginal.sample(v);
+        let (x, pdf_x, _) = self.rows[row].sample(u);
+
+        ((x, y), pdf_x * pdf_y)
+    }
+
+    /// Density of the point ``(x, y)``
+    pub fn pdf(&self, x: f32, y: f32) -> f32 {
+        let row = ((y * self.rows.len() as f32) as usize).min(self.rows.len() - 1);
+        let column = ((x * self.rows[row].count() as f32) as usize).min(self.rows[row].count() - 1);
+
+        if self.marginal.integral() > 0.0 {
+            self.rows[row].func[column].abs() / self.marginal.integral()
+        } else {
+            1.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fraction of the integral of ``func`` below ``x``
+    fn cdf(func: &[f32], x: f32) -> f32 {
+        let n = func.len() as f32;
+        let total: f32 = func.iter().sum();
+        let piece = ((x * n) as usize).min(func.len() - 1);
+        let below: f32 = func[..piece].iter().sum();
+
+        (below + (x * n - piece as f32) * func[piece]) / total
+    }
+
+    #[test]
+    fn pdf_1d_sums_to_one() {
+        let distribution = Distribution1D::new(vec![1.0, 3.0, 0.0, 4.0]);
+        let sum: f32 = (0..distribution.count()).map(|i| distribution.pdf_at(i)).sum();
+
+        assert!((sum / distribution.count() as f32 - 1.0).abs() < 1e-6);
+        assert!((distribution.integral() - 2.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn sample_1d_inverts_the_cdf() {
+        let func = [1.0, 3.0, 0.0, 4.0];
+        let distribution = Distribution1D::new(func.to_vec());
+
+        for k in 0..100 {
+            let u = k as f32 / 100.0;
+            let (x, pdf, piece) = distribution.sample(u);
+
+            assert!((cdf(&func, x) - u).abs() < 1e-5, "u = {}, x = {}", u, x);
+            assert_ne!(piece, 2, "a piece that is zero is never sampled");
+            assert_eq!(pdf, distribution.pdf_at(piece));
+        }
+    }
+
+    #[test]
+    fn zero_1d_is_uniform() {
+        let distribution = Distribution1D::new(vec![0.0; 4]);
+
+        assert_eq!(distribution.sample(0.3).0, 0.3);
+        assert_eq!(distribution.pdf_at(1), 1.0);
+    }
+
+    #[test]
+    fn pdf_2d_sums_to_one() {
+        let (width, height) = (3, 2);
+        let func = [1.0, 0.0, 2.0, 5.0, 1.0, 3.0];
+        let distribution = Distribution2D::new(&func, width, height);
+
+        let sum: f32 = (0..width * height)
+            .map(|k| {
+                let x = (k % width) as f32 + 0.5;
+                let y = (k / width) as f32 + 0.5;
+                distribution.pdf(x / width as f32, y / height as f32)
+            })
+            .sum();
+
+        assert!((sum / (width * height) as f32 - 1.0).abs() < 1e-6);
+        assert!((distribution.integral() - 2.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn sample_2d_inverts_the_cdfs() {
+        let (width, height) = (3, 2);
+        let func = [1.0, 0.0, 2.0, 5.0, 1.0, 3.0];
+        let distribution = Distribution2D::new(&func, width, height);
+        let rows: Vec<f32> = func.chunks(width).map(|x| x.iter().sum()).collect();
+
+        for k in 0..100 {
+            let (u, v) = ((k % 10) as f32 / 10.0 + 0.05, (k / 10) as f32 / 10.0 + 0.05);
+            let ((x, y), pdf) = distribution.sample(u, v);
+            let row = ((y * height as f32) as usize).min(height - 1);
+
+            // The row comes from the marginal, and the column from the row
+            assert!((cdf(&rows, y) - v).abs() < 1e-5, "v = {}, y = {}", v, y);
+            assert!((cdf(&func[row * width..(row + 1) * width], x) - u).abs() < 1e-5, "u = {}, x = {}", u, x);
+            assert!((pdf - distribution.pdf(x, y)).abs() < 1e-5);
+        }
+    }
+}
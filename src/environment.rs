@@ -0,0 +1,145 @@
+//! Lighting from an environment map: an equirectangular (latitude-longitude) HDR image
+//! surrounding the scene, usually a photograph of the sky.
+//!
+//! Rays that escape the scene see the environment, and it is also sampled directly as a
+//! [Light]. Directions are chosen in proportion to the brightness of the image, so that small,
+//! bright features (like the sun) are found quickly rather than by chance.
+
+use std::f32::consts::PI;
+use std::io::Result;
+use std::path::Path;
+
+use crate::distribution::Distribution2D;
+use crate::image::Image;
+use crate::light::{Light, LightSample};
+use crate::light_sampler::Bounds;
+use crate::ray::Ray;
+use crate::spectrum::luminance;
+use crate::utils::random;
+use crate::vec3::{Point3, Vec3};
+
+/// An environment map. The top row of the image is straight up (+y), and the centre
+/// of the image looks along +x, before any rotation.
+pub struct Environment {
+    image: Image,
+    /// Rotation of the environment around the up axis, in degrees
+    pub rotation: f32,
+    /// Scale applied to the radiance of the image
+    pub intensity: f32,
+    distribution: Distribution2D,
+}
+
+#[allow(dead_code)]
+impl Environment {
+    /// Creates an environment from an equirectangular image of linear radiance.
+    pub fn new(image: Image) -> Self {
+        // Rows near the poles cover less of the sphere, so they are sampled less often
+        let weights: Vec<f32> = image
+            .pixels
+            .iter()
+            .enumerate()
+            .map(|(i, x)| {
+                let theta = PI * ((i / image.width) as f32 + 0.5) / image.height as f32;
+                luminance(*x) * theta.sin()
+            })
+            .collect();
+        let distribution = Distribution2D::new(&weights, image.width, image.height);
+
+        Self {
+            image,
+            rotation: 0.0,
+            intensity: 1.0,
+            distribution,
+        }
+    }
+
+    /// Loads an environment from a Radiance HDR file (Refer to [Image::load_hdr()]).
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(Self::new(Image::load_hdr(path)?))
+    }
+
+    pub fn with_rotation(mut self, degrees: f32) -> Self {
+        self.rotation = degrees;
+        self
+    }
+
+    pub fn with_intensity(mut self, intensity: f32) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    /// Returns the radiance arriving along the (reversed) direction of the ray,
+    /// in the units carried by the ray.
+    pub fn radiance(&self, r: &Ray) -> Vec3 {
+        let (u, v) = self.image_coordinates(r.direction.unit());
+        r.sample_colour(self.lookup(u, v))
+    }
+
+    /// Maps a unit direction to coordinates in the image, both in [0, 1)
+    fn image_coordinates(&self, direction: Vec3) -> (f32, f32) {
+        let theta = direction.y.clamp(-1.0, 1.0).acos();
+        let phi = direction.z.atan2(direction.x);
+
+        let u = (phi / (2.0 * PI) + 0.5 - self.rotation / 360.0).rem_euclid(1.0);
+        (u, theta / PI)
+    }
+
+    /// Maps coordinates in the image back to a unit direction
+    fn direction(&self, u: f32, v: f32) -> Vec3 {
+        let theta = PI * v;
+        let phi = 2.0 * PI * (u - 0.5 + self.rotation / 360.0);
+
+        Vec3::from_point(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin())
+    }
+
+    /// Radiance of the pixel containing the coordinates. The nearest pixel is used (rather
+    /// than interpolating) so that the radiance matches the distribution it is sampled with.
+    fn lookup(&self, u: f32, v: f32) -> Vec3 {
+        let x = (u * self.image.width as f32) as isize;
+        let y = ((v * self.image.height as f32) as isize).min(self.image.height as isize - 1);
+
+        self.intensity * self.image.pixel(x, y)
+    }
+
+    /// Converts a density over the image into a density over directions
+    fn solid_angle_pdf(&self, u: f32, v: f32) -> f32 {
+        let sin_theta = (PI * v).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+
+        self.distribution.pdf(u, v) / (2.0 * PI * PI * sin_theta)
+    }
+}
+
+impl Light for Environment {
+    fn sample(&self, _point: Point3, r: &Ray) -> Option<LightSample> {
+        let ((u, v), _) = self.distribution.sample(random(), random());
+
+        let pdf = self.solid_angle_pdf(u, v);
+        if pdf <= 0.0 {
+            return None;
+        }
+
+        Some(LightSample {
+            direction: self.direction(u, v),
+            distance: f32::INFINITY,
+            radiance: r.sample_colour(self.lookup(u, v)) / pdf,
+            pdf,
+        })
+    }
+
+    fn pdf(&self, _point: Point3, direction: Vec3) -> f32 {
+        let (u, v) = self.image_coordinates(direction.unit());
+        self.solid_angle_pdf(u, v)
+    }
+
+    /// Infinitely far away lights have no finite power (Refer to [crate::light_sampler])
+    fn power(&self) -> f32 {
+        0.0
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        None
+    }
+}
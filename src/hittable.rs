@@ -5,6 +5,7 @@ use std::ops::Range;
 use std::sync::Arc;
 
 #[allow(dead_code)]
+#[derive(Clone)]
 /// Records any information associated with a hit: where did it hit, what is
 /// normal to it. This is processed in every loop to actually "scatter" light.
 /// Refer to: [Hittable], [material::Material]
@@ -16,13 +17,27 @@ pub struct HitRecord {
 
     pub front: bool,
     pub u: f32,
-    pub v: f32
+    pub v: f32,
+
+    /// Tangent and bitangent at the hit point, pointing along increasing ``u`` and ``v``.
+    /// Together with the normal, these make the (right-handed) frame used by normal and bump maps.
+    pub tangent: Vec3,
+    pub bitangent: Vec3
 }
 
 #[allow(dead_code)]
 impl HitRecord {
     /// Creates a new instance of [HitRecord]. Assumes that the front face is true by default.
+    /// The tangents are set to an arbitrary frame around the normal. Primitives with a
+    /// UV parameterisation should set them using [HitRecord::set_tangents()].
     pub fn new(point: Point3, normal: Vec3, t: f32, material: Arc<dyn Material>, u: f32, v: f32) -> Self {
+        let axis = if normal.x.abs() > 0.9 {
+            Vec3::from_point(0.0, 1.0, 0.0)
+        } else {
+            Vec3::from_point(1.0, 0.0, 0.0)
+        };
+        let tangent = axis.cross(normal).unit();
+
         Self {
             point,
             normal,
@@ -30,8 +45,26 @@ impl HitRecord {
             material,
             front: true,
 	    u,
-	    v
+	    v,
+            tangent,
+            bitangent: normal.cross(tangent)
+        }
+    }
+
+    /// Sets the tangent frame from the (outward) tangent along ``u``.
+    /// The bitangent is made perpendicular to both the normal and the tangent.
+    /// This must be called after [HitRecord::set_face_normal()].
+    pub fn set_tangents(&mut self, tangent: Vec3) {
+        let outward_normal = if self.front { self.normal } else { -self.normal };
+        // Remove any component along the normal, in case the tangent is not exact
+        let tangent = tangent - tangent.dot(outward_normal) * outward_normal;
+
+        if tangent.near_zero() {
+            return;
         }
+
+        self.tangent = tangent.unit();
+        self.bitangent = self.normal.cross(self.tangent);
     }
 
     /// Depending on the current HitRecord, sets the normal in the correct outward direction.
@@ -42,7 +75,8 @@ impl HitRecord {
             outward_normal
         } else {
             -outward_normal
-        }
+        };
+        self.bitangent = self.normal.cross(self.tangent);
     }
 }
 
@@ -67,7 +101,7 @@ impl Hittable for World {
         let mut hit: Option<HitRecord> = None;
 
         for object in self {
-            if let Some(rec) = object.hit(r, t_range.start..closest_yet) {
+            if let Some(rec) = hit_skipping(object.as_ref(), r, t_range.start..closest_yet, |rec| rec.material.cutout(r, rec)) {
                 closest_yet = closest_yet.min(rec.t);
                 hit = Some(rec)
             }
@@ -75,3 +109,23 @@ impl Hittable for World {
         hit
     }
 }
+
+/// Finds the closest hit on ``object`` that is not rejected by ``skip``.
+/// Rejected hits are stepped over by searching again just beyond them.
+pub fn hit_skipping(
+    object: &dyn Hittable,
+    r: &Ray,
+    t_range: Range<f32>,
+    skip: impl Fn(&HitRecord) -> bool,
+) -> Option<HitRecord> {
+    let mut start = t_range.start;
+
+    loop {
+        let rec = object.hit(r, start..t_range.end)?;
+        if !skip(&rec) {
+            return Some(rec);
+        }
+
+        start = rec.t + 1e-4 * rec.t.abs().max(1.0);
+    }
+}
@@ -0,0 +1,201 @@
+//! Loading images from disk, so that they can be used as textures.
+
+use crate::texture::Texture;
+use crate::vec3::Vec3;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+/// An image held in memory as linear floating point colours, stored row by row from the top.
+#[derive(Debug, Clone)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Vec3>,
+}
+
+#[allow(dead_code)]
+impl Image {
+    /// Loads a PPM image, either in ASCII (``P3``) or binary (``P6``) form.
+    /// Values are scaled to lie in [0, 1], and are not gamma corrected.
+    pub fn load_ppm<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let bytes = fs::read(path)?;
+        let mut pos = 0;
+
+        let magic = next_token(&bytes, &mut pos)?;
+        let width = parse_token(&bytes, &mut pos)?;
+        let height = parse_token(&bytes, &mut pos)?;
+        let max = parse_token(&bytes, &mut pos)? as f32;
+
+        let mut pixels = Vec::with_capacity(width * height);
+        match magic.as_str() {
+            "P3" => {
+                for _ in 0..width * height {
+                    let r = parse_token(&bytes, &mut pos)? as f32;
+                    let g = parse_token(&bytes, &mut pos)? as f32;
+                    let b = parse_token(&bytes, &mut pos)? as f32;
+                    pixels.push(Vec3::from_point(r / max, g / max, b / max));
+                }
+            }
+            "P6" => {
+                // A single whitespace character separates the header from the data
+                pos += 1;
+                let data = bytes.get(pos..pos + 3 * width * height).ok_or_else(|| invalid("Truncated PPM data"))?;
+                for rgb in data.chunks(3) {
+                    pixels.push(Vec3::from_point(rgb[0] as f32 / max, rgb[1] as f32 / max, rgb[2] as f32 / max));
+                }
+            }
+            _ => return Err(invalid("Not a PPM file")),
+        }
+
+        Ok(Self { width, height, pixels })
+    }
+
+    /// Loads a Radiance HDR (``.hdr``) image, holding linear colours of any brightness.
+    /// Both run length encoded and flat scanlines are supported.
+    pub fn load_hdr<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let bytes = fs::read(path)?;
+        let mut pos = 0;
+
+        // The header is a list of lines, ended by an empty line, followed by the resolution
+        let mut line = || -> Result<String> {
+            let start = pos;
+            while bytes.get(pos).is_some_and(|&c| c != b'\n') {
+                pos += 1;
+            }
+            if pos >= bytes.len() {
+                return Err(invalid("Unexpected end of file"));
+            }
+            pos += 1;
+            Ok(String::from_utf8_lossy(&bytes[start..pos - 1]).into_owned())
+        };
+
+        if !line()?.starts_with("#?") {
+            return Err(invalid("Not a Radiance HDR file"));
+        }
+        while !line()?.trim().is_empty() {}
+
+        let resolution = line()?;
+        let (height, width) = match resolution.split_whitespace().collect::<Vec<_>>()[..] {
+            ["-Y", height, "+X", width] => (
+                height.parse().map_err(|_| invalid("Invalid height"))?,
+                width.parse().map_err(|_| invalid("Invalid width"))?,
+            ),
+            _ => return Err(invalid("Unsupported HDR orientation")),
+        };
+
+        let mut pixels = Vec::with_capacity(width * height);
+        let mut scanline = vec![0u8; 4 * width];
+
+        for _ in 0..height {
+            let header = bytes.get(pos..pos + 4).ok_or_else(|| invalid("Truncated HDR data"))?;
+            let encoded = (8..0x8000).contains(&width)
+                && header[0] == 2
+                && header[1] == 2
+                && ((header[2] as usize) << 8 | header[3] as usize) == width;
+
+            if encoded {
+                // Each of the four channels is run length encoded separately
+                pos += 4;
+                for channel in 0..4 {
+                    let mut x = 0;
+                    while x < width {
+                        let count = *bytes.get(pos).ok_or_else(|| invalid("Truncated HDR data"))? as usize;
+                        pos += 1;
+
+                        if count > 128 {
+                            let value = *bytes.get(pos).ok_or_else(|| invalid("Truncated HDR data"))?;
+                            pos += 1;
+                            for _ in 0..(count - 128).min(width - x) {
+                                scanline[4 * x + channel] = value;
+                                x += 1;
+                            }
+                        } else {
+                            for _ in 0..count.min(width - x) {
+                                scanline[4 * x + channel] = *bytes.get(pos).ok_or_else(|| invalid("Truncated HDR data"))?;
+                                pos += 1;
+                                x += 1;
+                            }
+                        }
+                    }
+                }
+            } else {
+                let data = bytes.get(pos..pos + 4 * width).ok_or_else(|| invalid("Truncated HDR data"))?;
+                scanline.copy_from_slice(data);
+                pos += 4 * width;
+            }
+
+            pixels.extend(scanline.chunks(4).map(|rgbe| {
+                if rgbe[3] == 0 {
+                    Vec3::new()
+                } else {
+                    let scale = 2f32.powi(rgbe[3] as i32 - 136);
+                    Vec3::from_point(rgbe[0] as f32, rgbe[1] as f32, rgbe[2] as f32) * scale
+                }
+            }));
+        }
+
+        Ok(Self { width, height, pixels })
+    }
+
+    /// Returns the pixel at the given coordinates, wrapping around the edges.
+    pub fn pixel(&self, x: isize, y: isize) -> Vec3 {
+        let x = x.rem_euclid(self.width as isize) as usize;
+        let y = y.rem_euclid(self.height as isize) as usize;
+
+        self.pixels[y * self.width + x]
+    }
+
+    /// Bilinearly interpolates the image at the given texture coordinates.
+    /// ``v`` runs from the bottom of the image to the top.
+    pub fn sample(&self, u: f32, v: f32) -> Vec3 {
+        let x = u * self.width as f32 - 0.5;
+        let y = (1.0 - v) * self.height as f32 - 0.5;
+
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as isize, y0 as isize);
+
+        (1.0 - ty) * ((1.0 - tx) * self.pixel(x0, y0) + tx * self.pixel(x0 + 1, y0))
+            + ty * ((1.0 - tx) * self.pixel(x0, y0 + 1) + tx * self.pixel(x0 + 1, y0 + 1))
+    }
+}
+
+impl Texture for Image {
+    fn value (&self, u: f32, v: f32, _p: &Vec3) -> Vec3 {
+        self.sample(u, v)
+    }
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+/// Reads the next whitespace separated token of a header, skipping over comments.
+fn next_token(bytes: &[u8], pos: &mut usize) -> Result<String> {
+    loop {
+        match bytes.get(*pos) {
+            Some(b'#') => {
+                while bytes.get(*pos).is_some_and(|&c| c != b'\n') {
+                    *pos += 1;
+                }
+            }
+            Some(c) if c.is_ascii_whitespace() => *pos += 1,
+            Some(_) => break,
+            None => return Err(invalid("Unexpected end of file")),
+        }
+    }
+
+    let start = *pos;
+    while bytes.get(*pos).is_some_and(|c| !c.is_ascii_whitespace()) {
+        *pos += 1;
+    }
+
+    Ok(String::from_utf8_lossy(&bytes[start..*pos]).into_owned())
+}
+
+fn parse_token(bytes: &[u8], pos: &mut usize) -> Result<usize> {
+    next_token(bytes, pos)?
+        .parse()
+        .map_err(|_| invalid("Expected a number"))
+}
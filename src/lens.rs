@@ -0,0 +1,356 @@
+//! A camera lens made of several spherical elements, described by a lens prescription.
+//!
+//! Unlike the thin lens model of [crate::camera::Camera] (``defocus_angle`` and ``focus_distance``),
+//! tracing rays through the actual elements reproduces distortion, focus breathing,
+//! vignetting and aberrations of real lenses. Besides the rays blocked by the elements,
+//! the film darkens towards its edges with the cos⁴ falloff of the light reaching it,
+//! and with the size of the exit pupil seen from each point.
+//!
+//! Internally, everything is measured in millimetres, in a space where the film lies
+//! on the plane ``z = 0``, and the lens (and the scene) lie towards positive ``z``.
+//! Scene units are taken to be metres.
+
+use crate::utils::random;
+use crate::vec3::Vec3;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+/// Millimetres in a scene unit
+pub const MM_PER_UNIT: f32 = 1000.0;
+
+/// Number of rings on the film for which the exit pupil is computed
+const PUPIL_RINGS: usize = 64;
+
+/// A single surface of the lens, as given by a row of a lens prescription.
+/// All lengths are in millimetres.
+#[derive(Debug, Clone, Copy)]
+pub struct LensElement {
+    /// Radius of curvature. Positive when the centre of curvature is towards the film.
+    /// A radius of 0 marks the aperture stop.
+    pub radius: f32,
+    /// Distance along the axis to the next surface (or the film, for the last surface)
+    pub thickness: f32,
+    /// Refractive index of the medium after the surface. 0 or 1 is air.
+    pub eta: f32,
+    /// Diameter of the surface
+    pub aperture: f32,
+}
+
+/// A lens made of spherical elements, listed from the front (the side of the scene)
+/// to the back (the side of the film).
+#[derive(Debug, Clone)]
+pub struct LensSystem {
+    elements: Vec<LensElement>,
+    film_diagonal: f32,
+    /// Bounds ``(x_min, x_max, y_min, y_max)`` of the exit pupil on the plane of the rear element,
+    /// for each ring of the film, as seen from points on the ``x`` axis.
+    pupil_bounds: Vec<Option<(f32, f32, f32, f32)>>,
+    /// Area of the largest bounds of the exit pupil, which the pupil of every ring is compared to
+    largest_pupil: f32,
+}
+
+#[allow(dead_code)]
+impl LensSystem {
+    /// Creates a lens from its elements, and the diagonal of the film in millimetres.
+    /// The lens must be focused (Refer to [LensSystem::focused()]) before it is used.
+    pub fn new(elements: Vec<LensElement>, film_diagonal: f32) -> Self {
+        Self {
+            elements,
+            film_diagonal,
+            pupil_bounds: Vec::new(),
+            largest_pupil: 0.0,
+        }
+    }
+
+    /// Parses a lens prescription. Every line has four columns: radius, thickness,
+    /// refractive index and aperture diameter. Lines starting with ``#`` are ignored.
+    pub fn parse(table: &str, film_diagonal: f32) -> Result<Self> {
+        let mut elements = Vec::new();
+
+        for line in table.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let columns = line
+                .split_whitespace()
+                .map(|x| x.parse::<f32>())
+                .collect::<std::result::Result<Vec<_>, _>>()
+                .map_err(|_| Error::new(ErrorKind::InvalidData, format!("Invalid lens element: {}", line)))?;
+
+            if columns.len() != 4 {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Expected 4 columns in lens element: {}", line),
+                ));
+            }
+
+            elements.push(LensElement {
+                radius: columns[0],
+                thickness: columns[1],
+                eta: columns[2],
+                aperture: columns[3],
+            });
+        }
+
+        Ok(Self::new(elements, film_diagonal))
+    }
+
+    /// Loads a lens prescription from a file (Refer to [LensSystem::parse()]).
+    pub fn load<P: AsRef<Path>>(path: P, film_diagonal: f32) -> Result<Self> {
+        Self::parse(&fs::read_to_string(path)?, film_diagonal)
+    }
+
+    /// A 50mm f/2 double Gauss lens (US patent 2,673,491), a classic design for standard lenses.
+    pub fn double_gauss(film_diagonal: f32) -> Self {
+        let table = [
+            (29.475, 3.76, 1.67, 25.2),
+            (84.83, 0.12, 1.0, 25.2),
+            (19.275, 4.025, 1.67, 23.0),
+            (40.77, 3.275, 1.699, 23.0),
+            (12.75, 5.705, 1.0, 18.0),
+            (0.0, 4.5, 0.0, 17.1),
+            (-14.495, 1.18, 1.603, 17.0),
+            (40.77, 6.065, 1.658, 20.0),
+            (-20.385, 0.19, 1.0, 20.0),
+            (437.065, 3.22, 1.717, 20.0),
+            (-39.73, 0.0, 1.0, 20.0),
+        ];
+
+        let elements = table
+            .iter()
+            .map(|&(radius, thickness, eta, aperture)| LensElement { radius, thickness, eta, aperture })
+            .collect();
+
+        Self::new(elements, film_diagonal)
+    }
+
+    /// Returns a copy of the lens with the film moved so that objects at ``distance``
+    /// (in scene units, from the film) are in focus, and with its exit pupil computed.
+    /// If no ray makes it through the lens from that distance, the film is left where it was,
+    /// with a warning.
+    pub fn focused(&self, distance: f32) -> Self {
+        let mut lens = self.clone();
+        let distance = distance * MM_PER_UNIT;
+
+        // Trace a ray close to the axis from the point in focus, and move the film
+        // to where it crosses the axis. Moving the film changes the distance to the
+        // point slightly, so repeat until it settles.
+        for _ in 0..8 {
+            let front = lens.length();
+            let height = 0.01 * lens.elements[0].aperture / 2.0;
+            let origin = Vec3::from_point(0.0, 0.0, distance);
+            let direction = Vec3::from_point(height, 0.0, front) - origin;
+
+            let Some((o, d)) = lens.trace_from_scene(origin, direction) else {
+                eprintln!("Could not focus the lens at {} units, as the lens blocks the rays from there", distance / MM_PER_UNIT);
+                break;
+            };
+            if d.x.abs() < 1e-12 {
+                eprintln!("Could not focus the lens at {} units, as the rays from there leave it parallel to the axis", distance / MM_PER_UNIT);
+                break;
+            }
+
+            let crossing = o.z - o.x / d.x * d.z;
+            if let Some(last) = lens.elements.last_mut() {
+                last.thickness = (last.thickness - crossing).max(0.0);
+            }
+        }
+
+        lens.compute_exit_pupil();
+        lens
+    }
+
+    /// Returns half the width and height of the film for the given aspect ratio, in millimetres.
+    pub fn film_extent(&self, aspect_ratio: f32) -> (f32, f32) {
+        let half_height = self.film_diagonal / 2.0 / (1.0 + aspect_ratio * aspect_ratio).sqrt();
+        (aspect_ratio * half_height, half_height)
+    }
+
+    /// Samples a ray leaving the lens, starting from a point ``(x, y)`` on the film.
+    /// Returns the origin and direction in the lens' space, in scene units, or ``None``
+    /// if the ray was blocked inside the lens.
+    ///
+    /// Rays are also dropped at random to darken the edges of the film: each is kept with a
+    /// probability of cos⁴ of its angle to the axis, times the area of the exit pupil seen
+    /// from its point relative to the largest one. On average, this weighs every ray by that.
+    pub fn sample_ray(&self, x: f32, y: f32) -> Option<(Vec3, Vec3)> {
+        let r = (x * x + y * y).sqrt();
+        let ring = ((r / (self.film_diagonal / 2.0) * PUPIL_RINGS as f32) as usize).min(PUPIL_RINGS - 1);
+        let (x_min, x_max, y_min, y_max) = (*self.pupil_bounds.get(ring)?)?;
+
+        // The pupil was found for points on the x axis, so rotate it to the film point
+        let (px, py) = (x_min + random() * (x_max - x_min), y_min + random() * (y_max - y_min));
+        let (sin, cos) = if r > 0.0 { (y / r, x / r) } else { (0.0, 1.0) };
+        let pupil = Vec3::from_point(cos * px - sin * py, sin * px + cos * py, self.rear_z());
+
+        let origin = Vec3::from_point(x, y, 0.0);
+        let cos_theta = (pupil - origin).unit().z;
+        let area = (x_max - x_min) * (y_max - y_min);
+        if random() >= cos_theta.powi(4) * area / self.largest_pupil {
+            return None;
+        }
+
+        let (o, d) = self.trace_from_film(origin, pupil - origin)?;
+
+        Some((o / MM_PER_UNIT, d))
+    }
+
+    /// Distance from the film to the front element
+    fn length(&self) -> f32 {
+        self.elements.iter().map(|x| x.thickness).sum()
+    }
+
+    /// Distance from the film to the rear element
+    fn rear_z(&self) -> f32 {
+        self.elements.last().map_or(0.0, |x| x.thickness)
+    }
+
+    /// Finds the bounds of the exit pupil: the region of the rear element through which
+    /// rays from the film make it out of the lens.
+    fn compute_exit_pupil(&mut self) {
+        const GRID: usize = 64;
+        const FILM_SAMPLES: usize = 4;
+
+        let rear_radius = self.elements.last().map_or(0.0, |x| x.aperture / 2.0);
+        let extent = 1.5 * rear_radius;
+        let cell = 2.0 * extent / GRID as f32;
+        let film_radius = self.film_diagonal / 2.0;
+
+        self.pupil_bounds = (0..PUPIL_RINGS)
+            .map(|ring| {
+                let mut bounds: Option<(f32, f32, f32, f32)> = None;
+
+                for k in 0..FILM_SAMPLES {
+                    let r = film_radius * (ring as f32 + (k as f32 + 0.5) / FILM_SAMPLES as f32) / PUPIL_RINGS as f32;
+                    let origin = Vec3::from_point(r, 0.0, 0.0);
+
+                    for gx in 0..GRID {
+                        for gy in 0..GRID {
+                            let px = -extent + (gx as f32 + 0.5) * cell;
+                            let py = -extent + (gy as f32 + 0.5) * cell;
+                            let target = Vec3::from_point(px, py, self.rear_z());
+
+                            if self.trace_from_film(origin, target - origin).is_some() {
+                                bounds = Some(match bounds {
+                                    None => (px, px, py, py),
+                                    Some((a, b, c, d)) => (a.min(px), b.max(px), c.min(py), d.max(py)),
+                                });
+                            }
+                        }
+                    }
+                }
+
+                // Grow by a cell, as the edges were only found to the grid's precision
+                bounds.map(|(a, b, c, d)| (a - cell, b + cell, c - cell, d + cell))
+            })
+            .collect();
+
+        self.largest_pupil = self
+            .pupil_bounds
+            .iter()
+            .flatten()
+            .map(|(a, b, c, d)| (b - a) * (d - c))
+            .fold(0.0, f32::max);
+    }
+
+    /// Traces a ray from the film out through the lens, from the rear element to the front.
+    fn trace_from_film(&self, origin: Vec3, direction: Vec3) -> Option<(Vec3, Vec3)> {
+        let mut o = origin;
+        let mut d = direction.unit();
+        let mut z = 0.0;
+
+        for (i, element) in self.elements.iter().enumerate().rev() {
+            z += element.thickness;
+
+            let eta_out = self.elements.get(i.wrapping_sub(1)).map_or(1.0, |x| medium(x.eta));
+            (o, d) = self.trace_element(o, d, z, element, medium(element.eta), eta_out)?;
+        }
+
+        Some((o, d))
+    }
+
+    /// Traces a ray from the scene in through the lens, from the front element to the rear.
+    fn trace_from_scene(&self, origin: Vec3, direction: Vec3) -> Option<(Vec3, Vec3)> {
+        let mut o = origin;
+        let mut d = direction.unit();
+        let mut z = self.length();
+
+        for (i, element) in self.elements.iter().enumerate() {
+            let eta_in = if i == 0 { 1.0 } else { medium(self.elements[i - 1].eta) };
+            (o, d) = self.trace_element(o, d, z, element, eta_in, medium(element.eta))?;
+
+            z -= element.thickness;
+        }
+
+        Some((o, d))
+    }
+
+    /// Intersects a ray with the surface of an element whose vertex lies at ``z``, and
+    /// refracts it from a medium of index ``eta_in`` into one of index ``eta_out``.
+    fn trace_element(
+        &self,
+        o: Vec3,
+        d: Vec3,
+        z: f32,
+        element: &LensElement,
+        eta_in: f32,
+        eta_out: f32,
+    ) -> Option<(Vec3, Vec3)> {
+        if element.radius == 0.0 {
+            // The aperture stop is a flat disc
+            if d.z.abs() < 1e-12 {
+                return None;
+            }
+            let hit = o + ((z - o.z) / d.z) * d;
+
+            return (hit.x * hit.x + hit.y * hit.y <= (element.aperture / 2.0).powi(2)).then_some((hit, d));
+        }
+
+        let center = Vec3::from_point(0.0, 0.0, z - element.radius);
+        let oc = o - center;
+        let half_b = oc.dot(d);
+        let c = oc.length_sq() - element.radius * element.radius;
+        let discriminant = half_b * half_b - c;
+        if discriminant < 0.0 {
+            return None;
+        }
+
+        // Of the two intersections with the sphere, pick the one on the side of the vertex
+        let (t0, t1) = (-half_b - discriminant.sqrt(), -half_b + discriminant.sqrt());
+        let closer = (d.z < 0.0) ^ (element.radius < 0.0);
+        let t = if closer { t0 } else { t1 };
+        if t < 0.0 {
+            return None;
+        }
+
+        let hit = o + t * d;
+        if hit.x * hit.x + hit.y * hit.y > (element.aperture / 2.0).powi(2) {
+            return None;
+        }
+
+        // Face the normal against the ray, as refract_along() expects
+        let mut normal = (hit - center).unit();
+        if normal.dot(d) > 0.0 {
+            normal = -normal;
+        }
+
+        let ratio = eta_in / eta_out;
+        let cos_theta = (-d).dot(normal).min(1.0);
+        if ratio * ratio * (1.0 - cos_theta * cos_theta) > 1.0 {
+            return None;
+        }
+
+        Some((hit, d.refract_along(normal, ratio).unit()))
+    }
+}
+
+/// Refractive index of a medium, taking 0 (as used for the aperture stop) to be air.
+fn medium(eta: f32) -> f32 {
+    if eta == 0.0 {
+        1.0
+    } else {
+        eta
+    }
+}
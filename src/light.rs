@@ -0,0 +1,424 @@
+//! Lights that are sampled explicitly when shading a hit (next event estimation),
+//! by tracing a shadow ray from the hit towards the light.
+//!
+//! The lights here are "delta" lights: they are infinitely small (or infinitely far away),
+//! so rays bouncing around the scene can never hit them, and they only contribute light
+//! through explicit sampling. Lights that rays can also hit (like the environment, refer to
+//! [crate::environment::Environment], and [SphereLight]) report the density of their samples,
+//! so both strategies can be combined with multiple importance sampling.
+//!
+//! Only one light is sampled at each hit, chosen by a [crate::light_sampler::LightSampler].
+
+use std::f32::consts::PI;
+
+use crate::light_sampler::Bounds;
+use crate::ray::Ray;
+use crate::spectrum::luminance;
+use crate::utils::random;
+use crate::vec3::{Point3, Vec3};
+
+/// Light arriving at a point from a sampled light.
+pub struct LightSample {
+    /// Unit vector from the point towards the light
+    pub direction: Vec3,
+    /// Distance to the light, used to limit the shadow ray
+    pub distance: f32,
+    /// Incoming radiance, already divided by the probability of the sample
+    pub radiance: Vec3,
+    /// Probability density (per solid angle) of the direction, or 0 for delta lights
+    pub pdf: f32,
+}
+
+/// Light leaving a light, sampled to start a path from the light (Refer to [crate::bdpt]).
+pub struct Emission {
+    pub origin: Point3,
+    /// Unit vector along which the light leaves
+    pub direction: Vec3,
+    /// Normal of the light's surface at the origin, for lights with a surface
+    pub normal: Option<Vec3>,
+    /// Emitted radiance (or intensity, for point lights), in the units carried by the ray
+    pub radiance: Vec3,
+    /// Probability density of the origin, per unit area (1 for point lights)
+    pub pdf_position: f32,
+    /// Probability density of the direction, per solid angle
+    pub pdf_direction: f32,
+}
+
+/// Allows the creation of different lights.
+pub trait Light: Send + Sync {
+    /// Samples the light arriving at ``point``, in the units carried by ``r``
+    /// (Refer to [Ray::sample_colour()]). Returns ``None`` if no light arrives.
+    fn sample(&self, point: Point3, r: &Ray) -> Option<LightSample>;
+
+    /// Returns the probability density of [Light::sample()] choosing ``direction`` from ``point``.
+    /// This is 0 for delta lights, which rays can never hit.
+    fn pdf(&self, _point: Point3, _direction: Vec3) -> f32 {
+        0.0
+    }
+
+    /// Total power given off by the light, as a luminance. This is used to choose
+    /// brighter lights more often.
+    fn power(&self) -> f32;
+
+    /// Box around the light, or ``None`` for lights infinitely far away
+    fn bounds(&self) -> Option<Bounds>;
+
+    /// Whether the light is infinitely small, or shines in a single direction,
+    /// so that rays can never hit it
+    fn is_delta(&self) -> bool {
+        false
+    }
+
+    /// Samples light leaving the light, in the units carried by ``r``. Lights infinitely
+    /// far away cannot start paths, and return ``None``.
+    fn emit(&self, _r: &Ray) -> Option<Emission> {
+        None
+    }
+
+    /// Returns the densities of [Light::emit()] choosing ``point`` (on the light) and
+    /// ``direction``, as (position, direction).
+    fn emit_pdf(&self, _point: Point3, _direction: Vec3) -> (f32, f32) {
+        (0.0, 0.0)
+    }
+
+    /// Normal of the light's surface at ``point``, for lights with a surface
+    fn normal(&self, _point: Point3) -> Option<Vec3> {
+        None
+    }
+}
+
+/// Returns a unit vector in the cone of directions within ``acos(cos_max)`` of ``axis``,
+/// sampled uniformly.
+fn sample_cone(axis: Vec3, cos_max: f32) -> Vec3 {
+    let cos_theta = 1.0 - random() * (1.0 - cos_max);
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * random();
+
+    let (u, v) = orthonormal(axis);
+    sin_theta * (phi.cos() * u + phi.sin() * v) + cos_theta * axis
+}
+
+/// Returns two unit vectors perpendicular to ``axis`` and each other
+fn orthonormal(axis: Vec3) -> (Vec3, Vec3) {
+    let helper = if axis.x.abs() > 0.9 {
+        Vec3::from_point(0.0, 1.0, 0.0)
+    } else {
+        Vec3::from_point(1.0, 0.0, 0.0)
+    };
+    let u = helper.cross(axis).unit();
+    (u, axis.cross(u))
+}
+
+/// Weighs a sample taken with density ``pdf`` against another strategy that could
+/// have produced it with density ``other`` (Veach's power heuristic).
+pub fn power_heuristic(pdf: f32, other: f32) -> f32 {
+    let (a, b) = (pdf * pdf, other * other);
+    if a + b > 0.0 {
+        a / (a + b)
+    } else {
+        0.0
+    }
+}
+
+/// A light that shines equally in all directions from a single point.
+/// Light falls off with the square of the distance.
+pub struct PointLight {
+    pub position: Point3,
+    /// Radiant intensity, per colour channel
+    pub intensity: Vec3,
+}
+
+#[allow(dead_code)]
+impl PointLight {
+    pub fn new(position: Point3, intensity: Vec3) -> Self {
+        Self { position, intensity }
+    }
+}
+
+impl Light for PointLight {
+    fn sample(&self, point: Point3, r: &Ray) -> Option<LightSample> {
+        let offset = self.position - point;
+        let distance = offset.length();
+
+        Some(LightSample {
+            direction: offset / distance,
+            distance,
+            radiance: r.sample_colour(self.intensity) / (distance * distance),
+            pdf: 0.0,
+        })
+    }
+
+    fn power(&self) -> f32 {
+        4.0 * PI * luminance(self.intensity)
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        Some(Bounds::point(self.position))
+    }
+
+    fn is_delta(&self) -> bool {
+        true
+    }
+
+    fn emit(&self, r: &Ray) -> Option<Emission> {
+        Some(Emission {
+            origin: self.position,
+            direction: sample_cone(Vec3::from_point(0.0, 1.0, 0.0), -1.0),
+            normal: None,
+            radiance: r.sample_colour(self.intensity),
+            pdf_position: 1.0,
+            pdf_direction: 1.0 / (4.0 * PI),
+        })
+    }
+
+    fn emit_pdf(&self, _point: Point3, _direction: Vec3) -> (f32, f32) {
+        (1.0, 1.0 / (4.0 * PI))
+    }
+}
+
+/// A point light that only shines in a cone around ``direction``.
+/// The light is at full strength within ``falloff_start`` degrees of the direction,
+/// fading smoothly to nothing at ``cone_angle`` degrees.
+pub struct SpotLight {
+    pub position: Point3,
+    pub direction: Vec3,
+    pub intensity: Vec3,
+    pub cone_angle: f32,
+    pub falloff_start: f32,
+}
+
+#[allow(dead_code)]
+impl SpotLight {
+    pub fn new(position: Point3, direction: Vec3, intensity: Vec3, cone_angle: f32, falloff_start: f32) -> Self {
+        Self {
+            position,
+            direction: direction.unit(),
+            intensity,
+            cone_angle,
+            falloff_start: falloff_start.min(cone_angle),
+        }
+    }
+
+    /// Fraction of the intensity emitted at an angle (given by its cosine) from the direction
+    fn falloff(&self, cos_theta: f32) -> f32 {
+        let cos_outer = self.cone_angle.to_radians().cos();
+        let cos_inner = self.falloff_start.to_radians().cos();
+
+        if cos_theta >= cos_inner {
+            1.0
+        } else if cos_theta <= cos_outer {
+            0.0
+        } else {
+            let t = (cos_theta - cos_outer) / (cos_inner - cos_outer);
+            t * t * (3.0 - 2.0 * t)
+        }
+    }
+}
+
+impl Light for SpotLight {
+    fn sample(&self, point: Point3, r: &Ray) -> Option<LightSample> {
+        let offset = self.position - point;
+        let distance = offset.length();
+        let direction = offset / distance;
+
+        let falloff = self.falloff((-direction).dot(self.direction));
+        if falloff <= 0.0 {
+            return None;
+        }
+
+        Some(LightSample {
+            direction,
+            distance,
+            radiance: falloff * r.sample_colour(self.intensity) / (distance * distance),
+            pdf: 0.0,
+        })
+    }
+
+    fn power(&self) -> f32 {
+        // The smooth falloff is about halfway between the inner and outer cones
+        let cos_outer = self.cone_angle.to_radians().cos();
+        let cos_inner = self.falloff_start.to_radians().cos();
+
+        2.0 * PI * (1.0 - 0.5 * (cos_inner + cos_outer)) * luminance(self.intensity)
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        Some(Bounds::point(self.position))
+    }
+
+    fn is_delta(&self) -> bool {
+        true
+    }
+
+    fn emit(&self, r: &Ray) -> Option<Emission> {
+        // Directions are sampled uniformly in the cone, and weighted by the falloff
+        let cos_outer = self.cone_angle.to_radians().cos();
+        let direction = sample_cone(self.direction, cos_outer);
+
+        Some(Emission {
+            origin: self.position,
+            direction,
+            normal: None,
+            radiance: self.falloff(direction.dot(self.direction)) * r.sample_colour(self.intensity),
+            pdf_position: 1.0,
+            pdf_direction: 1.0 / (2.0 * PI * (1.0 - cos_outer)),
+        })
+    }
+
+    fn emit_pdf(&self, _point: Point3, direction: Vec3) -> (f32, f32) {
+        let cos_outer = self.cone_angle.to_radians().cos();
+        if direction.unit().dot(self.direction) < cos_outer {
+            return (1.0, 0.0);
+        }
+
+        (1.0, 1.0 / (2.0 * PI * (1.0 - cos_outer)))
+    }
+}
+
+/// A light infinitely far away, like the sun, whose light arrives from (nearly) the same
+/// direction everywhere. A non-zero ``angular_diameter`` (in degrees) spreads the light
+/// over a small disc in the sky, which softens shadows.
+pub struct DirectionalLight {
+    /// Direction in which the light travels
+    pub direction: Vec3,
+    /// Irradiance on a surface facing the light
+    pub irradiance: Vec3,
+    pub angular_diameter: f32,
+}
+
+#[allow(dead_code)]
+impl DirectionalLight {
+    pub fn new(direction: Vec3, irradiance: Vec3, angular_diameter: f32) -> Self {
+        Self {
+            direction: direction.unit(),
+            irradiance,
+            angular_diameter,
+        }
+    }
+}
+
+impl Light for DirectionalLight {
+    fn sample(&self, _point: Point3, r: &Ray) -> Option<LightSample> {
+        // Sample a direction uniformly in the cone covered by the disc
+        let cos_max = (self.angular_diameter.to_radians() / 2.0).cos();
+
+        Some(LightSample {
+            direction: sample_cone(-self.direction, cos_max),
+            distance: f32::INFINITY,
+            radiance: r.sample_colour(self.irradiance),
+            pdf: 0.0,
+        })
+    }
+
+    /// Infinitely far away lights have no finite power (Refer to [crate::light_sampler])
+    fn power(&self) -> f32 {
+        0.0
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        None
+    }
+
+    fn is_delta(&self) -> bool {
+        true
+    }
+}
+
+/// A glowing sphere, giving off the same ``radiance`` everywhere on its surface.
+/// The sphere itself is an object in the world with a [crate::material::DiffuseLight]
+/// material, which is what rays see (Refer to [crate::scene::Scene::push_area_light()]).
+pub struct SphereLight {
+    pub centre: Point3,
+    pub radius: f32,
+    pub radiance: Vec3,
+}
+
+#[allow(dead_code)]
+impl SphereLight {
+    pub fn new(centre: Point3, radius: f32, radiance: Vec3) -> Self {
+        Self { centre, radius, radiance }
+    }
+
+    /// Cosine of the half angle of the cone the sphere covers, seen from ``point``.
+    /// Points inside the sphere cannot see it as a cone.
+    fn cos_max(&self, point: Point3) -> Option<f32> {
+        let distance_sq = (self.centre - point).length_sq();
+        let radius_sq = self.radius * self.radius;
+        if distance_sq <= radius_sq {
+            return None;
+        }
+
+        Some((1.0 - radius_sq / distance_sq).max(0.0).sqrt())
+    }
+}
+
+impl Light for SphereLight {
+    fn sample(&self, point: Point3, r: &Ray) -> Option<LightSample> {
+        let cos_max = self.cos_max(point)?;
+        let offset = self.centre - point;
+
+        // Sample a direction uniformly in the cone covered by the sphere
+        let direction = sample_cone(offset.unit(), cos_max);
+
+        // Distance to the near side of the sphere along the direction
+        let along = offset.dot(direction);
+        let distance = along - (self.radius * self.radius - (offset.length_sq() - along * along)).max(0.0).sqrt();
+
+        let pdf = 1.0 / (2.0 * PI * (1.0 - cos_max));
+        Some(LightSample {
+            direction,
+            distance,
+            radiance: r.sample_colour(self.radiance) / pdf,
+            pdf,
+        })
+    }
+
+    fn pdf(&self, point: Point3, direction: Vec3) -> f32 {
+        match self.cos_max(point) {
+            Some(cos_max) if direction.unit().dot((self.centre - point).unit()) >= cos_max => {
+                1.0 / (2.0 * PI * (1.0 - cos_max))
+            }
+            _ => 0.0,
+        }
+    }
+
+    fn power(&self) -> f32 {
+        // Every point of the surface gives off π times its radiance
+        PI * 4.0 * PI * self.radius * self.radius * luminance(self.radiance)
+    }
+
+    fn bounds(&self) -> Option<Bounds> {
+        let extent = Vec3::from_point(self.radius, self.radius, self.radius);
+        Some(Bounds { min: self.centre - extent, max: self.centre + extent })
+    }
+
+    fn emit(&self, r: &Ray) -> Option<Emission> {
+        // A uniform point on the surface, and a cosine weighted direction away from it
+        let normal = sample_cone(Vec3::from_point(0.0, 1.0, 0.0), -1.0);
+        let (u, v) = orthonormal(normal);
+        let (sin_theta, phi) = (random().sqrt(), 2.0 * PI * random());
+        let cos_theta = (1.0 - sin_theta * sin_theta).max(0.0).sqrt();
+        let direction = sin_theta * (phi.cos() * u + phi.sin() * v) + cos_theta * normal;
+
+        Some(Emission {
+            origin: self.centre + self.radius * normal,
+            direction,
+            normal: Some(normal),
+            radiance: r.sample_colour(self.radiance),
+            pdf_position: 1.0 / (4.0 * PI * self.radius * self.radius),
+            pdf_direction: cos_theta / PI,
+        })
+    }
+
+    fn emit_pdf(&self, point: Point3, direction: Vec3) -> (f32, f32) {
+        let normal = (point - self.centre).unit();
+        (
+            1.0 / (4.0 * PI * self.radius * self.radius),
+            normal.dot(direction.unit()).max(0.0) / PI,
+        )
+    }
+
+    fn normal(&self, point: Point3) -> Option<Vec3> {
+        Some((point - self.centre).unit())
+    }
+}
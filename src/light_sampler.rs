@@ -0,0 +1,380 @@
+//! Choosing which light to sample at a hit. With many lights, sampling all of them at every
+//! hit is too slow, so a single light is chosen instead, and its contribution divided by the
+//! probability of choosing it. The better the choice matches the light actually arriving,
+//! the less noise there is.
+//!
+//! Lights are referred to by their index in the list given to [LightSelection::build()].
+//! Lights infinitely far away (like the sun, or the environment) have no position or finite
+//! power to compare against the others. Each of them is given one share, and the lights with
+//! bounds together are given one more, which they split by power (or position). A scene lit
+//! by the sun and a few lamps thus samples the sun half of the time: there is no telling
+//! whether the sun or the lamps are brighter, and neither can be left without samples.
+
+use crate::distribution::Distribution1D;
+use crate::light::Light;
+use crate::vec3::{Point3, Vec3};
+
+/// An axis aligned box
+#[derive(Debug, Clone, Copy)]
+pub struct Bounds {
+    pub min: Point3,
+    pub max: Point3,
+}
+
+impl Bounds {
+    /// A box around a single point
+    pub fn point(p: Point3) -> Self {
+        Self { min: p, max: p }
+    }
+
+    /// The smallest box containing both boxes
+    pub fn union(&self, other: &Bounds) -> Self {
+        Self {
+            min: Vec3::from_point(self.min.x.min(other.min.x), self.min.y.min(other.min.y), self.min.z.min(other.min.z)),
+            max: Vec3::from_point(self.max.x.max(other.max.x), self.max.y.max(other.max.y), self.max.z.max(other.max.z)),
+        }
+    }
+
+    pub fn centre(&self) -> Point3 {
+        0.5 * (self.min + self.max)
+    }
+
+    pub fn diagonal(&self) -> Vec3 {
+        self.max - self.min
+    }
+}
+
+/// Chooses a light to sample at a point.
+pub trait LightSampler: Send + Sync {
+    /// Chooses a light for ``point`` using the uniform random number ``u``, returning its
+    /// index and the probability of choosing it. Returns ``None`` if there is nothing to choose.
+    fn sample(&self, point: Point3, u: f32) -> Option<(usize, f32)>;
+
+    /// Probability of [LightSampler::sample()] choosing the light ``index`` for ``point``
+    fn pmf(&self, point: Point3, index: usize) -> f32;
+}
+
+/// How lights are chosen (Refer to [crate::scene::Scene::set_light_selection()]).
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+pub enum LightSelection {
+    /// Every light is equally likely
+    Uniform,
+    /// Lights are chosen in proportion to their power
+    Power,
+    /// Lights are grouped into a tree, which is walked down choosing the branch that is
+    /// likely to give more light at the point, judged by power and distance
+    Bvh,
+    /// [LightSelection::Power] for a few lights, and [LightSelection::Bvh] for many
+    Automatic,
+}
+
+/// Number of lights above which [LightSelection::Automatic] uses a tree
+const AUTOMATIC_BVH_THRESHOLD: usize = 16;
+
+impl LightSelection {
+    /// Creates a sampler choosing between the given lights
+    pub fn build(&self, lights: &[&dyn Light]) -> Box<dyn LightSampler> {
+        match self {
+            LightSelection::Uniform => Box::new(UniformLightSampler { count: lights.len() }),
+            LightSelection::Power => Box::new(PowerLightSampler::new(lights)),
+            LightSelection::Bvh => Box::new(BvhLightSampler::new(lights)),
+            LightSelection::Automatic if lights.len() > AUTOMATIC_BVH_THRESHOLD => {
+                Box::new(BvhLightSampler::new(lights))
+            }
+            LightSelection::Automatic => Box::new(PowerLightSampler::new(lights)),
+        }
+    }
+}
+
+/// Chooses every light with the same probability.
+pub struct UniformLightSampler {
+    count: usize,
+}
+
+impl LightSampler for UniformLightSampler {
+    fn sample(&self, _point: Point3, u: f32) -> Option<(usize, f32)> {
+        if self.count == 0 {
+            return None;
+        }
+
+        Some((((u * self.count as f32) as usize).min(self.count - 1), 1.0 / self.count as f32))
+    }
+
+    fn pmf(&self, _point: Point3, index: usize) -> f32 {
+        if index < self.count {
+            1.0 / self.count as f32
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Splits a list of lights into those with bounds and those infinitely far away,
+/// returning both (as indices), and the probability of choosing one of the latter:
+/// one share for each of them, out of one more for all the lights with bounds.
+fn split_infinite(lights: &[&dyn Light]) -> (Vec<usize>, Vec<usize>, f32) {
+    let (finite, infinite): (Vec<usize>, Vec<usize>) = (0..lights.len()).partition(|&i| lights[i].bounds().is_some());
+
+    let shares = infinite.len() + if finite.is_empty() { 0 } else { 1 };
+    let p_infinite = if shares == 0 { 0.0 } else { infinite.len() as f32 / shares as f32 };
+
+    (finite, infinite, p_infinite)
+}
+
+/// Chooses one of the infinitely far away lights, given a random number remapped to [0, 1).
+fn sample_infinite(infinite: &[usize], p_infinite: f32, u: f32) -> (usize, f32) {
+    let k = ((u * infinite.len() as f32) as usize).min(infinite.len() - 1);
+    (infinite[k], p_infinite / infinite.len() as f32)
+}
+
+/// Chooses lights in proportion to their power.
+pub struct PowerLightSampler {
+    finite: Vec<usize>,
+    infinite: Vec<usize>,
+    p_infinite: f32,
+    distribution: Option<Distribution1D>,
+    /// Position of each light in ``finite``, or in ``infinite``
+    position: Vec<usize>,
+}
+
+impl PowerLightSampler {
+    pub fn new(lights: &[&dyn Light]) -> Self {
+        let (finite, infinite, p_infinite) = split_infinite(lights);
+
+        let distribution = if finite.is_empty() {
+            None
+        } else {
+            Some(Distribution1D::new(finite.iter().map(|&i| lights[i].power()).collect()))
+        };
+
+        let mut position = vec![0; lights.len()];
+        for (k, &i) in finite.iter().chain(infinite.iter()).enumerate() {
+            position[i] = if k < finite.len() { k } else { k - finite.len() };
+        }
+
+        Self { finite, infinite, p_infinite, distribution, position }
+    }
+}
+
+impl LightSampler for PowerLightSampler {
+    fn sample(&self, _point: Point3, u: f32) -> Option<(usize, f32)> {
+        if u < self.p_infinite {
+            return Some(sample_infinite(&self.infinite, self.p_infinite, u / self.p_infinite));
+        }
+
+        let distribution = self.distribution.as_ref()?;
+        let u = (u - self.p_infinite) / (1.0 - self.p_infinite);
+        let (_, _, piece) = distribution.sample(u);
+
+        Some((self.finite[piece], (1.0 - self.p_infinite) * distribution.pdf_at(piece) / distribution.count() as f32))
+    }
+
+    fn pmf(&self, _point: Point3, index: usize) -> f32 {
+        let Some(&k) = self.position.get(index) else {
+            return 0.0;
+        };
+
+        match &self.distribution {
+            Some(distribution) if self.finite.get(k) == Some(&index) => {
+                (1.0 - self.p_infinite) * distribution.pdf_at(k) / distribution.count() as f32
+            }
+            _ => self.p_infinite / self.infinite.len() as f32,
+        }
+    }
+}
+
+/// A node of a [BvhLightSampler]
+enum Node {
+    Leaf {
+        light: usize,
+        bounds: Bounds,
+        power: f32,
+    },
+    Interior {
+        left: usize,
+        right: usize,
+        bounds: Bounds,
+        power: f32,
+    },
+}
+
+impl Node {
+    fn bounds(&self) -> &Bounds {
+        match self {
+            Node::Leaf { bounds, .. } | Node::Interior { bounds, .. } => bounds,
+        }
+    }
+
+    fn power(&self) -> f32 {
+        match self {
+            Node::Leaf { power, .. } | Node::Interior { power, .. } => *power,
+        }
+    }
+
+    /// Rough estimate of the light arriving at ``point`` from everything in the node.
+    /// The distance is never taken as less than the size of the node, so that nearby
+    /// nodes are not infinitely important.
+    fn importance(&self, point: Point3) -> f32 {
+        let bounds = self.bounds();
+        let distance_sq = (bounds.centre() - point).length_sq();
+        let size_sq = 0.25 * bounds.diagonal().length_sq();
+
+        self.power() / distance_sq.max(size_sq).max(1e-6)
+    }
+}
+
+/// Chooses lights by walking down a bounding volume hierarchy of them, choosing each branch
+/// in proportion to its (estimated) importance at the shading point. Nearby lights are
+/// chosen more often, so scenes with hundreds of small lights remain quick to converge.
+pub struct BvhLightSampler {
+    nodes: Vec<Node>,
+    infinite: Vec<usize>,
+    p_infinite: f32,
+    /// For each light, the branches leading to it from the root (as bits, 1 for right),
+    /// and how many there are. Infinitely far away lights have no path.
+    paths: Vec<Option<(u64, u32)>>,
+}
+
+impl BvhLightSampler {
+    pub fn new(lights: &[&dyn Light]) -> Self {
+        let (finite, infinite, p_infinite) = split_infinite(lights);
+
+        let mut sampler = Self {
+            nodes: Vec::new(),
+            infinite,
+            p_infinite,
+            paths: vec![None; lights.len()],
+        };
+
+        let mut leaves: Vec<(usize, Bounds, f32)> = finite
+            .iter()
+            .map(|&i| (i, lights[i].bounds().unwrap(), lights[i].power()))
+            .collect();
+        if !leaves.is_empty() {
+            sampler.build(&mut leaves, 0, 0);
+        }
+
+        sampler
+    }
+
+    /// Builds the node for a group of lights, splitting it in half along the longest axis
+    /// of their centres. Returns the index of the node.
+    fn build(&mut self, leaves: &mut [(usize, Bounds, f32)], path: u64, depth: u32) -> usize {
+        if let [(light, bounds, power)] = *leaves {
+            self.paths[light] = Some((path, depth));
+            self.nodes.push(Node::Leaf { light, bounds, power });
+            return self.nodes.len() - 1;
+        }
+
+        let centres = leaves
+            .iter()
+            .map(|x| Bounds::point(x.1.centre()))
+            .reduce(|a, b| a.union(&b))
+            .unwrap();
+        let extent = centres.diagonal();
+        let axis = if extent.x >= extent.y && extent.x >= extent.z {
+            0
+        } else if extent.y >= extent.z {
+            1
+        } else {
+            2
+        };
+        leaves.sort_by(|a, b| a.1.centre()[axis].total_cmp(&b.1.centre()[axis]));
+
+        // The node is pushed first, so that it can be found again once its children exist
+        let index = self.nodes.len();
+        self.nodes.push(Node::Leaf { light: 0, bounds: leaves[0].1, power: 0.0 });
+
+        let (first, second) = leaves.split_at_mut(leaves.len() / 2);
+        let left = self.build(first, path, depth + 1);
+        let right = self.build(second, path | (1 << depth), depth + 1);
+
+        self.nodes[index] = Node::Interior {
+            left,
+            right,
+            bounds: self.nodes[left].bounds().union(self.nodes[right].bounds()),
+            power: self.nodes[left].power() + self.nodes[right].power(),
+        };
+        index
+    }
+
+    /// Probability of going down the left branch of an interior node
+    fn p_left(&self, left: usize, right: usize, point: Point3) -> Option<f32> {
+        let (a, b) = (self.nodes[left].importance(point), self.nodes[right].importance(point));
+        if a + b > 0.0 {
+            Some(a / (a + b))
+        } else {
+            None
+        }
+    }
+}
+
+impl LightSampler for BvhLightSampler {
+    fn sample(&self, point: Point3, u: f32) -> Option<(usize, f32)> {
+        if u < self.p_infinite {
+            return Some(sample_infinite(&self.infinite, self.p_infinite, u / self.p_infinite));
+        }
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let mut u = (u - self.p_infinite) / (1.0 - self.p_infinite);
+        let mut pmf = 1.0 - self.p_infinite;
+        let mut node = 0;
+
+        loop {
+            match self.nodes[node] {
+                Node::Leaf { light, .. } => return Some((light, pmf)),
+                Node::Interior { left, right, .. } => {
+                    let p = self.p_left(left, right, point)?;
+
+                    // The random number is reused for the next choice
+                    if u < p {
+                        u /= p;
+                        pmf *= p;
+                        node = left;
+                    } else {
+                        u = ((u - p) / (1.0 - p)).min(1.0 - f32::EPSILON);
+                        pmf *= 1.0 - p;
+                        node = right;
+                    }
+                }
+            }
+        }
+    }
+
+    fn pmf(&self, point: Point3, index: usize) -> f32 {
+        let Some(&path) = self.paths.get(index) else {
+            return 0.0;
+        };
+        let Some((path, depth)) = path else {
+            return if self.infinite.contains(&index) {
+                self.p_infinite / self.infinite.len() as f32
+            } else {
+                0.0
+            };
+        };
+
+        let mut pmf = 1.0 - self.p_infinite;
+        let mut node = 0;
+        for level in 0..depth {
+            let Node::Interior { left, right, .. } = self.nodes[node] else {
+                break;
+            };
+            let Some(p) = self.p_left(left, right, point) else {
+                return 0.0;
+            };
+
+            if path & (1 << level) == 0 {
+                pmf *= p;
+                node = left;
+            } else {
+                pmf *= 1.0 - p;
+                node = right;
+            }
+        }
+
+        pmf
+    }
+}
@@ -1,42 +1,402 @@
+use std::env;
+use std::io;
+use std::ops::Range;
+use std::process;
 use std::sync::Arc;
 
-use camera::Camera;
+use animation::{CameraAnimation, Curve, Interpolation, PerFrame};
+use camera::{AdaptiveSampling, Camera, Integrator, Progressive, Region, RegionBounds};
+use checkpoint::Checkpoint;
+use denoise::DenoiseSettings;
+use mlt::MetropolisSettings;
+use photon::PhotonSettings;
+use environment::Environment;
 use hittable::World;
+use scene::Scene;
+use sky::Sky;
 use material::{Material, Dielectric, Lambertian, Metal};
 use sphere::Sphere;
 use vec3::Vec3;
 use ray::Ray;
-use utils::{random, random_range};
+use utils::{random, random_range, seed_random};
 use texture::Solid;
 
+mod animation;
+mod bdpt;
 mod camera;
+mod checkpoint;
+mod denoise;
+mod cutout;
+mod distribution;
+mod environment;
 mod hittable;
+mod image;
+mod lens;
 mod material;
+mod mlt;
+mod light;
+mod light_sampler;
+mod photon;
+mod photon_map;
 mod ray;
+mod scene;
+mod sky;
 mod sphere;
+mod spectrum;
 mod utils;
 mod vec3;
 mod texture;
+mod transform;
+
+/// Options given on the command line:
+/// - ``--frames START..END``: Renders a turntable animation of the scene into numbered images,
+///   instead of a single image to stdout. START must come before END, and the render cannot
+///   be progressive.
+/// - ``--fps FPS``: Frames per second of the animation. Defaults to 24.
+/// - ``--output PREFIX``: Prefix of the numbered images. Defaults to ``frame_``.
+/// - ``--environment FILE``: Lights the scene with an equirectangular ``.hdr`` image,
+///   instead of the sky gradient.
+/// - ``--environment-rotation DEGREES``: Turns the environment around the up axis.
+/// - ``--environment-intensity SCALE``: Scales the brightness of the environment.
+/// - ``--sky ELEVATION,AZIMUTH[,TURBIDITY]``: Lights the scene with a clear sky and the sun,
+///   placed at the given angles (in degrees). Turbidity defaults to 3. Cannot be used with ``--environment``.
+/// - ``--integrator path|bdpt|photon|sppm|mlt``: Traces paths from the camera only (the default),
+///   from both the camera and the lights (Refer to [bdpt]), gathers light from photons
+///   traced from the lights, once or in progressive passes (Refer to [photon]), or mutates
+///   the paths of the path tracer (Refer to [mlt]). Photons cannot be traced from ``--sky``
+///   or ``--environment``.
+/// - ``--colour rgb|spectral``: Carries light as RGB colours (the default), or as a set of
+///   wavelengths per ray (Refer to [spectrum]), which is slower but reproduces dispersion.
+/// - ``--photons COUNT``: Photons traced for each photon map, or in each progressive pass.
+///   This and ``--photon-radius`` need ``--integrator photon`` or ``sppm``.
+/// - ``--photon-radius RADIUS``: Largest distance photons are gathered from, or the starting
+///   distance in progressive passes, in scene units. Defaults to 0.25.
+/// - ``--adaptive THRESHOLD``: Stops taking samples in a pixel once the relative error of its
+///   brightness is below the threshold (Refer to [AdaptiveSampling]).
+/// - ``--heat-map FILE``: Writes the number of samples taken by every pixel to a PPM image,
+///   with adaptive sampling.
+/// - ``--progressive FILE``: Renders in passes over the whole image, saving it to the file as it
+///   goes (Refer to [Progressive]). The options below also turn this on, saving to ``progress.ppm``.
+/// - ``--save-every N|Ns``: Saves the image every ``N`` passes, or every ``N`` seconds. Defaults to every pass.
+/// - ``--time-limit SECONDS``: Stops starting passes after the given time.
+/// - ``--noise TARGET``: Stops once the average relative error of the pixels is below the target.
+/// - ``--checkpoint FILE``: Saves the state of the render to the file along with the image.
+/// - ``--resume FILE``: Continues the render saved in the checkpoint, and keeps saving to it.
+/// - ``--denoise PASSES``: Removes noise from the image with the given number of passes of the
+///   denoiser (Refer to [denoise]). 5 is a good start.
+/// - ``--resolution PERCENT``: Scales the size of the image, keeping the framing.
+/// - ``--region X0,Y0,X1,Y1``: Renders only the pixels from ``X0,Y0`` up to (not including) ``X1,Y1``.
+/// - ``--border X0,Y0,X1,Y1``: Renders only the given part of the image, in fractions of its size.
+/// - ``--canvas region|full``: Writes only the region (the default), or the whole image
+///   with the rest black.
+/// - ``--seed SEED``: Seeds the random numbers of the scene and of the samples, to repeat a render.
+struct Options {
+    frames: Option<Range<u32>>,
+    fps: f32,
+    output: String,
+    environment: Option<String>,
+    environment_rotation: f32,
+    environment_intensity: f32,
+    sky: Option<Sky>,
+    integrator: Integrator,
+    spectral: bool,
+    photons: Option<usize>,
+    photon_radius: Option<f32>,
+    adaptive: Option<AdaptiveSampling>,
+    progressive: Option<Progressive>,
+    denoise: Option<DenoiseSettings>,
+    resolution: f32,
+    region: Option<RegionBounds>,
+    full_canvas: bool,
+    seed: Option<u64>,
+}
+
+fn parse_options() -> Options {
+    let mut options = Options {
+        frames: None,
+        fps: 24.0,
+        output: String::from("frame_"),
+        environment: None,
+        environment_rotation: 0.0,
+        environment_intensity: 1.0,
+        sky: None,
+        integrator: Integrator::Path,
+        spectral: false,
+        photons: None,
+        photon_radius: None,
+        adaptive: None,
+        progressive: None,
+        denoise: None,
+        resolution: 100.0,
+        region: None,
+        full_canvas: false,
+        seed: None,
+    };
+    let mut args = env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        let value = args.next().unwrap_or_else(|| usage(&format!("Missing value for {}", arg)));
+
+        match arg.as_str() {
+            "--frames" => {
+                let range = value
+                    .split_once("..")
+                    .and_then(|(start, end)| Some(start.parse().ok()?..end.parse().ok()?))
+                    .filter(|range: &Range<u32>| !range.is_empty());
+                options.frames = Some(range.unwrap_or_else(|| usage("Expected frames as START..END, with START before END")));
+            }
+            "--fps" => {
+                options.fps = value
+                    .parse()
+                    .ok()
+                    .filter(|&fps: &f32| fps > 0.0 && fps.is_finite())
+                    .unwrap_or_else(|| usage("Expected a positive number for --fps"))
+            }
+            "--output" => options.output = value,
+            "--environment" => options.environment = Some(value),
+            "--environment-rotation" => {
+                options.environment_rotation = value.parse().unwrap_or_else(|_| usage("Expected degrees for --environment-rotation"))
+            }
+            "--environment-intensity" => {
+                options.environment_intensity = value.parse().unwrap_or_else(|_| usage("Expected a number for --environment-intensity"))
+            }
+            "--sky" => {
+                let numbers: Option<Vec<f32>> = value.split(',').map(|x| x.parse().ok()).collect();
+                options.sky = match numbers.as_deref() {
+                    Some(&[elevation, azimuth]) => Some(Sky::new(elevation, azimuth, 3.0)),
+                    Some(&[elevation, azimuth, turbidity]) => Some(Sky::new(elevation, azimuth, turbidity)),
+                    _ => usage("Expected the sky as ELEVATION,AZIMUTH[,TURBIDITY]"),
+                };
+            }
+            "--integrator" => {
+                options.integrator = match value.as_str() {
+                    "path" => Integrator::Path,
+                    "bdpt" => Integrator::Bidirectional,
+                    "photon" => Integrator::PhotonMapping(PhotonSettings::default()),
+                    "sppm" => Integrator::ProgressivePhotonMapping(PhotonSettings::default()),
+                    "mlt" => Integrator::Metropolis(MetropolisSettings::default()),
+                    _ => usage("Expected path, bdpt, photon, sppm or mlt for --integrator"),
+                };
+            }
+            "--colour" => {
+                options.spectral = match value.as_str() {
+                    "rgb" => false,
+                    "spectral" => true,
+                    _ => usage("Expected rgb or spectral for --colour"),
+                };
+            }
+            "--photons" => options.photons = Some(value.parse().unwrap_or_else(|_| usage("Expected a number for --photons"))),
+            "--photon-radius" => {
+                let radius = value.parse().ok().filter(|&radius: &f32| radius > 0.0 && radius.is_finite());
+                options.photon_radius = Some(radius.unwrap_or_else(|| usage("Expected a positive number for --photon-radius")));
+            }
+            "--adaptive" => {
+                let threshold = value.parse().ok().filter(|&threshold: &f32| threshold > 0.0 && threshold.is_finite());
+                let threshold = threshold.unwrap_or_else(|| usage("Expected a positive number for --adaptive"));
+                options.adaptive.get_or_insert_with(AdaptiveSampling::default).threshold = threshold;
+            }
+            "--heat-map" => options.adaptive.get_or_insert_with(AdaptiveSampling::default).heat_map = Some(value.into()),
+            "--progressive" => options.progressive.get_or_insert_with(Progressive::default).output = value.into(),
+            "--save-every" => {
+                let progressive = options.progressive.get_or_insert_with(Progressive::default);
+                match value.strip_suffix('s') {
+                    Some(seconds) => {
+                        progressive.save_seconds = Some(seconds.parse().unwrap_or_else(|_| usage("Expected N or Ns for --save-every")));
+                        progressive.save_passes = None;
+                    }
+                    None => progressive.save_passes = Some(value.parse().unwrap_or_else(|_| usage("Expected N or Ns for --save-every"))),
+                }
+            }
+            "--time-limit" => {
+                let seconds = value.parse().ok().filter(|&seconds: &f32| seconds > 0.0 && seconds.is_finite());
+                let seconds = seconds.unwrap_or_else(|| usage("Expected a positive number of seconds for --time-limit"));
+                options.progressive.get_or_insert_with(Progressive::default).time_limit = Some(seconds);
+            }
+            "--noise" => {
+                let noise = value.parse().ok().filter(|&noise: &f32| noise > 0.0 && noise.is_finite());
+                let noise = noise.unwrap_or_else(|| usage("Expected a positive number for --noise"));
+                options.progressive.get_or_insert_with(Progressive::default).noise = Some(noise);
+            }
+            "--checkpoint" | "--resume" => {
+                let progressive = options.progressive.get_or_insert_with(Progressive::default);
+                progressive.checkpoint = Some(value.into());
+                progressive.resume = arg == "--resume";
+            }
+            "--denoise" => {
+                let iterations = value.parse().unwrap_or_else(|_| usage("Expected a number for --denoise"));
+                options.denoise = Some(DenoiseSettings { iterations, ..Default::default() });
+            }
+            "--resolution" => options.resolution = value.parse().unwrap_or_else(|_| usage("Expected a percentage for --resolution")),
+            "--region" => {
+                let numbers: Option<Vec<u32>> = value.split(',').map(|x| x.parse().ok()).collect();
+                options.region = match numbers.as_deref() {
+                    Some(&[x0, y0, x1, y1]) => Some(RegionBounds::Pixels { x0, y0, x1, y1 }),
+                    _ => usage("Expected the region as X0,Y0,X1,Y1 in pixels"),
+                };
+            }
+            "--border" => {
+                let numbers: Option<Vec<f32>> = value.split(',').map(|x| x.parse().ok()).collect();
+                options.region = match numbers.as_deref() {
+                    Some(&[x0, y0, x1, y1]) => Some(RegionBounds::Border { x0, y0, x1, y1 }),
+                    _ => usage("Expected the border as X0,Y0,X1,Y1 in fractions of the image"),
+                };
+            }
+            "--canvas" => {
+                options.full_canvas = match value.as_str() {
+                    "region" => false,
+                    "full" => true,
+                    _ => usage("Expected region or full for --canvas"),
+                };
+            }
+            "--seed" => options.seed = Some(value.parse().unwrap_or_else(|_| usage("Expected a number for --seed"))),
+            _ => usage(&format!("Unknown option {}", arg)),
+        }
+    }
+
+    if options.progressive.is_some() && matches!(options.integrator, Integrator::ProgressivePhotonMapping(_) | Integrator::Metropolis(_)) {
+        usage("--integrator sppm and mlt render in passes of their own, so they cannot be used with --progressive, --save-every, --time-limit, --noise, --checkpoint or --resume");
+    }
+
+    if options.frames.is_some() && options.progressive.as_ref().is_some_and(|x| x.checkpoint.is_some()) {
+        usage("A checkpoint holds a single image, so --checkpoint and --resume cannot be used with --frames");
+    }
+    if options.frames.is_some() && options.progressive.is_some() {
+        usage("A progressive render saves a single image, so --progressive, --save-every, --time-limit and --noise cannot be used with --frames");
+    }
+
+    if options.sky.is_some() && options.environment.is_some() {
+        usage("--sky and --environment both light the scene, so only one of them can be given");
+    }
+
+    let photon_mapping = matches!(options.integrator, Integrator::PhotonMapping(_) | Integrator::ProgressivePhotonMapping(_));
+    if photon_mapping && (options.sky.is_some() || options.environment.is_some()) {
+        usage("--integrator photon and sppm cannot trace photons from --sky or --environment, which are infinitely far away");
+    }
+    if !photon_mapping && (options.photons.is_some() || options.photon_radius.is_some()) {
+        usage("--photons and --photon-radius only apply to --integrator photon and sppm");
+    }
+
+    if let Integrator::PhotonMapping(settings) | Integrator::ProgressivePhotonMapping(settings) = &mut options.integrator {
+        if let Some(count) = options.photons {
+            settings.photons = count;
+            settings.caustic_photons = count;
+        }
+        if let Some(radius) = options.photon_radius {
+            settings.radius = radius;
+        }
+    }
+
+    options
+}
+
+fn usage(error: &str) -> ! {
+    eprintln!("{}", error);
+    eprintln!("Usage: raytracer [--frames START..END] [--fps FPS] [--output PREFIX]");
+    eprintln!("                 [--environment FILE] [--environment-rotation DEGREES] [--environment-intensity SCALE]");
+    eprintln!("                 [--sky ELEVATION,AZIMUTH[,TURBIDITY]] [--integrator path|bdpt|photon|sppm|mlt]");
+    eprintln!("                 [--colour rgb|spectral] [--photons COUNT] [--photon-radius RADIUS]");
+    eprintln!("                 [--adaptive THRESHOLD] [--heat-map FILE]");
+    eprintln!("                 [--progressive FILE] [--save-every N|Ns] [--time-limit SECONDS] [--noise TARGET]");
+    eprintln!("                 [--checkpoint FILE] [--resume FILE] [--seed SEED] [--denoise PASSES]");
+    eprintln!("                 [--resolution PERCENT] [--region X0,Y0,X1,Y1 | --border X0,Y0,X1,Y1] [--canvas region|full]");
+    process::exit(1)
+}
+
+/// Orbits the camera once around ``lookat`` over the given frames, keeping its height.
+fn turntable(camera: &Camera, frames: &Range<u32>, fps: f32) -> CameraAnimation {
+    let offset = camera.lookfrom() - camera.lookat();
+    let radius = (offset.x * offset.x + offset.z * offset.z).sqrt();
+    let start_angle = offset.z.atan2(offset.x);
+    let (start, end) = (frames.start as f32 / fps, frames.end as f32 / fps);
+
+    let points: Vec<(f32, Vec3)> = (0..=8)
+        .map(|k| {
+            let fraction = k as f32 / 8.0;
+            let angle = start_angle + 2.0 * std::f32::consts::PI * fraction;
+            let position = Vec3::from_point(radius * angle.cos(), offset.y, radius * angle.sin());
+
+            (start + fraction * (end - start), camera.lookat() + position)
+        })
+        .collect();
+
+    CameraAnimation {
+        lookfrom: Some(Curve::with_interpolation(&points, Interpolation::CatmullRom)),
+        ..Default::default()
+    }
+}
 
 fn main() {
+    let mut options = parse_options();
     let mut world: World = World::new();
+
+    // A checkpointed (or seeded) render builds its scene from its seed, so that it can be
+    // resumed in the same scene. The checkpoint is loaded once, and handed to the camera.
+    let mut resumed = None;
+    if let Some(progressive) = &options.progressive {
+        options.seed = match &progressive.checkpoint {
+            Some(path) if progressive.resume => match Checkpoint::load(path) {
+                Ok(checkpoint) => Some(resumed.insert(checkpoint).seed),
+                Err(e) => {
+                    eprintln!("Failed to load the checkpoint {}: {}", path.display(), e);
+                    process::exit(1);
+                }
+            },
+            Some(_) => Some(options.seed.unwrap_or_else(rand::random)),
+            None => options.seed,
+        };
+    }
+    let seed = options.seed;
     
-    let camera: Camera = Camera::new(
-        16.0 / 9.0,
-	400,
-        100,
-        50,
-	20.0,
-        0.6,
-	10.0,
-        Vec3::from_point(13.0, 2.0, 3.0),
-        Vec3::from_point(0.0, 0.0, 0.0),
-        Vec3::from_point(0.0, 1.0, 0.0),
-    );
+    let camera: Camera = Camera::builder()
+        .with_aspect_ratio(16.0 / 9.0)
+        .with_image_width(400)
+        .with_samples_per_pixel(100)
+        .with_max_depth(50)
+        .with_vfov(20.0)
+        .with_defocus_angle(0.6)
+        .with_focus_distance(10.0)
+        .with_frame(
+            Vec3::from_point(13.0, 2.0, 3.0),
+            Vec3::from_point(0.0, 0.0, 0.0),
+            Vec3::from_point(0.0, 1.0, 0.0),
+        )
+        .build()
+        .unwrap_or_else(|e| {
+            eprintln!("Invalid camera: {}", e);
+            process::exit(1);
+        });
 
+    // Everything past the framing comes from the options
+    let camera = camera
+        .with_resolution(options.resolution)
+        .and_then(|x| x.with_integrator(options.integrator))
+        .and_then(|x| x.with_spectral(options.spectral))
+        .and_then(|x| x.with_seed(seed))
+        .and_then(|x| match options.adaptive.clone() {
+            Some(adaptive) => x.with_adaptive_sampling(adaptive),
+            None => Ok(x),
+        })
+        .and_then(|x| match options.progressive.clone() {
+            Some(progressive) => x.with_progressive(progressive),
+            None => Ok(x),
+        })
+        .and_then(|x| match resumed {
+            Some(checkpoint) => x.with_checkpoint(checkpoint),
+            None => Ok(x),
+        })
+        .and_then(|x| match options.region {
+            Some(bounds) => x.with_region(Region { bounds, full_canvas: options.full_canvas }),
+            None => Ok(x),
+        })
+        .and_then(|x| match options.denoise {
+            Some(settings) => x.with_denoiser(settings),
+            None => Ok(x),
+        })
+        .unwrap_or_else(|e| usage(&format!("Invalid options: {}", e)));
 
     let glass: Arc<Dielectric> = Arc::new(Dielectric::new(1.5));
 
+    seed_random(seed);
     let ground: Arc<Lambertian<Solid>> = Arc::new(Lambertian::new(Solid::from_colour(0.5, 0.5, 0.5)));
     world.push(Box::new(Sphere::stationary_from_dim(Vec3::from_point(0.0, -1000.0, 0.0), 1000.0, ground)));
 
@@ -68,7 +428,12 @@ fn main() {
 		glass.clone()
 	    };
 
-	    world.push(Box::new(Sphere::moving_from_dim(center, 0.2, mat)));
+	    // The spheres bounce over the time 0 to 1, which is every frame of an animation
+	    let sphere = Box::new(Sphere::moving_from_dim(center, 0.2, mat));
+	    match options.frames {
+		Some(_) => world.push(Box::new(PerFrame::new(sphere, options.fps))),
+		None => world.push(sphere),
+	    }
 	}
     }
 
@@ -76,5 +441,41 @@ fn main() {
     world.push(Box::new(Sphere::stationary_from_dim(Vec3::from_point(-4.0, 1.0, 0.0), 1.0, Arc::new(Lambertian::new(Vec3::from_point(0.4, 0.2, 0.1))))));
     world.push(Box::new(Sphere::stationary_from_dim(Vec3::from_point(4.0, 1.0, 0.0), 1.0, Arc::new(Metal::new(Vec3::from_point(1.0, 0.6, 0.6), 0.0)))));
     
-    camera.render(&world);
+    seed_random(None);
+
+    let mut scene = Scene::new(world);
+
+    if let Some(path) = &options.environment {
+        match Environment::load(path) {
+            Ok(environment) => scene.set_environment(
+                environment
+                    .with_rotation(options.environment_rotation)
+                    .with_intensity(options.environment_intensity),
+            ),
+            Err(e) => {
+                eprintln!("Failed to load the environment {}: {}", path, e);
+                process::exit(1);
+            }
+        }
+    }
+
+    if let Some(sky) = &options.sky {
+        scene.set_environment(sky.environment(2048));
+    }
+
+    match options.frames {
+        Some(frames) => {
+            let animation = turntable(&camera, &frames, options.fps);
+            if let Err(e) = animation::render_sequence(&camera, &animation, &scene, frames, options.fps, &options.output) {
+                eprintln!("Failed to render the animation: {}", e);
+                process::exit(1);
+            }
+        }
+        None => {
+            if let Err(e) = camera.render_to(&scene, &mut io::stdout().lock()) {
+                eprintln!("Failed to render the image: {}", e);
+                process::exit(1);
+            }
+        }
+    }
 }
@@ -1,18 +1,69 @@
+use std::sync::Arc;
+
+use crate::animation::Curve;
 use crate::hittable::HitRecord;
 use crate::ray::Ray;
 use crate::utils::random;
 use crate::vec3::{random_unit_vector, Point3, Vec3};
+use crate::spectrum::{self, SpectralIor, RGB_WAVELENGTHS};
 use crate::texture::Texture;
 
 pub struct Reflect {
     pub attenuation: Point3,
     pub scattered: Ray,
+    /// Probability density (per solid angle) of the scattered direction, used to weigh it
+    /// against sampling lights directly. This is 0 for exact directions, like mirrors.
+    pub pdf: f32,
+    /// The kind of scattering, which paths may limit the number of (Refer to [crate::camera::PathDepth])
+    pub lobe: Lobe,
+}
+
+/// The ways light is scattered by a surface.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lobe {
+    /// Scattered in all directions, like paper
+    Diffuse,
+    /// Reflected about the mirror direction, either exactly or blurred, like metal
+    Glossy,
+    /// Passed through the surface, like glass
+    Transmission,
 }
 
 /// Allows the creation of different materials.
 pub trait Material: Send + Sync {
     /// Calculates the scattering of a ray, based on the various properties of a given material.
     fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<Reflect>;
+
+    /// Whether the surface is cut out at the hit, so that rays pass through it
+    /// as if it was not there. Refer to [crate::cutout::AlphaMask].
+    fn cutout(&self, _r_in: &Ray, _rec: &HitRecord) -> bool {
+        false
+    }
+
+    /// Returns how much of the light arriving from ``direction`` is reflected back along
+    /// the incoming ray, including the cosine of the angle of the light with the normal.
+    /// This is used to sample lights directly. Materials that only scatter in exact
+    /// directions (like mirrors and glass) cannot reflect light from a given direction.
+    fn eval(&self, _r_in: &Ray, _rec: &HitRecord, _direction: Vec3) -> Point3 {
+        Vec3::new()
+    }
+
+    /// Returns the probability density of [Material::scatter()] scattering the ray
+    /// towards ``direction``. This is 0 for materials without an [Material::eval()].
+    fn pdf(&self, _r_in: &Ray, _rec: &HitRecord, _direction: Vec3) -> f32 {
+        0.0
+    }
+
+    /// Light given off by the surface back along the incoming ray.
+    fn emitted(&self, _r_in: &Ray, _rec: &HitRecord) -> Point3 {
+        Vec3::new()
+    }
+
+    /// Index of the light in [crate::scene::Scene::lights] that samples this surface
+    /// directly, so that hitting it can be weighed against sampling it.
+    fn light(&self) -> Option<usize> {
+        None
+    }
 }
 
 /// A Lambertian material relfects light according to Lambertian reflectance.
@@ -39,10 +90,62 @@ impl<T: Texture + Sync + Send> Material for Lambertian<T> {
         };
 
         Some(Reflect {
-            scattered: Ray::construct(rec.point, scatter_dir, r_in.time),
-            attenuation: self.albedo.value(0.0, 0.0, &rec.point),
+            scattered: r_in.spawn(rec.point, scatter_dir),
+            attenuation: r_in.sample_colour(self.albedo.value(0.0, 0.0, &rec.point)),
+            pdf: self.pdf(r_in, rec, scatter_dir),
+            lobe: Lobe::Diffuse,
         })
     }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> Point3 {
+        let cos_theta = rec.normal.dot(direction.unit()).max(0.0);
+        r_in.sample_colour(self.albedo.value(0.0, 0.0, &rec.point)) * (cos_theta / std::f32::consts::PI)
+    }
+
+    fn pdf(&self, _r_in: &Ray, rec: &HitRecord, direction: Vec3) -> f32 {
+        // Adding a random unit vector to the normal gives a cosine distribution
+        rec.normal.dot(direction.unit()).max(0.0) / std::f32::consts::PI
+    }
+}
+
+/// A diffuse light gives off the same light in every direction from the front of its surface,
+/// and does not reflect anything. On its own, it is only found by rays that happen to hit it.
+/// Use [crate::scene::Scene::push_area_light()] to also sample it directly.
+#[allow(dead_code)]
+pub struct DiffuseLight <T: Texture + Sync + Send> {
+    emit: T,
+    light: Option<usize>,
+}
+
+#[allow(dead_code)]
+impl<T: Texture + Sync + Send> DiffuseLight<T> {
+    pub fn new (emit: T) -> Self {
+        Self { emit, light: None }
+    }
+
+    /// Marks the surface as sampled directly by the light at ``index`` in the scene
+    pub fn with_light(mut self, index: usize) -> Self {
+        self.light = Some(index);
+        self
+    }
+}
+
+impl<T: Texture + Sync + Send> Material for DiffuseLight<T> {
+    fn scatter(&self, _r_in: &Ray, _rec: &HitRecord) -> Option<Reflect> {
+        None
+    }
+
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Point3 {
+        if !rec.front {
+            return Vec3::new();
+        }
+
+        r_in.sample_colour(self.emit.value(rec.u, rec.v, &rec.point))
+    }
+
+    fn light(&self) -> Option<usize> {
+        self.light
+    }
 }
 
 /// A metal surface is very similar to a Lambertian surface, with the exception
@@ -66,31 +169,141 @@ impl Material for Metal {
 
         match scattered_dir.dot(rec.normal) > 0.0 {
             true => Some(Reflect {
-                scattered: Ray::construct(rec.point, scattered_dir, r_in.time),
-                attenuation: self.albedo,
+                scattered: r_in.spawn(rec.point, scattered_dir),
+                attenuation: r_in.sample_colour(self.albedo),
+                pdf: 0.0,
+                lobe: Lobe::Glossy,
             }),
             false => None,
         }
     }
 }
 
+/// A conductor is a metal described by its measured complex refractive index
+/// (Refer to [SpectralIor]), rather than an albedo. The colour of the metal comes
+/// from the Fresnel reflectance, which changes with both wavelength and angle.
+#[allow(dead_code)]
+pub struct Conductor {
+    ior: SpectralIor,
+    fuzz: f32,
+}
+
+#[allow(dead_code)]
+impl Conductor {
+    pub fn new(ior: SpectralIor, fuzz: f32) -> Self {
+        Self { ior, fuzz }
+    }
+}
+
+impl Material for Conductor {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<Reflect> {
+        let unit = r_in.direction.unit();
+        let scattered_dir = unit.reflect_along(rec.normal) + self.fuzz * random_unit_vector();
+
+        if scattered_dir.dot(rec.normal) <= 0.0 {
+            return None;
+        }
+
+        let cos_theta = (-unit).dot(rec.normal).clamp(0.0, 1.0);
+        let attenuation = r_in.wavelengths.unwrap_or(RGB_WAVELENGTHS).map(|lambda| {
+            let (eta, k) = self.ior.at(lambda);
+            spectrum::fresnel_conductor(cos_theta, eta, k)
+        });
+
+        Some(Reflect {
+            scattered: r_in.spawn(rec.point, scattered_dir),
+            attenuation,
+            pdf: 0.0,
+            lobe: Lobe::Glossy,
+        })
+    }
+}
+
 /// A dielectric surface, like water, or glass, has a characteristic reflection
 /// pattern. Here, however, we are not concerned with details like polarization,
 /// largely worried about the refraction of light in the medium, which can change
 /// due to the refractive index of the medium.
+///
+/// Optionally, the medium can absorb light (Refer to [Dielectric::with_absorption()]),
+/// which tints it more the further light travels inside it, and its refractive index
+/// can vary with wavelength (Refer to [Dispersion]), which splits white light into rainbows.
 pub struct Dielectric {
     pub eta: f32,
+    pub absorption: Vec3,
+    pub dispersion: Option<Dispersion>,
 }
 
+#[allow(dead_code)]
 impl Dielectric {
     pub fn new(eta: f32) -> Self {
-        Self { eta }
+        Self {
+            eta,
+            absorption: Vec3::new(),
+            dispersion: None,
+        }
+    }
+
+    /// Sets the absorption coefficient of the medium, per unit length, for each channel.
+    /// Light travelling a distance ``d`` inside the medium is attenuated by ``exp(-absorption * d)``
+    /// (the Beer-Lambert law).
+    pub fn with_absorption(mut self, absorption: Vec3) -> Self {
+        self.absorption = absorption;
+        self
+    }
+
+    /// Sets the absorption such that white light travelling ``distance`` inside the medium
+    /// comes out as ``colour``. This is usually easier to reason about than the coefficient itself.
+    /// ``distance`` is clamped to at least ``1e-6`` (as is each channel of ``colour``), so that
+    /// a zero, negative or NaN distance gives a very dense medium instead of an invalid one.
+    pub fn with_tint(self, colour: Vec3, distance: f32) -> Self {
+        let distance = distance.max(1e-6);
+        self.with_absorption(colour.map(|c| -c.max(1e-6).ln() / distance))
+    }
+
+    /// Makes the refractive index depend on the wavelength of the ray.
+    /// When set, ``eta`` is ignored.
+    pub fn with_dispersion(mut self, dispersion: Dispersion) -> Self {
+        self.dispersion = Some(dispersion);
+        self
     }
 }
 
 impl Material for Dielectric {
     fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<Reflect> {
-        let refractive_index = if rec.front { 1.0 / self.eta } else { self.eta };
+        // With dispersion, every wavelength bends differently. We follow a single
+        // one, and scale it to keep the average unchanged. For RGB rays, this is a
+        // channel chosen at random at the first dispersive surface, and kept for the
+        // rest of the path. Spectral rays follow their hero wavelength, and drop the
+        // others once, at the first dispersive surface they reach.
+        let mut channel = r_in.channel;
+        let (eta, mut attenuation) = match (&self.dispersion, r_in.wavelengths) {
+            (Some(dispersion), Some(wavelengths)) => {
+                let weight = if r_in.hero_only { 1.0 } else { 3.0 };
+                (dispersion.ior(wavelengths.x), Vec3::from_point(weight, 0.0, 0.0))
+            }
+            (Some(dispersion), None) => {
+                let (followed, weight) = match channel {
+                    Some(followed) => (followed, 1.0),
+                    None => (*channel.insert(((random() * 3.0) as usize).min(2)), 3.0),
+                };
+                let mut mask = [0.0; 3];
+                mask[followed] = weight;
+
+                (
+                    dispersion.ior(RGB_WAVELENGTHS[followed]),
+                    Vec3::from_point(mask[0], mask[1], mask[2]),
+                )
+            }
+            (None, _) => (self.eta, Vec3::from_point(1.0, 1.0, 1.0)),
+        };
+
+        // Hitting the back face means the ray has travelled through the medium
+        if !rec.front {
+            let distance = rec.t * r_in.direction.length();
+            attenuation = attenuation * r_in.sample_colour(self.absorption).map(|a| (-a * distance).exp());
+        }
+
+        let refractive_index = if rec.front { 1.0 / eta } else { eta };
 
         let unit = r_in.direction.unit();
 
@@ -100,20 +313,447 @@ impl Material for Dielectric {
         let cannot_refract = (sin_theta * refractive_index) > 1.0;
         let will_reflect = reflectance(cos_theta, refractive_index) > random();
 
-        let direction = if cannot_refract || will_reflect {
-            unit.reflect_along(rec.normal)
+        let (direction, lobe) = if cannot_refract || will_reflect {
+            (unit.reflect_along(rec.normal), Lobe::Glossy)
         } else {
-            unit.refract_along(rec.normal, refractive_index)
+            (unit.refract_along(rec.normal, refractive_index), Lobe::Transmission)
         };
 
+        let mut scattered = r_in.spawn(rec.point, direction);
+        scattered.hero_only |= self.dispersion.is_some() && r_in.wavelengths.is_some();
+        scattered.channel = channel;
+
         Some(Reflect {
-            attenuation: Vec3::from_point(1.0, 1.0, 1.0),
-            scattered: Ray::construct(rec.point, direction, r_in.time),
+            attenuation,
+            scattered,
+            pdf: 0.0,
+            lobe,
+        })
+    }
+}
+
+/// Describes how the refractive index of a medium changes with the wavelength of light.
+/// Wavelengths are given in nanometres, while the coefficients follow the usual
+/// convention of wavelengths in micrometres.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+pub enum Dispersion {
+    /// Cauchy's equation: ``n = a + b / λ²``
+    Cauchy { a: f32, b: f32 },
+    /// The Sellmeier equation: ``n² = 1 + Σ bᵢλ² / (λ² - cᵢ)``
+    Sellmeier { b: [f32; 3], c: [f32; 3] },
+}
+
+#[allow(dead_code)]
+impl Dispersion {
+    /// Borosilicate crown glass, the most common optical glass.
+    pub const BK7: Dispersion = Dispersion::Sellmeier {
+        b: [1.039_612, 0.231_792_34, 1.010_469_4],
+        c: [0.006_000_699, 0.020_017_914, 103.560_65],
+    };
+
+    /// Dense flint glass, which disperses light much more strongly than [Dispersion::BK7].
+    pub const SF11: Dispersion = Dispersion::Sellmeier {
+        b: [1.737_597, 0.313_747_35, 1.898_781],
+        c: [0.013_188_707, 0.062_306_814, 155.236_3],
+    };
+
+    /// Returns the refractive index at the given wavelength (in nanometres).
+    pub fn ior(&self, wavelength: f32) -> f32 {
+        let lambda = wavelength / 1000.0;
+        let lambda_sq = lambda * lambda;
+
+        match self {
+            Dispersion::Cauchy { a, b } => a + b / lambda_sq,
+            Dispersion::Sellmeier { b, c } => (1.0
+                + (0..3)
+                    .map(|i| b[i] * lambda_sq / (lambda_sq - c[i]))
+                    .sum::<f32>())
+            .sqrt(),
+        }
+    }
+}
+
+/// A mix material blends two materials, such as rust over metal.
+/// At every hit, one of the two materials is chosen to scatter the ray, with
+/// the probability of picking ``second`` given by the scalar value of the mask
+/// at the hit point. On average, this is the same as a weighted sum of the two.
+#[allow(dead_code)]
+pub struct Mix <T: Texture + Sync + Send> {
+    first: Arc<dyn Material>,
+    second: Arc<dyn Material>,
+    mask: T,
+}
+
+#[allow(dead_code)]
+impl<T: Texture + Sync + Send> Mix<T> {
+    /// Creates a new mix material. A mask of 0 is entirely ``first``, while
+    /// a mask of 1 is entirely ``second``.
+    pub fn new (first: Arc<dyn Material>, second: Arc<dyn Material>, mask: T) -> Self {
+        Self { first, second, mask }
+    }
+}
+
+impl<T: Texture + Sync + Send> Material for Mix<T> {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<Reflect> {
+        let weight = self.mask.scalar(rec.u, rec.v, &rec.point).clamp(0.0, 1.0);
+
+        let reflect = if random() < weight {
+            self.second.scatter(r_in, rec)
+        } else {
+            self.first.scatter(r_in, rec)
+        };
+
+        reflect.map(|mut x| {
+            // Either material could have scattered in a direction that is not exact
+            if x.pdf > 0.0 {
+                x.pdf = self.pdf(r_in, rec, x.scattered.direction);
+            }
+            x
+        })
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> Point3 {
+        let weight = self.mask.scalar(rec.u, rec.v, &rec.point).clamp(0.0, 1.0);
+
+        (1.0 - weight) * self.first.eval(r_in, rec, direction) + weight * self.second.eval(r_in, rec, direction)
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> f32 {
+        let weight = self.mask.scalar(rec.u, rec.v, &rec.point).clamp(0.0, 1.0);
+
+        (1.0 - weight) * self.first.pdf(r_in, rec, direction) + weight * self.second.pdf(r_in, rec, direction)
+    }
+
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Point3 {
+        let weight = self.mask.scalar(rec.u, rec.v, &rec.point).clamp(0.0, 1.0);
+
+        (1.0 - weight) * self.first.emitted(r_in, rec) + weight * self.second.emitted(r_in, rec)
+    }
+
+    /// The light of whichever material emits (the first, if both do)
+    fn light(&self) -> Option<usize> {
+        self.first.light().or(self.second.light())
+    }
+
+    fn cutout(&self, r_in: &Ray, rec: &HitRecord) -> bool {
+        // Each material cuts out the share of the hits it would have scattered
+        let weight = self.mask.scalar(rec.u, rec.v, &rec.point).clamp(0.0, 1.0);
+
+        if random() < weight {
+            self.second.cutout(r_in, rec)
+        } else {
+            self.first.cutout(r_in, rec)
+        }
+    }
+}
+
+/// A tween animates between two materials over time, for example to fade a colour,
+/// or to let a metal rust. Like [Mix], one of the two is chosen to scatter each ray,
+/// with the probability of picking ``to`` given by a curve over the time of the ray.
+#[allow(dead_code)]
+pub struct Tween {
+    from: Arc<dyn Material>,
+    to: Arc<dyn Material>,
+    weight: Curve<f32>,
+}
+
+#[allow(dead_code)]
+impl Tween {
+    pub fn new(from: Arc<dyn Material>, to: Arc<dyn Material>, weight: Curve<f32>) -> Self {
+        Self { from, to, weight }
+    }
+}
+
+impl Material for Tween {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<Reflect> {
+        let reflect = if random() < self.weight.at(r_in.time).clamp(0.0, 1.0) {
+            self.to.scatter(r_in, rec)
+        } else {
+            self.from.scatter(r_in, rec)
+        };
+
+        reflect.map(|mut x| {
+            if x.pdf > 0.0 {
+                x.pdf = self.pdf(r_in, rec, x.scattered.direction);
+            }
+            x
+        })
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> Point3 {
+        let weight = self.weight.at(r_in.time).clamp(0.0, 1.0);
+
+        (1.0 - weight) * self.from.eval(r_in, rec, direction) + weight * self.to.eval(r_in, rec, direction)
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> f32 {
+        let weight = self.weight.at(r_in.time).clamp(0.0, 1.0);
+
+        (1.0 - weight) * self.from.pdf(r_in, rec, direction) + weight * self.to.pdf(r_in, rec, direction)
+    }
+
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Point3 {
+        let weight = self.weight.at(r_in.time).clamp(0.0, 1.0);
+
+        (1.0 - weight) * self.from.emitted(r_in, rec) + weight * self.to.emitted(r_in, rec)
+    }
+
+    /// The light of whichever material emits (``from``, if both do)
+    fn light(&self) -> Option<usize> {
+        self.from.light().or(self.to.light())
+    }
+
+    fn cutout(&self, r_in: &Ray, rec: &HitRecord) -> bool {
+        // Each material cuts out the share of the hits it would have scattered
+        if random() < self.weight.at(r_in.time).clamp(0.0, 1.0) {
+            self.to.cutout(r_in, rec)
+        } else {
+            self.from.cutout(r_in, rec)
+        }
+    }
+}
+
+/// A coated material places a thin, clear dielectric layer (like varnish or
+/// a lacquer) over an arbitrary base material.
+/// Light is either reflected off the coat, with a probability given by the
+/// Fresnel reflectance, or is transmitted into the base. Light leaving the base
+/// is attenuated by the fraction that the coat lets back out.
+#[allow(dead_code)]
+pub struct Coated {
+    base: Arc<dyn Material>,
+    eta: f32,
+}
+
+#[allow(dead_code)]
+impl Coated {
+    /// Creates a coat of refractive index ``eta`` over the ``base`` material.
+    pub fn new(base: Arc<dyn Material>, eta: f32) -> Self {
+        Self { base, eta }
+    }
+}
+
+impl Material for Coated {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<Reflect> {
+        // The coat only exists on the outside of the surface
+        if !rec.front {
+            return self.base.scatter(r_in, rec);
+        }
+
+        let unit = r_in.direction.unit();
+        let cos_in = (-unit).dot(rec.normal).min(1.0);
+
+        let coat = reflectance(cos_in, 1.0 / self.eta);
+        if coat > random() {
+            return Some(Reflect {
+                attenuation: Vec3::from_point(1.0, 1.0, 1.0),
+                scattered: r_in.spawn(rec.point, unit.reflect_along(rec.normal)),
+                pdf: 0.0,
+                lobe: Lobe::Glossy,
+            });
+        }
+
+        self.base.scatter(r_in, rec).map(|mut x| {
+            let cos_out = x.scattered.direction.unit().dot(rec.normal).max(0.0);
+            x.attenuation *= 1.0 - reflectance(cos_out, 1.0 / self.eta);
+            x.pdf *= 1.0 - coat;
+            x
         })
     }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> Point3 {
+        if !rec.front {
+            return self.base.eval(r_in, rec, direction);
+        }
+
+        // Light passes through the coat on the way in and on the way out
+        let cos_in = (-r_in.direction.unit()).dot(rec.normal).min(1.0);
+        let cos_out = direction.unit().dot(rec.normal).max(0.0);
+        let transmitted = (1.0 - reflectance(cos_in, 1.0 / self.eta)) * (1.0 - reflectance(cos_out, 1.0 / self.eta));
+
+        transmitted * self.base.eval(r_in, rec, direction)
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> f32 {
+        if !rec.front {
+            return self.base.pdf(r_in, rec, direction);
+        }
+
+        let cos_in = (-r_in.direction.unit()).dot(rec.normal).min(1.0);
+        (1.0 - reflectance(cos_in, 1.0 / self.eta)) * self.base.pdf(r_in, rec, direction)
+    }
+
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Point3 {
+        self.base.emitted(r_in, rec)
+    }
+
+    fn light(&self) -> Option<usize> {
+        self.base.light()
+    }
+
+    fn cutout(&self, r_in: &Ray, rec: &HitRecord) -> bool {
+        self.base.cutout(r_in, rec)
+    }
+}
+
+/// A normal map replaces the shading normal of a base material with one read from
+/// a texture (usually an [crate::image::Image]). The texture holds normals in tangent
+/// space, where red, green and blue map to the tangent, bitangent and normal respectively,
+/// each scaled from [0, 1] to [-1, 1].
+#[allow(dead_code)]
+pub struct NormalMap <T: Texture + Sync + Send> {
+    base: Arc<dyn Material>,
+    map: T,
+    strength: f32,
+}
+
+#[allow(dead_code)]
+impl<T: Texture + Sync + Send> NormalMap<T> {
+    /// Creates a new normal map. A ``strength`` of 1 uses the map as is, while 0 ignores it.
+    pub fn new (base: Arc<dyn Material>, map: T, strength: f32) -> Self {
+        Self { base, map, strength }
+    }
+}
+
+impl<T: Texture + Sync + Send> NormalMap<T> {
+    /// Returns the hit record with the normal read from the map
+    fn shading(&self, rec: &HitRecord) -> HitRecord {
+        let texel = self.map.value(rec.u, rec.v, &rec.point);
+        let local = texel.map(|c| 2.0 * c - 1.0);
+
+        let mapped = local.x * rec.tangent + local.y * rec.bitangent + local.z * rec.normal;
+        let normal = rec.normal + self.strength * (mapped - rec.normal);
+
+        perturb(rec, normal)
+    }
+}
+
+impl<T: Texture + Sync + Send> Material for NormalMap<T> {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<Reflect> {
+        self.base.scatter(r_in, &self.shading(rec))
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> Point3 {
+        self.base.eval(r_in, &self.shading(rec), direction)
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> f32 {
+        self.base.pdf(r_in, &self.shading(rec), direction)
+    }
+
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Point3 {
+        self.base.emitted(r_in, rec)
+    }
+
+    fn light(&self) -> Option<usize> {
+        self.base.light()
+    }
+
+    fn cutout(&self, r_in: &Ray, rec: &HitRecord) -> bool {
+        self.base.cutout(r_in, rec)
+    }
+}
+
+/// A bump map perturbs the shading normal of a base material using a scalar height
+/// texture, as if the surface were displaced along its normal. The slope of the height
+/// is found with finite differences in texture space.
+#[allow(dead_code)]
+pub struct BumpMap <T: Texture + Sync + Send> {
+    base: Arc<dyn Material>,
+    height: T,
+    scale: f32,
+}
+
+#[allow(dead_code)]
+impl<T: Texture + Sync + Send> BumpMap<T> {
+    /// Creates a new bump map. ``scale`` controls how strongly the height bends the normal.
+    pub fn new (base: Arc<dyn Material>, height: T, scale: f32) -> Self {
+        Self { base, height, scale }
+    }
+}
+
+impl<T: Texture + Sync + Send> BumpMap<T> {
+    /// Returns the hit record with the normal bent by the slope of the height
+    fn shading(&self, rec: &HitRecord) -> HitRecord {
+        const DELTA: f32 = 1.0 / 1024.0;
+
+        let h = self.height.scalar(rec.u, rec.v, &rec.point);
+        let dh_du = (self.height.scalar(rec.u + DELTA, rec.v, &rec.point) - h) / DELTA;
+        let dh_dv = (self.height.scalar(rec.u, rec.v + DELTA, &rec.point) - h) / DELTA;
+
+        let normal = rec.normal - self.scale * (dh_du * rec.tangent + dh_dv * rec.bitangent);
+
+        perturb(rec, normal)
+    }
+}
+
+impl<T: Texture + Sync + Send> Material for BumpMap<T> {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<Reflect> {
+        self.base.scatter(r_in, &self.shading(rec))
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> Point3 {
+        self.base.eval(r_in, &self.shading(rec), direction)
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> f32 {
+        self.base.pdf(r_in, &self.shading(rec), direction)
+    }
+
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Point3 {
+        self.base.emitted(r_in, rec)
+    }
+
+    fn light(&self) -> Option<usize> {
+        self.base.light()
+    }
+
+    fn cutout(&self, r_in: &Ray, rec: &HitRecord) -> bool {
+        self.base.cutout(r_in, rec)
+    }
+}
+
+/// Returns a copy of the hit record with the given shading normal, keeping the tangent frame orthonormal.
+fn perturb(rec: &HitRecord, normal: Vec3) -> HitRecord {
+    let mut rec = rec.clone();
+    if normal.near_zero() {
+        return rec;
+    }
+
+    rec.normal = normal.unit();
+    rec.tangent = (rec.tangent - rec.tangent.dot(rec.normal) * rec.normal).unit();
+    rec.bitangent = rec.normal.cross(rec.tangent);
+    rec
 }
 
 fn reflectance(cos_theta: f32, ri: f32) -> f32 {
     let r0 = ((1.0 - ri) / (1.0 + ri)).powi(2);
     r0 + (1.0 - r0) * (1.0 - cos_theta).powi(5)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sellmeier_ior_matches_the_catalogue() {
+        // Refractive indices at the helium d line, 587.6 nm
+        assert!((Dispersion::BK7.ior(587.6) - 1.5168).abs() < 1e-4);
+        assert!((Dispersion::SF11.ior(587.6) - 1.7847).abs() < 1e-4);
+    }
+
+    #[test]
+    fn cauchy_ior_follows_the_equation() {
+        let dispersion = Dispersion::Cauchy { a: 1.5, b: 0.004 };
+
+        assert!((dispersion.ior(500.0) - (1.5 + 0.004 / 0.25)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn ior_falls_with_wavelength() {
+        for dispersion in [Dispersion::BK7, Dispersion::SF11, Dispersion::Cauchy { a: 1.5, b: 0.004 }] {
+            assert!(dispersion.ior(400.0) > dispersion.ior(550.0));
+            assert!(dispersion.ior(550.0) > dispersion.ior(700.0));
+        }
+    }
+}
@@ -0,0 +1,286 @@
+//! Metropolis light transport in primary sample space (Kelemen et al., "A Simple and Robust
+//! Mutation Strategy for the Metropolis Light Transport Algorithm"), following PBRT.
+//!
+//! A path traced by the path tracer is a function of the random numbers it uses. Rather than
+//! drawing fresh numbers for every path, Markov chains wander through the space of these
+//! numbers, changing them slightly (small steps) or completely (large steps), and keep the
+//! changes in proportion to the brightness of the resulting path. Once a chain finds a narrow
+//! way for light to reach the camera, such as through a keyhole, it keeps exploring the paths
+//! around it, so that effort goes where the light is.
+//!
+//! Every path lands on a pixel picked by its first two numbers. The chains spend time on each
+//! pixel in proportion to its brightness, which is scaled back to absolute values by the mean
+//! brightness of all paths, estimated beforehand from independent paths (bootstrapping).
+
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use crate::camera::{ray_colour, Camera};
+use crate::distribution::Distribution1D;
+use crate::scene::Scene;
+use crate::spectrum::{self, luminance};
+use crate::utils::set_random_source;
+use crate::vec3::Vec3;
+
+type Colour = Vec3;
+
+/// Settings of the Metropolis integrator.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MetropolisSettings {
+    /// Number of independent paths traced to estimate the mean brightness, and to start
+    /// the chains from
+    pub bootstrap: usize,
+    /// Number of Markov chains, which each take an equal share of the mutations
+    pub chains: usize,
+    /// Probability of a mutation replacing every number (a large step)
+    pub large_step: f32,
+    /// Standard deviation of the changes made to each number by a small step
+    pub sigma: f32,
+}
+
+impl Default for MetropolisSettings {
+    fn default() -> Self {
+        Self {
+            bootstrap: 100_000,
+            chains: 1000,
+            large_step: 0.3,
+            sigma: 0.01,
+        }
+    }
+}
+
+/// A random number used by a path, with what is needed to undo a rejected mutation.
+#[derive(Debug, Clone, Copy, Default)]
+struct PrimarySample {
+    value: f32,
+    /// Iteration in which the number was last changed
+    modified: u64,
+    value_backup: f32,
+    modified_backup: u64,
+}
+
+/// The random numbers of a path, mutated lazily: each number is only brought up to date
+/// with the mutations made since it was last used when a path asks for it.
+struct MetropolisSampler {
+    rng: SmallRng,
+    settings: MetropolisSettings,
+    samples: Vec<PrimarySample>,
+    iteration: u64,
+    large_step: bool,
+    last_large_step: u64,
+    /// Index of the next number a path asks for
+    index: usize,
+}
+
+thread_local! {
+    /// The sampler of the chain running on the current thread, which [crate::utils::random()]
+    /// draws from while a path is evaluated
+    static SAMPLER: RefCell<Option<MetropolisSampler>> = const { RefCell::new(None) };
+}
+
+impl MetropolisSampler {
+    /// Creates a sampler whose first path uses numbers drawn from a generator seeded with ``seed``
+    fn new(seed: u64, settings: MetropolisSettings) -> Self {
+        Self {
+            rng: SmallRng::seed_from_u64(seed),
+            settings,
+            samples: Vec::new(),
+            iteration: 0,
+            large_step: true,
+            last_large_step: 0,
+            index: 0,
+        }
+    }
+
+    /// Starts a new mutation
+    fn start_iteration(&mut self) {
+        self.iteration += 1;
+        self.large_step = self.rng.gen::<f32>() < self.settings.large_step;
+        self.index = 0;
+    }
+
+    /// Returns the next number of the path, mutated as needed
+    fn next(&mut self) -> f32 {
+        // A number the path has not asked for before starts out uniform, as after a large step.
+        // Starting at 0 instead, small steps would keep it near 0 or 1, and sampling by
+        // rejection (like points in a disc) would never finish.
+        while self.samples.len() <= self.index {
+            let value = self.rng.gen();
+            self.samples.push(PrimarySample { value, modified: self.last_large_step, ..Default::default() });
+        }
+
+        let sample = &mut self.samples[self.index];
+        self.index += 1;
+
+        // Catch up with the last large step, which replaced every number
+        if sample.modified < self.last_large_step {
+            sample.value = self.rng.gen();
+            sample.modified = self.last_large_step;
+        }
+
+        sample.value_backup = sample.value;
+        sample.modified_backup = sample.modified;
+
+        if self.large_step {
+            sample.value = self.rng.gen();
+        } else {
+            // The small steps missed since the number was last used add up to one larger step
+            let steps = (self.iteration - sample.modified) as f32;
+            let sigma = self.settings.sigma * steps.sqrt();
+            sample.value = (sample.value + sigma * normal(&mut self.rng)).rem_euclid(1.0);
+            // Rounding can land exactly on 1
+            if sample.value >= 1.0 {
+                sample.value = 0.0;
+            }
+        }
+        sample.modified = self.iteration;
+
+        sample.value
+    }
+
+    /// Keeps the mutation
+    fn accept(&mut self) {
+        if self.large_step {
+            self.last_large_step = self.iteration;
+        }
+    }
+
+    /// Undoes the mutation
+    fn reject(&mut self) {
+        for sample in self.samples.iter_mut().filter(|x| x.modified == self.iteration) {
+            sample.value = sample.value_backup;
+            sample.modified = sample.modified_backup;
+        }
+        self.iteration -= 1;
+    }
+}
+
+/// Samples a standard normal distribution (Box-Muller transform)
+fn normal(rng: &mut SmallRng) -> f32 {
+    let (u, v): (f32, f32) = (rng.gen(), rng.gen());
+    (-2.0 * (1.0 - u).ln()).sqrt() * (2.0 * std::f32::consts::PI * v).cos()
+}
+
+/// Draws the next number from the sampler of the current thread
+fn next_sample() -> f32 {
+    SAMPLER.with(|x| x.borrow_mut().as_mut().map_or(0.0, |x| x.next()))
+}
+
+/// Traces the path given by the numbers of ``sampler``, returning the sampler, the pixel of the
+/// output image the path lands on, and the light it carries in RGB.
+fn evaluate(sampler: MetropolisSampler, camera: &Camera, scene: &Scene) -> (MetropolisSampler, (u32, u32), Colour) {
+    SAMPLER.with(|x| *x.borrow_mut() = Some(sampler));
+    set_random_source(Some(next_sample));
+
+    let (width, height) = camera.output_size();
+    let i = ((next_sample() * width as f32) as u32).min(width - 1);
+    let j = ((next_sample() * height as f32) as u32).min(height - 1);
+    let colour = match camera.pixel_ray(i, j) {
+        Some(ray) => {
+            let colour = ray_colour(&ray, scene, camera.max_depth(), camera.path_depth());
+            match ray.wavelengths {
+                Some(wavelengths) => spectrum::to_rgb(colour, wavelengths),
+                None => colour,
+            }
+        }
+        None => Vec3::new(),
+    };
+
+    set_random_source(None);
+    let sampler = SAMPLER.with(|x| x.borrow_mut().take()).unwrap();
+    (sampler, (i, j), colour)
+}
+
+/// Brightness a chain visits paths in proportion to
+fn contribution(colour: Colour) -> f32 {
+    luminance(colour).max(0.0)
+}
+
+/// Renders the scene with Metropolis light transport, making ``samples_per_pixel`` mutations
+/// for every pixel on average. Returns the colour of every pixel of the output image in RGB.
+/// The chains take their random numbers from the seed of the camera, if it has one.
+pub fn render(camera: &Camera, scene: &Scene, settings: &MetropolisSettings) -> Vec<Colour> {
+    let (width, height) = camera.output_size();
+    let seed: u64 = camera.seed().unwrap_or_else(|| rand::thread_rng().gen());
+
+    // Bootstrap: independent paths give the mean brightness, and the paths to start from
+    eprintln!("Tracing bootstrap paths");
+    let bootstrap = settings.bootstrap.max(1);
+    let weights: Vec<f32> = (0..bootstrap)
+        .into_par_iter()
+        .map(|k| {
+            let sampler = MetropolisSampler::new(seed.wrapping_add(k as u64), *settings);
+            contribution(evaluate(sampler, camera, scene).2)
+        })
+        .collect();
+    let mean = weights.iter().sum::<f32>() / bootstrap as f32;
+    if mean <= 0.0 {
+        return vec![Vec3::new(); (width * height) as usize];
+    }
+    let starts = Distribution1D::new(weights);
+
+    let chains = settings.chains.max(1);
+    let mutations = camera.samples_per_pixel() as u64 * (width * height) as u64;
+    let splats = Mutex::new(vec![Vec3::new(); (width * height) as usize]);
+    let remaining = AtomicUsize::new(chains);
+
+    (0..chains).into_par_iter().for_each(|chain| {
+        let mut rng = SmallRng::seed_from_u64(seed.wrapping_add((bootstrap + chain) as u64));
+
+        // Start from a bootstrap path, chosen by brightness, by replaying its numbers
+        let (_, _, start) = starts.sample(rng.gen());
+        let sampler = MetropolisSampler::new(seed.wrapping_add(start as u64), *settings);
+        let (mut sampler, mut pixel, mut colour) = evaluate(sampler, camera, scene);
+
+        let count = mutations / chains as u64 + ((chain as u64) < mutations % chains as u64) as u64;
+        let mut local = Vec::with_capacity(1024);
+        for _ in 0..count {
+            sampler.start_iteration();
+            let (proposed_sampler, proposed_pixel, proposed) = evaluate(sampler, camera, scene);
+            sampler = proposed_sampler;
+
+            let (f_current, f_proposed) = (contribution(colour), contribution(proposed));
+            let accept = if f_current > 0.0 { (f_proposed / f_current).min(1.0) } else { 1.0 };
+
+            // Both paths are recorded, weighted by how likely each is to be kept
+            if accept > 0.0 && f_proposed > 0.0 {
+                local.push((proposed_pixel, proposed * (accept / f_proposed)));
+            }
+            if f_current > 0.0 && accept < 1.0 {
+                local.push((pixel, colour * ((1.0 - accept) / f_current)));
+            }
+
+            if rng.gen::<f32>() < accept {
+                (pixel, colour) = (proposed_pixel, proposed);
+                sampler.accept();
+            } else {
+                sampler.reject();
+            }
+
+            if local.len() >= 1024 {
+                flush(&splats, &mut local, width);
+            }
+        }
+        flush(&splats, &mut local, width);
+
+        let left = remaining.fetch_sub(1, Ordering::Relaxed) - 1;
+        eprint!("\rNumber of chains remaining: {}", left);
+    });
+
+    eprintln!();
+
+    let scale = mean / camera.samples_per_pixel().max(1) as f32;
+    splats.into_inner().unwrap().into_iter().map(|x| x * scale).collect()
+}
+
+/// Adds the splats of a chain to the image
+fn flush(splats: &Mutex<Vec<Colour>>, local: &mut Vec<((u32, u32), Colour)>, width: u32) {
+    let mut splats = splats.lock().unwrap();
+    for ((i, j), colour) in local.drain(..) {
+        splats[(j * width + i) as usize] += colour;
+    }
+}
@@ -0,0 +1,393 @@
+//! Photon mapping (Jensen, "Realistic Image Synthesis Using Photon Mapping"), and its
+//! progressive form, stochastic progressive photon mapping (Hachisuka and Jensen).
+//!
+//! Paths are first traced from the lights, leaving photons wherever they land on a diffuse
+//! surface (Refer to [PhotonMap]). The light reflected at a point is then estimated from the
+//! density of the photons around it. This finds caustics, light focused by glass or mirrors
+//! onto diffuse surfaces, which paths from the camera can only find by chance.
+//!
+//! Photons are kept in two maps. The caustic map holds photons that only passed through glass
+//! and mirrors on the way from the light, and is dense enough to show caustics sharply. The
+//! global map holds photons that were reflected by at least one diffuse surface, and is only
+//! looked at after another bounce (final gathering), where its blotchiness is blurred away.
+//! Light arriving directly from the lights is sampled as in the path tracer.
+//!
+//! Photon maps give biased (blurred) results for any number of photons. Progressive photon
+//! mapping instead traces new photons in every pass, and shrinks the radius photons are
+//! gathered from as they accumulate, so that the image converges to the right answer.
+//!
+//! Photons are traced in RGB. Lights infinitely far away give off no photons, as there is no
+//! way to aim them at the scene, so scenes lit by them are not rendered with photon mapping
+//! (Refer to [Camera::render_to()]).
+
+use rayon::prelude::*;
+use std::f32::consts::PI;
+
+use crate::camera::{background, direct_light, ray_colour, Camera};
+use crate::hittable::{HitRecord, Hittable};
+use crate::light_sampler::{LightSampler, PowerLightSampler};
+use crate::photon_map::{Photon, PhotonMap};
+use crate::ray::Ray;
+use crate::scene::Scene;
+use crate::spectrum;
+use crate::utils::{mix_seed, random, with_seed};
+use crate::vec3::{Point3, Vec3};
+
+type Colour = Vec3;
+
+/// Settings of the photon mapping integrators.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PhotonSettings {
+    /// Photons traced from the lights for the global map, or in each progressive pass
+    pub photons: usize,
+    /// Photons traced from the lights for the caustic map
+    pub caustic_photons: usize,
+    /// Number of nearest photons each estimate is made from
+    pub nearest: usize,
+    /// Largest distance photons are gathered from, or the starting distance in progressive passes
+    pub radius: f32,
+    /// Rays traced from each hit to look up the global map. Without any, the global map
+    /// is looked up directly, which is faster but blotchy.
+    pub final_gather: u32,
+    /// Number of progressive passes, each tracing one ray through every pixel
+    pub passes: u32,
+    /// Fraction of the photons found in each progressive pass that is kept as the radius
+    /// shrinks. Lower values shrink the radius faster.
+    pub alpha: f32,
+}
+
+impl Default for PhotonSettings {
+    fn default() -> Self {
+        Self {
+            photons: 200_000,
+            caustic_photons: 200_000,
+            nearest: 64,
+            radius: 0.25,
+            final_gather: 16,
+            passes: 64,
+            alpha: 2.0 / 3.0,
+        }
+    }
+}
+
+/// Whether the surface at the hit reflects light diffusely (at least in part), so that
+/// photons are kept on it. Mirrors and glass scatter in a single direction.
+fn is_diffuse(r: &Ray, rec: &HitRecord) -> bool {
+    rec.material.pdf(r, rec, rec.normal) > 0.0
+}
+
+/// Traces a photon from a light chosen by ``lights``, at a time while the camera's shutter is
+/// open, for at most the camera's ``max_depth`` hits. ``store`` is called at every diffuse hit with the
+/// photon, the number of surfaces it was scattered by before, and whether all of them scattered
+/// it like mirrors or glass. A surface that is only diffuse in part (like a coated one) counts
+/// as a mirror for the photons it reflects off its mirror-like part. The power of photons is not divided by the number of photons traced.
+fn trace_photon(scene: &Scene, lights: &PowerLightSampler, camera: &Camera, store: &mut impl FnMut(Photon, u32, bool)) {
+    let Some((index, pmf)) = lights.sample(Point3::new(), random()) else {
+        return;
+    };
+    let (open, close) = camera.shutter();
+    let time = open + random() * (close - open);
+    let r = Ray::construct(Point3::new(), Vec3::new(), time);
+    let Some(emission) = scene.light(index).and_then(|x| x.emit(&r)) else {
+        return;
+    };
+    if emission.pdf_position <= 0.0 || emission.pdf_direction <= 0.0 {
+        return;
+    }
+
+    let cos_theta = emission.normal.map_or(1.0, |x| x.dot(emission.direction).abs());
+    let mut power = emission.radiance * cos_theta / (pmf * emission.pdf_position * emission.pdf_direction);
+    let mut ray = r.spawn(emission.origin, emission.direction);
+    let mut specular = true;
+
+    for bounces in 0..camera.max_depth() {
+        let Some(rec) = scene.world.hit(&ray, 0.001..f32::INFINITY) else {
+            return;
+        };
+
+        if is_diffuse(&ray, &rec) {
+            let photon = Photon { point: rec.point, direction: -ray.direction.unit(), power };
+            store(photon, bounces, specular && bounces > 0);
+        }
+
+        let Some(x) = rec.material.scatter(&ray, &rec) else {
+            return;
+        };
+        specular &= x.pdf == 0.0;
+        power = power * x.attenuation;
+        if power.near_zero() {
+            return;
+        }
+        ray = x.scattered;
+    }
+}
+
+/// Estimates the light reflected at the hit towards the ray from the density of the photons
+/// around it, in the units carried by the ray.
+fn estimate(map: &PhotonMap, r: &Ray, rec: &HitRecord, settings: &PhotonSettings) -> Colour {
+    let (photons, radius_sq) = map.nearest(rec.point, settings.nearest, settings.radius);
+    if photons.is_empty() {
+        return Vec3::new();
+    }
+
+    let mut reflected = Vec3::new();
+    for photon in photons {
+        let cos_theta = rec.normal.dot(photon.direction);
+        if cos_theta > 0.0 {
+            reflected += rec.material.eval(r, rec, photon.direction) / cos_theta * r.sample_colour(photon.power);
+        }
+    }
+
+    reflected / (PI * radius_sq)
+}
+
+/// The caustic and global photon maps of a scene.
+pub struct PhotonMaps {
+    pub caustic: PhotonMap,
+    pub global: PhotonMap,
+    pub settings: PhotonSettings,
+}
+
+impl PhotonMaps {
+    /// Traces photons through the scene to build both maps. With a ``seed``, every photon
+    /// takes its random numbers from it, so that the maps can be built again the same.
+    pub fn build(scene: &Scene, lights: &PowerLightSampler, camera: &Camera, settings: PhotonSettings, seed: Option<u64>) -> Self {
+        let trace = |count: usize, map: u64, keep: fn(u32, bool) -> bool| {
+            let photons: Vec<Photon> = (0..count)
+                .into_par_iter()
+                .flat_map_iter(|k| {
+                    let mut photons = Vec::new();
+                    with_seed(seed.map(|x| mix_seed(&[x, map, k as u64])), || {
+                        trace_photon(scene, lights, camera, &mut |photon, bounces, caustic| {
+                            if keep(bounces, caustic) {
+                                photons.push(Photon { power: photon.power / count as f32, ..photon });
+                            }
+                        })
+                    });
+                    photons
+                })
+                .collect();
+            PhotonMap::new(photons)
+        };
+
+        eprintln!("Tracing photons");
+        // Light arriving directly is sampled instead, and caustics are kept apart
+        let caustic = trace(settings.caustic_photons, 0, |_, caustic| caustic);
+        let global = trace(settings.photons, 1, |bounces, caustic| bounces > 0 && !caustic);
+
+        Self { caustic, global, settings }
+    }
+}
+
+/// Traces the camera ray ``r`` through the photon maps, and returns the light it carries.
+pub fn trace(r: &Ray, scene: &Scene, maps: &PhotonMaps, depth: u32) -> Colour {
+    radiance(r, scene, maps, depth, false)
+}
+
+/// Returns the light carried by the ray, following it through mirrors and glass until it
+/// reaches a diffuse surface. Final gather rays (``gathering``) only see the light that is
+/// not already found by sampling the lights or by the caustic map at the hit they left.
+fn radiance(r: &Ray, scene: &Scene, maps: &PhotonMaps, depth: u32, gathering: bool) -> Colour {
+    if depth == 0 {
+        return Vec3::new();
+    }
+
+    let Some(rec) = scene.world.hit(r, 0.001..f32::INFINITY) else {
+        return match scene.environment {
+            Some(_) if gathering => Vec3::new(),
+            _ => background(r, scene),
+        };
+    };
+
+    let mut colour = match rec.material.light() {
+        Some(_) if gathering => Vec3::new(),
+        _ => rec.material.emitted(r, &rec),
+    };
+
+    if is_diffuse(r, &rec) {
+        colour += direct_light(r, &rec, scene, false) + estimate(&maps.caustic, r, &rec, &maps.settings);
+        colour += if gathering || maps.settings.final_gather == 0 {
+            estimate(&maps.global, r, &rec, &maps.settings)
+        } else {
+            final_gather(r, &rec, scene, maps, depth)
+        };
+    }
+
+    // The mirror-like part of the surface is followed, as photons are not kept there
+    if let Some(x) = rec.material.scatter(r, &rec) {
+        if x.pdf == 0.0 {
+            colour += x.attenuation * radiance(&x.scattered, scene, maps, depth - 1, gathering);
+        }
+    }
+
+    colour
+}
+
+/// Estimates the light reflected diffusely at the hit after another bounce, by tracing rays
+/// from it and looking up the global map where they land.
+fn final_gather(r: &Ray, rec: &HitRecord, scene: &Scene, maps: &PhotonMaps, depth: u32) -> Colour {
+    let mut gathered = Vec3::new();
+    for _ in 0..maps.settings.final_gather {
+        if let Some(x) = rec.material.scatter(r, rec) {
+            if x.pdf > 0.0 {
+                gathered += x.attenuation * radiance(&x.scattered, scene, maps, depth - 1, true);
+            }
+        }
+    }
+
+    gathered / maps.settings.final_gather as f32
+}
+
+/// The first diffuse surface seen through a pixel, where photons are gathered in a
+/// progressive pass
+struct VisiblePoint {
+    r_in: Ray,
+    rec: HitRecord,
+    /// Fraction of the light leaving the point that reaches the camera
+    beta: Vec3,
+}
+
+/// What is known of a pixel over the progressive passes.
+struct PixelState {
+    /// Light found by the camera rays directly, summed over the passes
+    direct: Colour,
+    radius_sq: f32,
+    /// Number of photons the radius has been shrunk to keep
+    photons: f32,
+    /// Flux of the photons found, scaled down with the radius
+    flux: Colour,
+}
+
+/// Follows the ray through mirrors and glass to the first diffuse surface, returning the light
+/// seen along the way (including the light arriving directly at the surface), in RGB, and the
+/// surface. The mirror-like part of that surface (like the coat of a coated one) is path traced,
+/// as photons are only gathered for its diffuse part.
+fn visible_point(r: &Ray, scene: &Scene, camera: &Camera) -> (Colour, Option<VisiblePoint>) {
+    let to_rgb = |colour: Colour| match r.wavelengths {
+        Some(wavelengths) => spectrum::to_rgb(colour, wavelengths),
+        None => colour,
+    };
+
+    let mut ray = *r;
+    let mut beta = Vec3::from_point(1.0, 1.0, 1.0);
+    let mut colour = Vec3::new();
+
+    for bounces in 0..camera.max_depth() {
+        let Some(rec) = scene.world.hit(&ray, 0.001..f32::INFINITY) else {
+            colour += beta * background(&ray, scene);
+            break;
+        };
+        colour += beta * rec.material.emitted(&ray, &rec);
+
+        if is_diffuse(&ray, &rec) {
+            colour += beta * direct_light(&ray, &rec, scene, false);
+            if let Some(x) = rec.material.scatter(&ray, &rec).filter(|x| x.pdf == 0.0) {
+                let depth = camera.max_depth() - bounces - 1;
+                colour += beta * x.attenuation * ray_colour(&x.scattered, scene, depth, camera.path_depth());
+            }
+            return (to_rgb(colour), Some(VisiblePoint { r_in: ray, rec, beta }));
+        }
+
+        let Some(x) = rec.material.scatter(&ray, &rec) else {
+            break;
+        };
+        beta = beta * x.attenuation;
+        ray = x.scattered;
+    }
+
+    (to_rgb(colour), None)
+}
+
+/// Renders the scene with stochastic progressive photon mapping, returning the colour
+/// of every pixel of the output image in RGB. With a ``seed``, every pixel and every photon of
+/// a pass takes its random numbers from it, so that rendering again gives the same image.
+pub fn render_progressive(
+    camera: &Camera,
+    scene: &Scene,
+    lights: &PowerLightSampler,
+    settings: &PhotonSettings,
+    seed: Option<u64>,
+) -> Vec<Colour> {
+    let (width, height) = camera.output_size();
+    let mut pixels: Vec<PixelState> = (0..width * height)
+        .map(|_| PixelState {
+            direct: Vec3::new(),
+            radius_sq: settings.radius * settings.radius,
+            photons: 0.0,
+            flux: Vec3::new(),
+        })
+        .collect();
+
+    for pass in 0..settings.passes {
+        eprint!("\rNumber of passes remaining: {}", settings.passes - pass);
+
+        let points: Vec<(Colour, Option<VisiblePoint>)> = (0..width * height)
+            .into_par_iter()
+            .map(|k| {
+                with_seed(seed.map(|x| mix_seed(&[x, pass as u64, 0, k as u64])), || {
+                    match camera.pixel_ray(k % width, k / width) {
+                        Some(ray) => visible_point(&ray, scene, camera),
+                        None => (Vec3::new(), None),
+                    }
+                })
+            })
+            .collect();
+
+        // Light arriving directly was sampled at the visible points
+        let photons: Vec<Photon> = (0..settings.photons)
+            .into_par_iter()
+            .flat_map_iter(|i| {
+                let mut photons = Vec::new();
+                with_seed(seed.map(|x| mix_seed(&[x, pass as u64, 1, i as u64])), || {
+                    trace_photon(scene, lights, camera, &mut |photon, bounces, _| {
+                        if bounces > 0 {
+                            photons.push(photon);
+                        }
+                    })
+                });
+                photons
+            })
+            .collect();
+        let map = PhotonMap::new(photons);
+
+        pixels.par_iter_mut().zip(points).for_each(|(pixel, (direct, point))| {
+            pixel.direct += direct;
+            let Some(point) = point else {
+                return;
+            };
+
+            let (r, rec) = (&point.r_in, &point.rec);
+            let mut found = 0;
+            let mut flux = Vec3::new();
+            map.within(rec.point, pixel.radius_sq.sqrt(), &mut |photon| {
+                let cos_theta = rec.normal.dot(photon.direction);
+                if cos_theta > 0.0 {
+                    flux += rec.material.eval(r, rec, photon.direction) / cos_theta * r.sample_colour(photon.power);
+                    found += 1;
+                }
+            });
+            let flux = match r.wavelengths {
+                Some(wavelengths) => spectrum::to_rgb(point.beta * flux, wavelengths),
+                None => point.beta * flux,
+            };
+
+            // Keep a fraction of the new photons, shrinking the radius to match
+            if found > 0 {
+                let photons = pixel.photons + settings.alpha * found as f32;
+                let radius_sq = pixel.radius_sq * photons / (pixel.photons + found as f32);
+                pixel.flux = (pixel.flux + flux) * (radius_sq / pixel.radius_sq);
+                pixel.photons = photons;
+                pixel.radius_sq = radius_sq;
+            }
+        });
+    }
+
+    eprintln!();
+
+    let passes = settings.passes.max(1) as f32;
+    let emitted = passes * settings.photons.max(1) as f32;
+    pixels
+        .iter()
+        .map(|x| x.direct / passes + x.flux / (emitted * PI * x.radius_sq))
+        .collect()
+}
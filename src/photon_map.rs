@@ -0,0 +1,239 @@
+//! A photon map: photons left on surfaces by paths traced from the lights, stored in a
+//! kd-tree so that the photons around a point can be found quickly (Refer to [crate::photon]).
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use crate::vec3::{Point3, Vec3};
+
+/// Light arriving at a point of a surface, carried there by a path from a light.
+#[derive(Debug, Clone, Copy)]
+pub struct Photon {
+    pub point: Point3,
+    /// Unit vector towards where the photon came from
+    pub direction: Vec3,
+    /// Flux carried by the photon
+    pub power: Vec3,
+}
+
+/// Photons arranged as a balanced kd-tree. The tree is implicit: the photon in the middle of
+/// each range of the array splits the range along its axis, with the photons before it below
+/// the split and those after it above.
+pub struct PhotonMap {
+    photons: Vec<Photon>,
+    /// Axis each photon splits its range along
+    axes: Vec<u8>,
+}
+
+/// A photon found by [PhotonMap::nearest()], ordered by distance
+struct Candidate {
+    distance_sq: f32,
+    index: usize,
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance_sq.total_cmp(&other.distance_sq)
+    }
+}
+
+#[allow(dead_code)]
+impl PhotonMap {
+    pub fn new(mut photons: Vec<Photon>) -> Self {
+        let mut axes = vec![0; photons.len()];
+        build(&mut photons, &mut axes);
+
+        Self { photons, axes }
+    }
+
+    pub fn len(&self) -> usize {
+        self.photons.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.photons.is_empty()
+    }
+
+    /// Calls ``f`` with every photon within ``radius`` of ``point``
+    pub fn within(&self, point: Point3, radius: f32, f: &mut impl FnMut(&Photon)) {
+        self.within_range(0, self.photons.len(), point, radius * radius, f);
+    }
+
+    /// Finds the (at most) ``count`` photons nearest to ``point``, no further than ``max_radius``.
+    /// Returns them with the squared radius of the disc they were gathered from: the distance to
+    /// the furthest photon if ``count`` were found, and ``max_radius`` otherwise.
+    pub fn nearest(&self, point: Point3, count: usize, max_radius: f32) -> (Vec<&Photon>, f32) {
+        let mut heap = BinaryHeap::with_capacity(count + 1);
+        let mut radius_sq = max_radius * max_radius;
+        if count > 0 {
+            self.nearest_range(0, self.photons.len(), point, count, &mut radius_sq, &mut heap);
+        }
+
+        if heap.len() < count {
+            radius_sq = max_radius * max_radius;
+        }
+        (heap.into_iter().map(|x| &self.photons[x.index]).collect(), radius_sq)
+    }
+
+    fn within_range(&self, lo: usize, hi: usize, point: Point3, radius_sq: f32, f: &mut impl FnMut(&Photon)) {
+        if lo >= hi {
+            return;
+        }
+
+        let mid = lo + (hi - lo) / 2;
+        let photon = &self.photons[mid];
+        let offset = point[self.axes[mid] as usize] - photon.point[self.axes[mid] as usize];
+        let (near, far) = if offset < 0.0 { ((lo, mid), (mid + 1, hi)) } else { ((mid + 1, hi), (lo, mid)) };
+
+        self.within_range(near.0, near.1, point, radius_sq, f);
+        if (photon.point - point).length_sq() <= radius_sq {
+            f(photon);
+        }
+        if offset * offset <= radius_sq {
+            self.within_range(far.0, far.1, point, radius_sq, f);
+        }
+    }
+
+    /// Keeps the nearest photons found so far in ``heap``, shrinking ``radius_sq`` to the
+    /// furthest of them once it is full
+    fn nearest_range(&self, lo: usize, hi: usize, point: Point3, count: usize, radius_sq: &mut f32, heap: &mut BinaryHeap<Candidate>) {
+        if lo >= hi {
+            return;
+        }
+
+        let mid = lo + (hi - lo) / 2;
+        let photon = &self.photons[mid];
+        let offset = point[self.axes[mid] as usize] - photon.point[self.axes[mid] as usize];
+        let (near, far) = if offset < 0.0 { ((lo, mid), (mid + 1, hi)) } else { ((mid + 1, hi), (lo, mid)) };
+
+        self.nearest_range(near.0, near.1, point, count, radius_sq, heap);
+
+        let distance_sq = (photon.point - point).length_sq();
+        if distance_sq <= *radius_sq {
+            heap.push(Candidate { distance_sq, index: mid });
+            if heap.len() > count {
+                heap.pop();
+            }
+            if heap.len() == count {
+                *radius_sq = heap.peek().unwrap().distance_sq;
+            }
+        }
+
+        if offset * offset <= *radius_sq {
+            self.nearest_range(far.0, far.1, point, count, radius_sq, heap);
+        }
+    }
+}
+
+/// Arranges ``photons`` into a kd-tree, splitting each range at its median along the axis
+/// the photons are spread furthest on
+fn build(photons: &mut [Photon], axes: &mut [u8]) {
+    if photons.is_empty() {
+        return;
+    }
+
+    let mut min = photons[0].point;
+    let mut max = photons[0].point;
+    for photon in photons.iter() {
+        min = Vec3::from_point(min.x.min(photon.point.x), min.y.min(photon.point.y), min.z.min(photon.point.z));
+        max = Vec3::from_point(max.x.max(photon.point.x), max.y.max(photon.point.y), max.z.max(photon.point.z));
+    }
+    let extent = max - min;
+    let axis = if extent.x >= extent.y && extent.x >= extent.z {
+        0
+    } else if extent.y >= extent.z {
+        1
+    } else {
+        2
+    };
+
+    let mid = photons.len() / 2;
+    photons.select_nth_unstable_by(mid, |a, b| a.point[axis].total_cmp(&b.point[axis]));
+    axes[mid] = axis as u8;
+
+    let (below, above) = photons.split_at_mut(mid);
+    let (axes_below, axes_above) = axes.split_at_mut(mid);
+    build(below, axes_below);
+    build(&mut above[1..], &mut axes_above[1..]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{random, with_seed};
+
+    /// Photons scattered in the unit cube, each with its index as its power
+    fn photons(count: usize) -> Vec<Photon> {
+        with_seed(Some(7), || {
+            (0..count)
+                .map(|i| Photon {
+                    point: Vec3::from_point(random(), random(), random()),
+                    direction: Vec3::from_point(0.0, 1.0, 0.0),
+                    power: Vec3::from_point(i as f32, 0.0, 0.0),
+                })
+                .collect()
+        })
+    }
+
+    #[test]
+    fn within_finds_the_photons_in_range() {
+        let photons = photons(500);
+        let map = PhotonMap::new(photons.clone());
+        assert_eq!(map.len(), 500);
+
+        let queries = with_seed(Some(8), || (0..20).map(|_| Vec3::from_point(random(), random(), random())).collect::<Vec<_>>());
+        for point in queries {
+            for radius in [0.05, 0.2, 0.5] {
+                let mut found = Vec::new();
+                map.within(point, radius, &mut |photon| found.push(photon.power.x as usize));
+                found.sort_unstable();
+
+                let expected: Vec<usize> = photons
+                    .iter()
+                    .filter(|x| (x.point - point).length_sq() <= radius * radius)
+                    .map(|x| x.power.x as usize)
+                    .collect();
+                assert_eq!(found, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn nearest_finds_the_closest_photons() {
+        let photons = photons(200);
+        let map = PhotonMap::new(photons.clone());
+        let point = Vec3::from_point(0.5, 0.5, 0.5);
+
+        let (found, radius_sq) = map.nearest(point, 10, 1.0);
+        let mut distances: Vec<f32> = photons.iter().map(|x| (x.point - point).length_sq()).collect();
+        distances.sort_by(f32::total_cmp);
+
+        assert_eq!(found.len(), 10);
+        assert_eq!(radius_sq, distances[9]);
+        assert!(found.iter().all(|x| (x.point - point).length_sq() <= distances[9]));
+    }
+
+    #[test]
+    fn empty_map_finds_nothing() {
+        let map = PhotonMap::new(Vec::new());
+        let mut found = 0;
+        map.within(Vec3::new(), 1.0, &mut |_| found += 1);
+
+        assert!(map.is_empty());
+        assert_eq!(found, 0);
+    }
+}
@@ -1,12 +1,21 @@
 //! Simple abstraction to store a ray, that is defined by a point relative to the origin.
 
+use crate::spectrum;
 use crate::vec3::{Point3, Vec3};
 
 #[derive(Debug, Clone, Copy)]
 pub struct Ray {
     pub origin: Point3,
     pub direction: Vec3,
-    pub time: f32
+    pub time: f32,
+    /// The wavelengths carried by the ray in spectral mode. Refer to [spectrum].
+    pub wavelengths: Option<Vec3>,
+    /// Whether all but the first (hero) wavelength were dropped, by passing through a
+    /// dispersive medium (Refer to [crate::material::Dispersion])
+    pub hero_only: bool,
+    /// The only RGB channel still carried by the ray, once it has passed through a
+    /// dispersive medium (Refer to [crate::material::Dispersion])
+    pub channel: Option<usize>,
 }
 
 #[allow(dead_code)]
@@ -16,13 +25,39 @@ impl Ray {
         Ray {
             origin: Point3::new(),
             direction: Vec3::new(),
-	    time: 0.0
+	    time: 0.0,
+            wavelengths: None,
+            hero_only: false,
+            channel: None,
         }
     }
 
     /// Constructs a new ray from the point and direction
     pub const fn construct(origin: Vec3, direction: Point3, time: f32) -> Ray {
-        Ray { origin, direction, time}
+        Ray { origin, direction, time, wavelengths: None, hero_only: false, channel: None }
+    }
+
+    /// Constructs a ray continuing the path of this one from a new origin and direction,
+    /// carrying over the time, the wavelengths and the channel of the ray.
+    pub const fn spawn(&self, origin: Point3, direction: Vec3) -> Ray {
+        Ray {
+            origin,
+            direction,
+            time: self.time,
+            wavelengths: self.wavelengths,
+            hero_only: self.hero_only,
+            channel: self.channel,
+        }
+    }
+
+    /// Converts an RGB colour into the quantity carried by the ray.
+    /// This is the colour itself, unless the ray is spectral, in which case
+    /// it is the value of the colour's spectrum at the ray's wavelengths.
+    pub fn sample_colour(&self, rgb: Vec3) -> Vec3 {
+        match self.wavelengths {
+            Some(wavelengths) => spectrum::upsample(rgb, wavelengths),
+            None => rgb,
+        }
     }
 
     /// Calculates the point at ``t`` distance from the origin of the ray
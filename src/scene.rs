@@ -0,0 +1,107 @@
+//! Everything that is rendered: the objects, the lights that are sampled explicitly,
+//! and what lies beyond them.
+
+use std::sync::{Arc, OnceLock};
+
+use crate::environment::Environment;
+use crate::hittable::World;
+use crate::light::{Light, SphereLight};
+use crate::light_sampler::{LightSampler, LightSelection};
+use crate::material::DiffuseLight;
+use crate::sphere::Sphere;
+use crate::vec3::{Point3, Vec3};
+
+/// Holds the complete scene.
+pub struct Scene {
+    pub world: World,
+    pub lights: Vec<Box<dyn Light>>,
+    /// Surrounds the scene. Without one, rays that escape see a plain sky gradient.
+    pub environment: Option<Environment>,
+    /// How the light sampled at each hit is chosen
+    pub light_selection: LightSelection,
+
+    /// Built on first use, once every light has been added
+    light_sampler: OnceLock<Box<dyn LightSampler>>,
+}
+
+#[allow(dead_code)]
+impl Scene {
+    /// Creates a scene of the given objects, without any lights other than the sky.
+    pub fn new(world: World) -> Self {
+        Self {
+            world,
+            lights: Vec::new(),
+            environment: None,
+            light_selection: LightSelection::Automatic,
+            light_sampler: OnceLock::new(),
+        }
+    }
+
+    /// Adds a light to the scene
+    pub fn push_light(&mut self, light: Box<dyn Light>) {
+        self.lights.push(light);
+        self.light_sampler = OnceLock::new();
+    }
+
+    /// Adds a glowing sphere to the scene, which is both seen by rays and sampled as a light.
+    pub fn push_area_light(&mut self, centre: Point3, radius: f32, radiance: Vec3) {
+        let material = DiffuseLight::new(radiance).with_light(self.lights.len());
+        self.world.push(Box::new(Sphere::stationary_from_dim(centre, radius, Arc::new(material))));
+        self.push_light(Box::new(SphereLight::new(centre, radius, radiance)));
+    }
+
+    /// Surrounds the scene with an environment map, which also lights it
+    pub fn set_environment(&mut self, environment: Environment) {
+        self.environment = Some(environment);
+        self.light_sampler = OnceLock::new();
+    }
+
+    pub fn set_light_selection(&mut self, selection: LightSelection) {
+        self.light_selection = selection;
+        self.light_sampler = OnceLock::new();
+    }
+
+    /// Every light that can be sampled explicitly. The environment comes last, after
+    /// [Scene::lights], which gives the index it is known by.
+    pub fn all_lights(&self) -> impl Iterator<Item = &dyn Light> {
+        self.lights
+            .iter()
+            .map(|x| x.as_ref())
+            .chain(self.environment.as_ref().map(|x| x as &dyn Light))
+    }
+
+    /// Index of the environment among [Scene::all_lights()]
+    pub fn environment_index(&self) -> usize {
+        self.lights.len()
+    }
+
+    /// Returns the light at ``index`` among [Scene::all_lights()]
+    pub fn light(&self, index: usize) -> Option<&dyn Light> {
+        match self.lights.get(index) {
+            Some(light) => Some(light.as_ref()),
+            None if index == self.environment_index() => self.environment.as_ref().map(|x| x as &dyn Light),
+            None => None,
+        }
+    }
+
+    /// Chooses a light to sample at ``point``, returning its index and the probability
+    /// of choosing it
+    pub fn sample_light(&self, point: Point3, u: f32) -> Option<(usize, f32)> {
+        self.light_sampler().sample(point, u)
+    }
+
+    /// Density of sampling ``direction`` from ``point`` through the light at ``index``,
+    /// including the probability of choosing the light
+    pub fn light_pdf(&self, point: Point3, index: usize, direction: Vec3) -> f32 {
+        match self.light(index) {
+            Some(light) => self.light_sampler().pmf(point, index) * light.pdf(point, direction),
+            None => 0.0,
+        }
+    }
+
+    fn light_sampler(&self) -> &dyn LightSampler {
+        self.light_sampler
+            .get_or_init(|| self.light_selection.build(&self.all_lights().collect::<Vec<_>>()))
+            .as_ref()
+    }
+}
@@ -0,0 +1,218 @@
+//! An analytic model of daylight: the clear sky of Preetham, Shirley and Smits
+//! ("A Practical Analytic Model for Daylight"), together with the disc of the sun.
+//!
+//! The sky is baked into an [Environment], so that it is seen by rays escaping the scene
+//! and importance sampled like any other environment map. The sun is part of the same map,
+//! so it also shows up in reflections, and its brightness steers the sampling towards it.
+
+use std::f32::consts::PI;
+
+use crate::environment::Environment;
+use crate::image::Image;
+use crate::spectrum::{self, luminance, RGB_WAVELENGTHS};
+use crate::vec3::Vec3;
+
+/// Angular diameter of the sun, in degrees
+pub const SUN_ANGULAR_DIAMETER: f32 = 0.53;
+
+/// Luminance of the sun outside the atmosphere, in kcd/m²
+const SUN_LUMINANCE: f32 = 2.0e6;
+
+/// The model gives luminance in kcd/m². It is scaled down so that a white surface in
+/// daylight comes out with a brightness of about 1.
+const SCALE: f32 = 0.05;
+
+/// A clear sky, lit by the sun.
+#[derive(Debug, Clone, Copy)]
+pub struct Sky {
+    /// Height of the sun above the horizon, in degrees
+    pub elevation: f32,
+    /// Direction of the sun around the up axis, in degrees from +x towards +z
+    pub azimuth: f32,
+    /// Haziness of the atmosphere, from 2 (very clear) to 10 (hazy)
+    pub turbidity: f32,
+    /// Albedo of the ground below the horizon
+    pub ground: Vec3,
+}
+
+#[allow(dead_code)]
+impl Sky {
+    pub fn new(elevation: f32, azimuth: f32, turbidity: f32) -> Self {
+        Self {
+            elevation,
+            azimuth,
+            turbidity: turbidity.clamp(2.0, 10.0),
+            ground: Vec3::from_point(0.3, 0.3, 0.3),
+        }
+    }
+
+    pub fn with_ground(mut self, albedo: Vec3) -> Self {
+        self.ground = albedo;
+        self
+    }
+
+    /// Unit vector towards the sun
+    pub fn sun_direction(&self) -> Vec3 {
+        let (elevation, azimuth) = (self.elevation.to_radians(), self.azimuth.to_radians());
+        Vec3::from_point(elevation.cos() * azimuth.cos(), elevation.sin(), elevation.cos() * azimuth.sin())
+    }
+
+    /// Radiance of the disc of the sun, dimmed and reddened by the atmosphere
+    pub fn sun_radiance(&self) -> Vec3 {
+        if self.elevation <= 0.0 {
+            return Vec3::new();
+        }
+
+        // Relative length of the path through the atmosphere (Kasten's air mass formula)
+        let zenith = 90.0 - self.elevation;
+        let air_mass = 1.0 / (zenith.to_radians().cos() + 0.15 * (93.885 - zenith).powf(-1.253));
+
+        // Optical depth of air molecules (Rayleigh) and of haze (Ångström), with wavelengths in μm
+        let beta = 0.04608 * self.turbidity - 0.04586;
+        let transmittance = RGB_WAVELENGTHS.map(|lambda| {
+            let lambda = lambda / 1000.0;
+            (-air_mass * (0.008735 * lambda.powf(-4.08) + beta * lambda.powf(-1.3))).exp()
+        });
+
+        SUN_LUMINANCE * SCALE * transmittance
+    }
+
+    /// Radiance of the sky (without the sun) in a unit direction above the horizon
+    pub fn radiance(&self, direction: Vec3) -> Vec3 {
+        let t = self.turbidity;
+        // The model breaks down once the sun sets, so it is kept on the horizon
+        let theta_s = (90.0 - self.elevation).clamp(0.0, 89.9).to_radians();
+
+        let theta = direction.y.clamp(0.001, 1.0).acos();
+        let gamma = direction.unit().dot(self.sun_direction()).clamp(-1.0, 1.0).acos();
+
+        // Distribution coefficients (A to E) for luminance and the two chromaticities
+        let luminance_coefficients = [
+            0.1787 * t - 1.4630,
+            -0.3554 * t + 0.4275,
+            -0.0227 * t + 5.3251,
+            0.1206 * t - 2.5771,
+            -0.0670 * t + 0.3703,
+        ];
+        let x_coefficients = [
+            -0.0193 * t - 0.2592,
+            -0.0665 * t + 0.0008,
+            -0.0004 * t + 0.2125,
+            -0.0641 * t - 0.8989,
+            -0.0033 * t + 0.0452,
+        ];
+        let y_coefficients = [
+            -0.0167 * t - 0.2608,
+            -0.0950 * t + 0.0092,
+            -0.0079 * t + 0.2102,
+            -0.0441 * t - 1.6537,
+            -0.0109 * t + 0.0529,
+        ];
+
+        // Values at the zenith
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let zenith_luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let polynomial = |c: [[f32; 4]; 3]| {
+            let row = |r: [f32; 4]| r[0] * theta_s.powi(3) + r[1] * theta_s.powi(2) + r[2] * theta_s + r[3];
+            t * t * row(c[0]) + t * row(c[1]) + row(c[2])
+        };
+        let zenith_x = polynomial([
+            [0.00166, -0.00375, 0.00209, 0.0],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886],
+        ]);
+        let zenith_y = polynomial([
+            [0.00275, -0.00610, 0.00317, 0.0],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688],
+        ]);
+
+        // Each value is scaled from the zenith by the Perez distribution
+        let relative = |c: [f32; 5]| perez(c, theta, gamma) / perez(c, 0.0, theta_s);
+        let luminance = zenith_luminance * relative(luminance_coefficients);
+        let x = zenith_x * relative(x_coefficients);
+        let y = zenith_y * relative(y_coefficients);
+
+        let xyz = Vec3::from_point(x / y * luminance, luminance, (1.0 - x - y) / y * luminance);
+        SCALE * spectrum::xyz_to_rgb(xyz).map(|c| c.max(0.0))
+    }
+
+    /// Bakes the sky and the sun into an environment map ``width`` pixels wide.
+    /// The sun covers a few pixels at a width of 2048.
+    pub fn environment(&self, width: usize) -> Environment {
+        let height = (width / 2).max(1);
+        let ground = self.ground * self.horizontal_irradiance() / PI;
+
+        let sun = self.sun_direction();
+        let sun_radiance = self.sun_radiance();
+        let cos_sun = (SUN_ANGULAR_DIAMETER.to_radians() / 2.0).cos();
+        // Pixels this close to the sun may be partly covered by it
+        let cos_near = (SUN_ANGULAR_DIAMETER.to_radians() / 2.0 + 2.0 * PI / width as f32).cos();
+
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let direction = pixel_direction(x as f32 + 0.5, y as f32 + 0.5, width, height);
+                if direction.y < 0.0 {
+                    pixels.push(ground);
+                    continue;
+                }
+
+                let mut colour = self.radiance(direction);
+
+                if direction.dot(sun) > cos_near {
+                    // Supersample the edge of the disc
+                    const N: usize = 8;
+                    let mut covered = 0;
+                    for k in 0..N * N {
+                        let (sx, sy) = ((k % N) as f32 + 0.5, (k / N) as f32 + 0.5);
+                        let d = pixel_direction(x as f32 + sx / N as f32, y as f32 + sy / N as f32, width, height);
+                        if d.dot(sun) > cos_sun {
+                            covered += 1;
+                        }
+                    }
+                    colour += (covered as f32 / (N * N) as f32) * sun_radiance;
+                }
+
+                pixels.push(colour);
+            }
+        }
+
+        Environment::new(Image { width, height, pixels })
+    }
+
+    /// Light falling on a horizontal surface from the sun and the sky
+    fn horizontal_irradiance(&self) -> f32 {
+        let sun_solid_angle = 2.0 * PI * (1.0 - (SUN_ANGULAR_DIAMETER.to_radians() / 2.0).cos());
+        let sun = luminance(self.sun_radiance()) * sun_solid_angle * self.sun_direction().y.max(0.0);
+
+        // Integrate the sky over the hemisphere, in rings of equal height
+        const RINGS: usize = 16;
+        const SEGMENTS: usize = 32;
+        let mut sky = 0.0;
+        for i in 0..RINGS {
+            let cos_theta = (i as f32 + 0.5) / RINGS as f32;
+            let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+            for j in 0..SEGMENTS {
+                let phi = 2.0 * PI * (j as f32 + 0.5) / SEGMENTS as f32;
+                let direction = Vec3::from_point(sin_theta * phi.cos(), cos_theta, sin_theta * phi.sin());
+                sky += luminance(self.radiance(direction)) * cos_theta;
+            }
+        }
+
+        sun + sky * 2.0 * PI / (RINGS * SEGMENTS) as f32
+    }
+}
+
+/// The Perez sky distribution, at an angle ``theta`` from the zenith and ``gamma`` from the sun
+fn perez(c: [f32; 5], theta: f32, gamma: f32) -> f32 {
+    (1.0 + c[0] * (c[1] / theta.cos()).exp()) * (1.0 + c[2] * (c[3] * gamma).exp() + c[4] * gamma.cos().powi(2))
+}
+
+/// Direction through a point of an environment map, using the layout of [Environment]
+fn pixel_direction(x: f32, y: f32, width: usize, height: usize) -> Vec3 {
+    let theta = PI * y / height as f32;
+    let phi = 2.0 * PI * (x / width as f32 - 0.5);
+
+    Vec3::from_point(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin())
+}
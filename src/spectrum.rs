@@ -0,0 +1,176 @@
+//! Helpers for spectral rendering.
+//!
+//! In spectral mode, every camera ray carries three wavelengths (Refer to [sample_wavelengths()]),
+//! and the three components of a [Vec3] colour hold the radiance at each of those wavelengths,
+//! rather than red, green and blue. RGB colours from textures are turned into spectra with
+//! [upsample()], and the radiance is turned back into RGB at the film with [to_rgb()].
+
+use crate::vec3::Vec3;
+
+/// The shortest wavelength (in nanometres) that is sampled
+pub const LAMBDA_MIN: f32 = 360.0;
+/// The longest wavelength (in nanometres) that is sampled
+pub const LAMBDA_MAX: f32 = 830.0;
+
+/// Wavelengths (in nanometres) taken as representative of the red, green and blue channels.
+pub const RGB_WAVELENGTHS: Vec3 = Vec3::from_point(630.0, 532.0, 465.0);
+
+/// Integral of the CIE ``ȳ`` matching function, used to normalise luminance.
+const CIE_Y_INTEGRAL: f32 = 106.857;
+
+/// Linear sRGB of an equal energy spectrum. Dividing by this white balances
+/// the film, so that a constant spectrum of 1 maps back to white.
+const EQUAL_ENERGY_RGB: Vec3 = Vec3::from_point(1.2048, 0.9484, 0.9087);
+
+/// Samples three wavelengths using hero wavelength sampling.
+/// The hero wavelength is chosen uniformly using ``u``, and the other two are
+/// spaced evenly across the visible range from it, wrapping around at the ends.
+pub fn sample_wavelengths(u: f32) -> Vec3 {
+    let range = LAMBDA_MAX - LAMBDA_MIN;
+    let rotate = |offset: f32| LAMBDA_MIN + ((u + offset) % 1.0) * range;
+
+    Vec3::from_point(rotate(0.0), rotate(1.0 / 3.0), rotate(2.0 / 3.0))
+}
+
+/// Converts an RGB colour into the value of a smooth spectrum at each of the given wavelengths.
+///
+/// The spectrum is a sum of three smooth bands (blue, green and red) that add up to 1
+/// everywhere, so white maps to a constant spectrum and colours in [0, 1] stay in [0, 1].
+pub fn upsample(rgb: Vec3, wavelengths: Vec3) -> Vec3 {
+    wavelengths.map(|lambda| {
+        let blue = 1.0 - smoothstep(470.0, 510.0, lambda);
+        let red = smoothstep(570.0, 610.0, lambda);
+        let green = 1.0 - blue - red;
+
+        red * rgb.x + green * rgb.y + blue * rgb.z
+    })
+}
+
+/// Converts the radiance carried at three wavelengths back into a linear RGB colour.
+/// Each wavelength is weighted by the CIE matching functions to get XYZ, which is then
+/// converted to (white balanced) linear sRGB.
+pub fn to_rgb(radiance: Vec3, wavelengths: Vec3) -> Vec3 {
+    let mut xyz = Vec3::new();
+    for i in 0..3 {
+        xyz += radiance[i] * cie_xyz(wavelengths[i]);
+    }
+    xyz *= (LAMBDA_MAX - LAMBDA_MIN) / (3.0 * CIE_Y_INTEGRAL);
+
+    let rgb = xyz_to_rgb(xyz);
+
+    Vec3::from_point(
+        (rgb.x / EQUAL_ENERGY_RGB.x).max(0.0),
+        (rgb.y / EQUAL_ENERGY_RGB.y).max(0.0),
+        (rgb.z / EQUAL_ENERGY_RGB.z).max(0.0),
+    )
+}
+
+/// Converts a CIE XYZ colour to linear sRGB (with a D65 white point).
+pub fn xyz_to_rgb(xyz: Vec3) -> Vec3 {
+    Vec3::from_point(
+        3.2406 * xyz.x - 1.5372 * xyz.y - 0.4986 * xyz.z,
+        -0.9689 * xyz.x + 1.8758 * xyz.y + 0.0415 * xyz.z,
+        0.0557 * xyz.x - 0.2040 * xyz.y + 1.0570 * xyz.z,
+    )
+}
+
+/// Brightness (the Y of CIE XYZ) of a linear sRGB colour
+pub fn luminance(rgb: Vec3) -> f32 {
+    0.2126 * rgb.x + 0.7152 * rgb.y + 0.0722 * rgb.z
+}
+
+/// The CIE 1931 colour matching functions at a given wavelength, using the
+/// multi-lobe Gaussian fit by Wyman, Sloan and Shirley.
+pub fn cie_xyz(lambda: f32) -> Vec3 {
+    let g = |mu: f32, sigma_low: f32, sigma_high: f32| {
+        let sigma = if lambda < mu { sigma_low } else { sigma_high };
+        (-0.5 * ((lambda - mu) / sigma).powi(2)).exp()
+    };
+
+    Vec3::from_point(
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+        0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+        1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8),
+    )
+}
+
+/// A refractive index with both a real (``eta``) and imaginary (``k``) part,
+/// tabulated at evenly spaced wavelengths. This is how metals are described.
+#[derive(Debug, Clone, Copy)]
+pub struct SpectralIor {
+    /// Wavelength (in nanometres) of the first entry
+    pub start: f32,
+    /// Spacing (in nanometres) between entries
+    pub step: f32,
+    pub eta: &'static [f32],
+    pub k: &'static [f32],
+}
+
+#[allow(dead_code)]
+impl SpectralIor {
+    pub const GOLD: SpectralIor = SpectralIor {
+        start: 400.0,
+        step: 50.0,
+        eta: &[1.658, 1.500, 0.970, 0.430, 0.250, 0.170, 0.160],
+        k: &[1.956, 1.880, 1.870, 2.450, 2.980, 3.450, 3.950],
+    };
+
+    pub const COPPER: SpectralIor = SpectralIor {
+        start: 400.0,
+        step: 50.0,
+        eta: &[1.180, 1.170, 1.120, 1.020, 0.270, 0.210, 0.210],
+        k: &[2.210, 2.400, 2.600, 2.580, 3.410, 3.670, 4.050],
+    };
+
+    pub const SILVER: SpectralIor = SpectralIor {
+        start: 400.0,
+        step: 50.0,
+        eta: &[0.050, 0.040, 0.050, 0.060, 0.060, 0.070, 0.040],
+        k: &[2.100, 2.650, 3.130, 3.590, 4.010, 4.420, 4.830],
+    };
+
+    pub const ALUMINIUM: SpectralIor = SpectralIor {
+        start: 400.0,
+        step: 50.0,
+        eta: &[0.490, 0.620, 0.770, 0.960, 1.200, 1.470, 1.830],
+        k: &[4.860, 5.470, 6.080, 6.690, 7.260, 7.790, 8.310],
+    };
+
+    /// Returns ``(eta, k)`` at the given wavelength, linearly interpolating between
+    /// entries, and clamping outside the table.
+    pub fn at(&self, lambda: f32) -> (f32, f32) {
+        let last = self.eta.len() - 1;
+        let x = ((lambda - self.start) / self.step).clamp(0.0, last as f32);
+        let i = (x as usize).min(last.saturating_sub(1));
+        let t = x - i as f32;
+        let lerp = |table: &[f32]| table[i] + t * (table[(i + 1).min(last)] - table[i]);
+
+        (lerp(self.eta), lerp(self.k))
+    }
+}
+
+/// Fresnel reflectance of unpolarised light at a conductor.
+pub fn fresnel_conductor(cos_theta: f32, eta: f32, k: f32) -> f32 {
+    let cos2 = cos_theta * cos_theta;
+    let sin2 = 1.0 - cos2;
+    let eta2 = eta * eta;
+    let k2 = k * k;
+
+    let t0 = eta2 - k2 - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+    let t1 = a2_plus_b2 + cos2;
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+    let t2 = 2.0 * cos_theta * a;
+    let rs = (t1 - t2) / (t1 + t2);
+
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+
+    0.5 * (rs + rp)
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
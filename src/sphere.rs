@@ -98,6 +98,8 @@ impl Hittable for Sphere {
             );
 
             rec.set_face_normal(r, outward_normal);
+            // Derivative of the point along u, from the parameterisation in get_uv()
+            rec.set_tangents(Vec3::from_point(outward_normal.z, 0.0, -outward_normal.x));
             rec
         })
     }
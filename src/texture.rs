@@ -2,6 +2,13 @@ use crate::vec3::Vec3;
 
 pub trait Texture {
     fn value (&self, u: f32, v: f32, p: &Vec3) -> Vec3;
+
+    /// Reads the texture as a single scalar, taken as the mean of its channels.
+    /// Useful for masks, where only a weight is required.
+    fn scalar (&self, u: f32, v: f32, p: &Vec3) -> f32 {
+        let value = self.value(u, v, p);
+        (value.x + value.y + value.z) / 3.0
+    }
 }
 
 pub type Solid = Vec3;
@@ -14,7 +21,7 @@ impl Solid {
 
 impl Texture for Solid {
     fn value (&self, _u: f32, _v: f32, _p: &Vec3) -> Vec3 {
-	self.clone()
+	*self
     }
 }
 
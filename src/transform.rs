@@ -0,0 +1,218 @@
+//! Placing objects in the scene with rigid transforms (and a uniform scale), which may
+//! change over time to give motion blur, including rotational blur.
+
+use crate::animation::Curve;
+use crate::hittable::{HitRecord, Hittable};
+use crate::ray::Ray;
+use crate::vec3::Vec3;
+use std::ops::Range;
+
+/// A quaternion, used to represent rotations.
+#[derive(Debug, Clone, Copy)]
+pub struct Quaternion {
+    pub w: f32,
+    pub v: Vec3,
+}
+
+#[allow(dead_code)]
+impl Quaternion {
+    /// The rotation that does nothing
+    pub const IDENTITY: Quaternion = Quaternion { w: 1.0, v: Vec3::new() };
+
+    /// Creates a rotation by ``angle`` degrees (anticlockwise) around ``axis``.
+    pub fn from_axis_angle(axis: Vec3, angle: f32) -> Self {
+        let half = angle.to_radians() / 2.0;
+        Self {
+            w: half.cos(),
+            v: half.sin() * axis.unit(),
+        }
+    }
+
+    /// Composes two rotations, such that ``self`` is applied after ``rhs``.
+    pub fn then_after(self, rhs: Quaternion) -> Quaternion {
+        Quaternion {
+            w: self.w * rhs.w - self.v.dot(rhs.v),
+            v: self.w * rhs.v + rhs.w * self.v + self.v.cross(rhs.v),
+        }
+    }
+
+    /// Returns the opposite rotation
+    pub fn inverse(self) -> Quaternion {
+        Quaternion { w: self.w, v: -self.v }
+    }
+
+    /// Rotates a vector
+    pub fn rotate(self, p: Vec3) -> Vec3 {
+        let t = 2.0 * self.v.cross(p);
+        p + self.w * t + self.v.cross(t)
+    }
+
+    /// Spherically interpolates between two rotations, following the shortest arc.
+    pub fn slerp(self, other: Quaternion, t: f32) -> Quaternion {
+        let mut cos = self.w * other.w + self.v.dot(other.v);
+        let mut other = other;
+        if cos < 0.0 {
+            cos = -cos;
+            other = Quaternion { w: -other.w, v: -other.v };
+        }
+
+        let (a, b) = if cos > 0.9995 {
+            // Nearly the same rotation: fall back to linear interpolation
+            (1.0 - t, t)
+        } else {
+            let theta = cos.acos();
+            (((1.0 - t) * theta).sin() / theta.sin(), (t * theta).sin() / theta.sin())
+        };
+
+        let q = Quaternion { w: a * self.w + b * other.w, v: a * self.v + b * other.v };
+        let length = (q.w * q.w + q.v.length_sq()).sqrt();
+        Quaternion { w: q.w / length, v: q.v / length }
+    }
+}
+
+/// A rotation, followed by a uniform scale, followed by a translation.
+#[derive(Debug, Clone, Copy)]
+pub struct Transform {
+    pub translation: Vec3,
+    pub rotation: Quaternion,
+    pub scale: f32,
+}
+
+#[allow(dead_code)]
+impl Transform {
+    /// The transform that does nothing
+    pub const IDENTITY: Transform = Transform {
+        translation: Vec3::new(),
+        rotation: Quaternion::IDENTITY,
+        scale: 1.0,
+    };
+
+    pub const fn new(translation: Vec3, rotation: Quaternion, scale: f32) -> Self {
+        Self { translation, rotation, scale }
+    }
+
+    /// Interpolates between two transforms.
+    pub fn lerp(&self, other: &Transform, t: f32) -> Transform {
+        Transform {
+            translation: self.translation + t * (other.translation - self.translation),
+            rotation: self.rotation.slerp(other.rotation, t),
+            scale: self.scale + t * (other.scale - self.scale),
+        }
+    }
+
+    /// Transforms a point
+    pub fn point(&self, p: Vec3) -> Vec3 {
+        self.scale * self.rotation.rotate(p) + self.translation
+    }
+
+    /// Transforms a direction. Since the scale is uniform, this also works for normals,
+    /// up to their length.
+    pub fn vector(&self, v: Vec3) -> Vec3 {
+        self.scale * self.rotation.rotate(v)
+    }
+
+    /// Undoes the transform on a point
+    pub fn inverse_point(&self, p: Vec3) -> Vec3 {
+        self.rotation.inverse().rotate(p - self.translation) / self.scale
+    }
+
+    /// Undoes the transform on a direction
+    pub fn inverse_vector(&self, v: Vec3) -> Vec3 {
+        self.rotation.inverse().rotate(v) / self.scale
+    }
+}
+
+/// Places any object in the scene with a [Transform], which can be animated with keyframes
+/// or curves. The transform follows the time of the ray.
+pub struct Transformed {
+    object: Box<dyn Hittable>,
+    motion: Motion,
+}
+
+/// How the transform of a [Transformed] object changes over time.
+enum Motion {
+    /// Pairs of time and transform, sorted by time, linearly interpolated
+    Keyframes(Vec<(f32, Transform)>),
+    /// A rotation of ``angle`` degrees around ``axis``, with each part following a curve
+    Curves {
+        translation: Curve<Vec3>,
+        axis: Vec3,
+        angle: Curve<f32>,
+        scale: Curve<f32>,
+    },
+}
+
+#[allow(dead_code)]
+impl Transformed {
+    /// Places the object with a fixed transform
+    pub fn new(object: Box<dyn Hittable>, transform: Transform) -> Self {
+        Self::animated(object, vec![(0.0, transform)])
+    }
+
+    /// Animates the object with transforms at given times. Between keyframes, the
+    /// transform is interpolated linearly (and spherically for rotations). Before the
+    /// first and after the last keyframe, the object holds still.
+    pub fn animated(object: Box<dyn Hittable>, mut keyframes: Vec<(f32, Transform)>) -> Self {
+        if keyframes.is_empty() {
+            keyframes.push((0.0, Transform::IDENTITY));
+        }
+        keyframes.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        Self { object, motion: Motion::Keyframes(keyframes) }
+    }
+
+    /// Animates the object with separate curves for its translation, its rotation
+    /// (in degrees around a fixed ``axis``) and its scale. Refer to [Curve].
+    pub fn with_curves(
+        object: Box<dyn Hittable>,
+        translation: Curve<Vec3>,
+        axis: Vec3,
+        angle: Curve<f32>,
+        scale: Curve<f32>,
+    ) -> Self {
+        Self {
+            object,
+            motion: Motion::Curves { translation, axis, angle, scale },
+        }
+    }
+
+    /// Returns the transform at the given time
+    pub fn at(&self, time: f32) -> Transform {
+        match &self.motion {
+            Motion::Keyframes(keyframes) => {
+                let next = keyframes.partition_point(|x| x.0 <= time);
+
+                match (keyframes.get(next.wrapping_sub(1)), keyframes.get(next)) {
+                    (Some((t0, a)), Some((t1, b))) => a.lerp(b, (time - t0) / (t1 - t0)),
+                    (Some((_, a)), None) | (None, Some((_, a))) => *a,
+                    (None, None) => Transform::IDENTITY,
+                }
+            }
+            Motion::Curves { translation, axis, angle, scale } => Transform::new(
+                translation.at(time),
+                Quaternion::from_axis_angle(*axis, angle.at(time)),
+                scale.at(time),
+            ),
+        }
+    }
+}
+
+impl Hittable for Transformed {
+    /// The ray is moved into the space of the object instead of moving the object.
+    /// Both the origin and the direction are scaled, so ``t`` is the same in both spaces.
+    fn hit(&self, r: &Ray, t_range: Range<f32>) -> Option<HitRecord> {
+        let transform = self.at(r.time);
+
+        let mut local = *r;
+        local.origin = transform.inverse_point(r.origin);
+        local.direction = transform.inverse_vector(r.direction);
+
+        let mut rec = self.object.hit(&local, t_range)?;
+        rec.point = transform.point(rec.point);
+        rec.normal = transform.rotation.rotate(rec.normal);
+        rec.tangent = transform.rotation.rotate(rec.tangent);
+        rec.bitangent = transform.rotation.rotate(rec.bitangent);
+
+        Some(rec)
+    }
+}
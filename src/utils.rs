@@ -1,12 +1,78 @@
-use rand::{distributions::Standard, Rng};
+use rand::rngs::SmallRng;
+use rand::{distributions::Standard, Rng, SeedableRng};
+use std::cell::{Cell, RefCell};
 use std::ops::Range;
 
+thread_local! {
+    /// Replaces the random numbers of the current thread while set (Refer to [set_random_source()]).
+    static SOURCE: Cell<Option<fn() -> f32>> = const { Cell::new(None) };
+    /// Seeded generator used instead of the thread's own while set (Refer to [with_seed()]).
+    static SEEDED: RefCell<Option<SmallRng>> = const { RefCell::new(None) };
+}
+
 /// Generates a random floating point number
 pub fn random() -> f32 {
-    rand::thread_rng().sample::<f32, Standard>(Standard)
+    match SOURCE.get() {
+        Some(source) => source(),
+        None => uniform(),
+    }
 }
 
 /// Generates a random number in the given range
 pub fn random_range(r: Range<f32>) -> f32 {
-    rand::thread_rng().gen_range(r)
+    match SOURCE.get() {
+        Some(source) => r.start + source() * (r.end - r.start),
+        None => SEEDED.with_borrow_mut(|x| match x {
+            Some(rng) => rng.gen_range(r),
+            None => rand::thread_rng().gen_range(r),
+        }),
+    }
+}
+
+/// Generates a random floating point number from the thread's generator,
+/// even while its random numbers are replaced
+pub fn uniform() -> f32 {
+    SEEDED.with_borrow_mut(|x| match x {
+        Some(rng) => rng.sample::<f32, Standard>(Standard),
+        None => rand::thread_rng().sample::<f32, Standard>(Standard),
+    })
+}
+
+/// Makes the random numbers of the current thread come from a generator seeded with ``seed``,
+/// so that its random decisions can be repeated, or from the thread's own generator again
+/// with ``None``.
+pub fn seed_random(seed: Option<u64>) {
+    SEEDED.set(seed.map(SmallRng::seed_from_u64));
+}
+
+/// Runs ``f`` with the random numbers of the current thread seeded with ``seed`` (Refer to
+/// [seed_random()]), or as is with ``None``. Work handed to other threads (by rayon) is not
+/// covered, and needs seeding of its own.
+pub fn with_seed<T>(seed: Option<u64>, f: impl FnOnce() -> T) -> T {
+    let Some(seed) = seed else {
+        return f();
+    };
+
+    let previous = SEEDED.replace(Some(SmallRng::seed_from_u64(seed)));
+    let result = f();
+    SEEDED.set(previous);
+    result
+}
+
+/// Mixes the given numbers into one seed, such as a render's seed with the index of a pixel and
+/// of a sample in it, so that every sample gets random numbers of its own (SplitMix64).
+pub fn mix_seed(values: &[u64]) -> u64 {
+    values.iter().fold(0x9e37_79b9_7f4a_7c15, |hash, &value| {
+        let mut z = (hash ^ value).wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    })
+}
+
+/// Makes [random()] and [random_range()] take their numbers (in [0, 1)) from ``source`` on the
+/// current thread, or from the thread's generator again with ``None``. This lets an integrator
+/// control every random decision made while building a path (Refer to [crate::mlt]).
+pub fn set_random_source(source: Option<fn() -> f32>) {
+    SOURCE.set(source);
 }
@@ -143,6 +143,20 @@ impl ops::DivAssign<f32> for Vec3 {
     }
 }
 
+impl ops::Index<usize> for Vec3 {
+    type Output = f32;
+
+    #[inline]
+    fn index(&self, i: usize) -> &f32 {
+        match i {
+            0 => &self.x,
+            1 => &self.y,
+            2 => &self.z,
+            _ => panic!("Index {} out of range for Vec3", i),
+        }
+    }
+}
+
 impl fmt::Display for Vec3 {
     #[inline]
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -194,6 +208,16 @@ impl Vec3 {
         }
     }
 
+    /// Applies ``f`` to every coordinate of the vector
+    #[inline]
+    pub fn map(self, f: impl Fn(f32) -> f32) -> Vec3 {
+        Self {
+            x: f(self.x),
+            y: f(self.y),
+            z: f(self.z),
+        }
+    }
+
     /// Returns the length of the vector, squared.
     #[inline]
     pub fn length_sq(self) -> f32 {